@@ -1,9 +1,41 @@
 use actix_web_static_files::NpmBuild;
-use std::env;
+use std::{
+    env,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 const SERVE_DIR: &str = "web/dist/media-server-one";
+/// What the web frontend is built from.
+const WEB_SOURCES: &[&str] = &[
+    "web/src",
+    "web/angular.json",
+    "web/package.json",
+    "web/package-lock.json",
+    "web/tsconfig.json",
+    "web/tsconfig.app.json",
+];
 
 fn main() {
+    // Build metadata shown by the status endpoint. Builds outside of a git
+    // checkout have no commit.
+    let git_commit = git(&["rev-parse", "HEAD"]).unwrap_or_default();
+    let build_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    println!("cargo:rustc-env=BUILD_GIT_COMMIT={}", git_commit);
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_time);
+
+    // once anything is listed, cargo only reruns this for the listed files,
+    // so the web sources have to be listed too
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=DONT_RUN_NPM");
+    for path in git_ref_files() {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+
     let run_npm = match env::var("DONT_RUN_NPM") {
         Ok(var) => {
             if var == "1" {
@@ -16,6 +48,10 @@ fn main() {
     };
 
     if run_npm {
+        for path in WEB_SOURCES {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+
         NpmBuild::new("web")
             .install()
             .unwrap()
@@ -27,3 +63,26 @@ fn main() {
             .unwrap();
     }
 }
+
+/// Runs git, giving its trimmed output if it succeeds.
+fn git(args: &[&str]) -> Option<String> {
+    Command::new("git")
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The files that change with the checked out commit: HEAD, and the branch it
+/// points to, which may live in packed-refs instead. Missing files would make
+/// cargo rerun the build script every time, so they are left out.
+fn git_ref_files() -> Vec<String> {
+    let mut refs = vec!["HEAD".to_string(), "packed-refs".to_string()];
+    refs.extend(git(&["symbolic-ref", "-q", "HEAD"]));
+
+    refs.iter()
+        .filter_map(|name| git(&["rev-parse", "--git-path", name]))
+        .filter(|path| Path::new(path).exists())
+        .collect()
+}
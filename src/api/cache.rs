@@ -0,0 +1,9 @@
+use crate::{media::cache, util::web::json_ok};
+use actix_web::HttpResponse;
+
+/// Reports how much of the cache quota is used, in total and per type of
+/// asset. Sizes are in bytes.
+#[get("/cache")]
+pub async fn get_cache() -> HttpResponse {
+    json_ok(cache::stats())
+}
@@ -0,0 +1,24 @@
+use crate::{
+    config::Config,
+    error::Result,
+    media::chapters,
+    util::{
+        path::resolve_file,
+        web::{blocking, json_ok},
+    },
+};
+use actix_web::{web, HttpResponse};
+
+/// Lists a media file's chapters, read from its container or from a sidecar
+/// chapter file.
+#[get("/chapters/{path:.*}")]
+pub async fn get_chapters(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    let file_path = resolve_file(&config, &path)?;
+    let config = config.into_inner();
+    let chapters = blocking(move || Ok(chapters::find_chapters(&config, &file_path))).await?;
+
+    Ok(json_ok(chapters))
+}
@@ -5,9 +5,24 @@ use crate::{
         ErrorKind::{FilesIndexUnknownError, InvalidMethodError, UriSegmentError},
         Result, ResultExt,
     },
+    media::{
+        chapters, loudness, loudness::Loudness, music, optimize, subtitles,
+        subtitles::SubtitleTrack,
+    },
+    metadata::{
+        exif,
+        exif::ExifData,
+        folder,
+        folder::{FolderMetadata, SortOrder},
+        nfo,
+        nfo::Nfo,
+        playlist,
+        playlist::Playlist,
+    },
     util::{
-        path::{file_extension, parse_path},
-        web::{json_ok, json_ok_status},
+        path::{file_extension, is_image, parse_path, PATH_SET},
+        signing,
+        web::{blocking, json_ok, json_ok_status},
     },
 };
 use actix_service::ServiceFactory;
@@ -18,26 +33,28 @@ use actix_web::{
     web, HttpRequest, HttpResponse, Scope,
 };
 use core::result;
-use futures::future::{ok, Ready};
+use error_chain::ChainedError;
+use futures::future::{ok, Either, LocalBoxFuture, Ready};
 use path_slash::PathExt;
-use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
 use std::{
+    cmp::Reverse,
     io,
-    path::Path,
+    net::IpAddr,
+    path::{Path, PathBuf},
     task::{Context, Poll},
+    time::SystemTime,
 };
 
-const CDN_FILES_URL: &'static str = "/cdn/files";
-const API_PREFIX_LEN: usize = "/api/v1/index/files".len();
-
-lazy_static! {
-    static ref PATH_SET: AsciiSet = NON_ALPHANUMERIC
-        .remove(b'/')
-        .remove(b'-')
-        .remove(b'_')
-        .remove(b'.')
-        .remove(b'+');
-}
+const CDN_CHAPTERS_URL: &str = "/cdn/chapters";
+const CDN_COVERS_URL: &str = "/cdn/covers";
+const CDN_HLS_URL: &str = "/cdn/hls";
+const CDN_IMAGES_URL: &str = "/cdn/images";
+const CDN_OPTIMIZED_URL: &str = "/cdn/optimized";
+const CDN_SUBTITLES_URL: &str = "/cdn/subtitles";
+const CDN_THUMBNAILS_URL: &str = "/cdn/thumbnails";
+const API_PREFIX: &str = "/api/v1/index/files";
+const API_PREFIX_LEN: usize = API_PREFIX.len();
 
 pub fn files(config: &Config) -> Scope {
     web::scope("files").service(FilesIndex {
@@ -85,7 +102,10 @@ impl Service for FilesIndexService {
     type Request = ServiceRequest;
     type Response = ServiceResponse;
     type Error = WebError;
-    type Future = Ready<result::Result<ServiceResponse, WebError>>;
+    type Future = Either<
+        Ready<result::Result<ServiceResponse, WebError>>,
+        LocalBoxFuture<'static, result::Result<ServiceResponse, WebError>>,
+    >;
 
     fn poll_ready(&mut self, _ctx: &mut Context<'_>) -> Poll<result::Result<(), Self::Error>> {
         Poll::Ready(Ok(()))
@@ -95,7 +115,7 @@ impl Service for FilesIndexService {
         let is_method_valid = matches!(*req.method(), Method::HEAD | Method::GET);
 
         if !is_method_valid {
-            return ok(req.error_response(Error::from_kind(InvalidMethodError)));
+            return Either::Left(ok(req.error_response(Error::from_kind(InvalidMethodError))));
         }
 
         let full_path_str = req.path().to_string();
@@ -103,7 +123,7 @@ impl Service for FilesIndexService {
         let relative_path_str = match percent_decode_str(&full_path_str).decode_utf8() {
             Ok(str) => str[API_PREFIX_LEN..].to_string(),
             Err(_err) => {
-                return ok(req.error_response(Error::from_kind(UriSegmentError)));
+                return Either::Left(ok(req.error_response(Error::from_kind(UriSegmentError))));
             }
         };
 
@@ -114,13 +134,13 @@ impl Service for FilesIndexService {
 
         let relative_path = match parse_path(&relative_path_str, false) {
             Ok(p) => p,
-            Err(e) => return ok(error_response(e, &http)),
+            Err(e) => return Either::Left(ok(error_response(e, &http))),
         };
 
         let file_path = match self.config.base_dir.join(&relative_path).canonicalize() {
             Ok(p) => p,
             Err(e) => {
-                return ok(response_from_io_error(
+                return Either::Left(ok(response_from_io_error(
                     e,
                     &http,
                     relative_path_str,
@@ -128,7 +148,7 @@ impl Service for FilesIndexService {
                     relative_path
                         .file_name()
                         .map_or("".to_string(), |s| s.to_string_lossy().to_string()),
-                ));
+                )));
             }
         };
 
@@ -138,13 +158,13 @@ impl Service for FilesIndexService {
                 if !full_path_str.ends_with('/') {
                     let redirect_to = format!("{}/", full_path_str);
 
-                    return ok(ServiceResponse::new(
+                    return Either::Left(ok(ServiceResponse::new(
                         http,
                         HttpResponse::Found()
                             .header(header::LOCATION, redirect_to)
                             .body("")
                             .into_body(),
-                    ));
+                    )));
                 }
 
                 match render_directory(
@@ -158,33 +178,61 @@ impl Service for FilesIndexService {
                         .file_name()
                         .map_or("".to_string(), |s| s.to_string_lossy().to_string()),
                 ) {
-                    Ok(res) => ok(res),
-                    Err(e) => return ok(error_response(e, &http)),
+                    Ok(res) => Either::Left(ok(res)),
+                    Err(e) => Either::Left(ok(error_response(e, &http))),
                 }
-            } else {
-                let json = JsonEntryInfo {
-                    detail: JsonEntryDetail::File {
-                        mime_type: actix_files::file_extension_to_mime(
-                            file_extension(&relative_path_str).unwrap_or(""),
-                        )
-                        .to_string(),
-                        url: format!("{}{}", CDN_FILES_URL, url_encoded_relative_path),
-                    },
-                    name: relative_path
+            } else if playlist::is_playlist(&relative_path_str) {
+                match render_playlist(
+                    &self.config,
+                    &http,
+                    &file_path,
+                    &relative_path,
+                    relative_path_str,
+                    url_encoded_relative_path,
+                    relative_path
                         .file_name()
                         .map_or("".to_string(), |s| s.to_string_lossy().to_string()),
-                    path: url_encoded_relative_path,
-                    path_pretty: relative_path_str,
-                };
+                ) {
+                    Ok(res) => Either::Left(ok(res)),
+                    Err(e) => Either::Left(ok(error_response(e, &http))),
+                }
+            } else {
+                // probing and reading sidecar files can take a while, so it is
+                // kept off the event loop
+                let config = self.config.clone();
+                let client = http.peer_addr().map(|addr| addr.ip());
+                // cached assets are keyed by the path the CDN resolves, which
+                // keeps symlinks as they are
+                let file_path = config.base_dir.join(&relative_path);
+
+                Either::Right(Box::pin(async move {
+                    let json = blocking(move || {
+                        Ok(render_file(
+                            &config,
+                            client,
+                            &file_path,
+                            &relative_path,
+                            relative_path_str,
+                            url_encoded_relative_path,
+                        ))
+                    })
+                    .await;
 
-                ok(ServiceResponse::new(http, json_ok(json)))
+                    Ok(match json {
+                        Ok(json) => ServiceResponse::new(http, json_ok(json)),
+                        Err(e) => error_response(e, &http),
+                    })
+                }))
             }
         } else {
-            ok(ServiceResponse::new(
+            Either::Left(ok(ServiceResponse::new(
                 http,
                 json_ok_status(
                     StatusCode::NOT_FOUND,
                     JsonEntryInfo {
+                        title: None,
+                        description: None,
+                        cover: None,
                         detail: JsonEntryDetail::Error {
                             error: JsonIndexError::NotFound,
                         },
@@ -195,7 +243,7 @@ impl Service for FilesIndexService {
                         path_pretty: relative_path_str,
                     },
                 ),
-            ))
+            )))
         }
     }
 }
@@ -211,6 +259,8 @@ fn render_directory(
 ) -> Result<ServiceResponse> {
     let url_base = Path::new(http.path());
     let path_base = Path::new("/").join(relative_path);
+    let client = http.peer_addr().map(|addr| addr.ip());
+    let folder = load_folder(folder::find_for_directory(file_path)).unwrap_or_default();
     let mut children_vec = vec![];
 
     let read_dir = match file_path.read_dir() {
@@ -236,7 +286,7 @@ fn render_directory(
         };
         let stripped_path_str = stripped_path.to_string_lossy();
 
-        if config.is_legal_path(&stripped_path_str) {
+        if config.is_legal_path(&stripped_path_str) && !folder.is_hidden(&stripped_path_str) {
             let url_path = url_base.join(stripped_path);
             let path_path = path_base.join(stripped_path);
 
@@ -245,20 +295,32 @@ fn render_directory(
                 let url = utf8_percent_encode(&url_path.to_slash_lossy(), &PATH_SET).to_string();
                 let path = utf8_percent_encode(&path_path.to_slash_lossy(), &PATH_SET).to_string();
 
+                let modified = metadata.modified().ok();
+
                 if metadata.is_dir() {
-                    children_vec.push(JsonDirectoryChild {
-                        name,
-                        ty: JsonEntryType::Directory,
-                        url: format!("{}/", url),
-                        path: format!("{}/", path),
-                    })
+                    children_vec.push((
+                        JsonDirectoryChild {
+                            name,
+                            title: directory_title(&entry_path),
+                            thumbnail: None,
+                            ty: JsonEntryType::Directory,
+                            url: format!("{}/", url),
+                            path: format!("{}/", path),
+                        },
+                        modified,
+                    ))
                 } else {
-                    children_vec.push(JsonDirectoryChild {
-                        name,
-                        ty: JsonEntryType::File,
-                        url,
-                        path,
-                    })
+                    children_vec.push((
+                        JsonDirectoryChild {
+                            thumbnail: thumbnail_url(config, client, &name, &path),
+                            title: nfo_title(nfo::find_for_file(&entry_path)),
+                            ty: file_entry_type(&name),
+                            name,
+                            url,
+                            path,
+                        },
+                        modified,
+                    ))
                 }
             } else {
                 continue;
@@ -268,9 +330,24 @@ fn render_directory(
         }
     }
 
+    sort_children(&mut children_vec, folder.sort_order);
+
+    let nfo = load_nfo(config, nfo::find_for_directory(file_path), relative_path);
+
     let json = JsonEntryInfo {
+        title: folder
+            .title
+            .or_else(|| nfo.as_ref().and_then(|nfo| nfo.title.clone())),
+        description: folder
+            .description
+            .or_else(|| nfo.as_ref().and_then(|nfo| nfo.plot.clone())),
+        cover: folder
+            .cover
+            .and_then(|cover| local_file_url(config, relative_path, &cover))
+            .or_else(|| nfo.as_ref().and_then(nfo_cover)),
         detail: JsonEntryDetail::Directory {
-            children: children_vec,
+            children: children_vec.into_iter().map(|(child, _)| child).collect(),
+            nfo,
         },
         name: file_name,
         path: url_encoded_relative_path,
@@ -280,6 +357,474 @@ fn render_directory(
     Ok(ServiceResponse::new(http.clone(), json_ok(json)))
 }
 
+/// Describes a file, along with everything that can be served for it.
+fn render_file(
+    config: &Config,
+    client: Option<IpAddr>,
+    file_path: &Path,
+    relative_path: &Path,
+    relative_path_str: String,
+    url_encoded_relative_path: String,
+) -> JsonEntryInfo {
+    let nfo = load_nfo(
+        config,
+        nfo::find_for_file(file_path),
+        relative_path.parent().unwrap_or_else(|| Path::new("")),
+    );
+
+    let url = signing::files_url(config, &relative_path.to_slash_lossy(), client);
+
+    JsonEntryInfo {
+        title: nfo.as_ref().and_then(|nfo| nfo.title.clone()),
+        description: nfo.as_ref().and_then(|nfo| nfo.plot.clone()),
+        cover: nfo
+            .as_ref()
+            .and_then(nfo_cover)
+            .or_else(|| embedded_cover_url(config, client, file_path, &url_encoded_relative_path)),
+        detail: JsonEntryDetail::File {
+            mime_type: actix_files::file_extension_to_mime(
+                file_extension(&relative_path_str).unwrap_or(""),
+            )
+            .to_string(),
+            playback_url: playback_url(config, client, file_path, &url_encoded_relative_path, &url),
+            download_url: download_url(&url),
+            url,
+            hls_url: hls_url(
+                config,
+                client,
+                &relative_path_str,
+                &url_encoded_relative_path,
+            ),
+            subtitles: subtitle_tracks(
+                config,
+                client,
+                file_path,
+                &relative_path_str,
+                &url_encoded_relative_path,
+            ),
+            chapters_url: chapters_url(
+                config,
+                client,
+                file_path,
+                &relative_path_str,
+                &url_encoded_relative_path,
+            ),
+            loudness: file_loudness(config, file_path),
+            image_variants: image_variants(
+                config,
+                client,
+                &relative_path_str,
+                &url_encoded_relative_path,
+            ),
+            exif: file_exif(file_path, &relative_path_str),
+            nfo,
+        },
+        name: relative_path
+            .file_name()
+            .map_or("".to_string(), |s| s.to_string_lossy().to_string()),
+        path: url_encoded_relative_path,
+        path_pretty: relative_path_str,
+    }
+}
+
+/// Lists the media a playlist file refers to, in the playlist's order. Entries
+/// that are remote, outside of `base_dir`, illegal or missing are left out.
+fn render_playlist(
+    config: &Config,
+    http: &HttpRequest,
+    file_path: &Path,
+    relative_path: &Path,
+    relative_path_str: String,
+    url_encoded_relative_path: String,
+    file_name: String,
+) -> Result<ServiceResponse> {
+    let base_dir = &config.base_dir;
+    let playlist_dir = file_path.parent().unwrap_or(base_dir);
+    let client = http.peer_addr().map(|addr| addr.ip());
+    let Playlist { entries } = match playlist::load(file_path) {
+        Ok(playlist) => playlist,
+        Err(e) => {
+            warn!("{}", e.display_chain());
+            Playlist::default()
+        }
+    };
+
+    let children = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let relative_path =
+                resolve_playlist_entry(config, base_dir, playlist_dir, &entry.location)?;
+            let name = relative_path.file_name()?.to_string_lossy().to_string();
+            let path = format!(
+                "/{}",
+                utf8_percent_encode(&relative_path.to_slash_lossy(), &PATH_SET)
+            );
+
+            Some(JsonDirectoryChild {
+                thumbnail: thumbnail_url(config, client, &name, &path),
+                title: entry
+                    .title
+                    .or_else(|| nfo_title(nfo::find_for_file(&base_dir.join(&relative_path)))),
+                ty: file_entry_type(&name),
+                name,
+                url: format!("{}{}", API_PREFIX, path),
+                path,
+            })
+        })
+        .collect();
+
+    let json = JsonEntryInfo {
+        title: None,
+        description: None,
+        cover: None,
+        detail: JsonEntryDetail::Playlist {
+            url: signing::files_url(config, &relative_path.to_slash_lossy(), client),
+            children,
+        },
+        name: file_name,
+        path: url_encoded_relative_path,
+        path_pretty: relative_path_str,
+    };
+
+    Ok(ServiceResponse::new(http.clone(), json_ok(json)))
+}
+
+/// Resolves a playlist entry into a path relative to `base_dir`, provided it
+/// points to a legal file inside of it. Entries are relative to the
+/// playlist's directory unless they are absolute or `file://` urls.
+fn resolve_playlist_entry(
+    config: &Config,
+    base_dir: &Path,
+    playlist_dir: &Path,
+    location: &str,
+) -> Option<PathBuf> {
+    let location = match location.strip_prefix("file://") {
+        Some(path) => percent_decode_str(path).decode_utf8().ok()?.into_owned(),
+        None if location.contains("://") => return None,
+        None => location.to_string(),
+    };
+    // canonicalizing resolves `..` and symlinks, so the prefix check below
+    // can not be escaped
+    let entry_path = playlist_dir.join(location).canonicalize().ok()?;
+    let relative_path = entry_path.strip_prefix(base_dir).ok()?;
+
+    if parse_path(&relative_path.to_slash_lossy(), false).is_ok()
+        && config.is_legal_path(&relative_path.to_string_lossy())
+        && entry_path.is_file()
+    {
+        Some(relative_path.to_path_buf())
+    } else {
+        None
+    }
+}
+
+/// Loads a `.nfo` file if there is one, rewriting local artwork references
+/// into CDN urls. Broken `.nfo` files are logged and otherwise ignored.
+fn load_nfo(config: &Config, nfo_path: Option<PathBuf>, relative_dir: &Path) -> Option<Nfo> {
+    let mut nfo = match nfo::load(&nfo_path?) {
+        Ok(nfo) => nfo,
+        Err(e) => {
+            warn!("{}", e.display_chain());
+            return None;
+        }
+    };
+
+    nfo.artwork = nfo
+        .artwork
+        .into_iter()
+        .filter_map(|mut artwork| {
+            if !artwork.is_remote() {
+                artwork.url = local_file_url(config, relative_dir, &artwork.url)?;
+            }
+            Some(artwork)
+        })
+        .collect();
+
+    Some(nfo)
+}
+
+/// Gets the title from a child's `.nfo` file for use in directory listings.
+fn nfo_title(nfo_path: Option<PathBuf>) -> Option<String> {
+    nfo::load(&nfo_path?).ok().and_then(|nfo| nfo.title)
+}
+
+/// Gets the thumbnail url for a video or image file. Thumbnails are only
+/// available with the ffmpeg feature.
+fn thumbnail_url(
+    config: &Config,
+    client: Option<IpAddr>,
+    name: &str,
+    path: &str,
+) -> Option<String> {
+    if !cfg!(feature = "ffmpeg") {
+        None
+    } else if is_video(name) {
+        Some(cdn_url(config, client, CDN_THUMBNAILS_URL, path, ""))
+    } else if is_image(name) {
+        Some(cdn_url(
+            config,
+            client,
+            CDN_IMAGES_URL,
+            path,
+            "?variant=thumbnail",
+        ))
+    } else {
+        None
+    }
+}
+
+/// Links to the resized variants of an image file. Like thumbnails, these are
+/// only available with the ffmpeg feature.
+fn image_variants(
+    config: &Config,
+    client: Option<IpAddr>,
+    name: &str,
+    path: &str,
+) -> Option<JsonImageVariants> {
+    if cfg!(feature = "ffmpeg") && is_image(name) {
+        Some(JsonImageVariants {
+            thumbnail: cdn_url(config, client, CDN_IMAGES_URL, path, "?variant=thumbnail"),
+            screen: cdn_url(config, client, CDN_IMAGES_URL, path, "?variant=screen"),
+        })
+    } else {
+        None
+    }
+}
+
+/// Reads an image file's EXIF data. Broken EXIF data is logged and otherwise
+/// ignored.
+fn file_exif(file_path: &Path, name: &str) -> Option<ExifData> {
+    if !is_image(name) {
+        return None;
+    }
+
+    match exif::load(file_path) {
+        Ok(exif) => exif,
+        Err(e) => {
+            warn!("{}", e.display_chain());
+            None
+        }
+    }
+}
+
+/// Links to the optimized version of a file if it has one, as it plays without
+/// live transcoding, and to the file itself otherwise.
+fn playback_url(
+    config: &Config,
+    client: Option<IpAddr>,
+    file_path: &Path,
+    path: &str,
+    url: &str,
+) -> String {
+    if optimize::optimized(config, file_path).is_some() {
+        cdn_url(config, client, CDN_OPTIMIZED_URL, path, "")
+    } else {
+        url.to_string()
+    }
+}
+
+/// Links to a file so that browsers save it rather than show it. Signed urls
+/// already have a query.
+fn download_url(url: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+
+    format!("{}{}download=1", url, separator)
+}
+
+/// Gets the HLS master playlist url for a video file. Like thumbnails, HLS is
+/// only available with the ffmpeg feature.
+fn hls_url(config: &Config, client: Option<IpAddr>, name: &str, path: &str) -> Option<String> {
+    if cfg!(feature = "ffmpeg") && is_video(name) {
+        Some(cdn_url(config, client, CDN_HLS_URL, path, "/master.m3u8"))
+    } else {
+        None
+    }
+}
+
+/// Lists a video file's subtitle tracks with the urls of their WebVTT
+/// conversions.
+fn subtitle_tracks(
+    config: &Config,
+    client: Option<IpAddr>,
+    file_path: &Path,
+    name: &str,
+    path: &str,
+) -> Vec<JsonSubtitleTrack> {
+    if !is_video(name) {
+        return vec![];
+    }
+
+    subtitles::find_tracks(config, file_path)
+        .into_iter()
+        .map(|track| JsonSubtitleTrack {
+            url: cdn_url(
+                config,
+                client,
+                CDN_SUBTITLES_URL,
+                path,
+                &format!(
+                    "?track={}",
+                    utf8_percent_encode(&track.id, NON_ALPHANUMERIC)
+                ),
+            ),
+            track,
+        })
+        .collect()
+}
+
+/// Links to the WebVTT chapters track of a video or audio file that has
+/// chapters.
+fn chapters_url(
+    config: &Config,
+    client: Option<IpAddr>,
+    file_path: &Path,
+    name: &str,
+    path: &str,
+) -> Option<String> {
+    let mime = actix_files::file_extension_to_mime(file_extension(name).unwrap_or(""));
+    if mime.type_() != "video" && mime.type_() != "audio" {
+        return None;
+    }
+
+    if chapters::find_chapters(config, file_path).is_empty() {
+        None
+    } else {
+        Some(cdn_url(config, client, CDN_CHAPTERS_URL, path, ""))
+    }
+}
+
+/// Gets a file's loudness with the gain suggested for it. Files that have not
+/// been analyzed yet are queued for analysis and have none for now.
+fn file_loudness(config: &Config, file_path: &Path) -> Option<JsonLoudness> {
+    if !loudness::has_audio_extension(file_path) {
+        return None;
+    }
+
+    match loudness::cached(config, file_path) {
+        Some(loudness) => loudness.map(|loudness| JsonLoudness {
+            suggested_gain: loudness.suggested_gain(&config.loudness),
+            loudness,
+        }),
+        None => {
+            loudness::request(config, file_path);
+            None
+        }
+    }
+}
+
+fn file_entry_type(name: &str) -> JsonEntryType {
+    if playlist::is_playlist(name) {
+        JsonEntryType::Playlist
+    } else {
+        JsonEntryType::File
+    }
+}
+
+fn is_video(name: &str) -> bool {
+    actix_files::file_extension_to_mime(file_extension(name).unwrap_or("")).type_() == "video"
+}
+
+/// Links to the cover art embedded in an audio file, if it has any.
+fn embedded_cover_url(
+    config: &Config,
+    client: Option<IpAddr>,
+    file_path: &Path,
+    path: &str,
+) -> Option<String> {
+    if music::has_cover(config, file_path) {
+        Some(cdn_url(config, client, CDN_COVERS_URL, path, ""))
+    } else {
+        None
+    }
+}
+
+/// Picks the poster out of a `.nfo` file's artwork, falling back to whatever
+/// artwork comes first.
+fn nfo_cover(nfo: &Nfo) -> Option<String> {
+    nfo.artwork
+        .iter()
+        .find(|artwork| artwork.aspect.as_deref() == Some("poster"))
+        .or_else(|| nfo.artwork.first())
+        .map(|artwork| artwork.url.clone())
+}
+
+/// Loads a `folder.toml` file if there is one. Broken folder metadata files
+/// are logged and otherwise ignored.
+fn load_folder(folder_path: Option<PathBuf>) -> Option<FolderMetadata> {
+    match folder::load(&folder_path?) {
+        Ok(folder) => Some(folder),
+        Err(e) => {
+            warn!("{}", e.display_chain());
+            None
+        }
+    }
+}
+
+/// Gets a child directory's display title for use in directory listings,
+/// preferring its `folder.toml` over its `.nfo` file.
+fn directory_title(dir_path: &Path) -> Option<String> {
+    folder::find_for_directory(dir_path)
+        .and_then(|folder_path| folder::load(&folder_path).ok())
+        .and_then(|folder| folder.title)
+        .or_else(|| nfo_title(nfo::find_for_directory(dir_path)))
+}
+
+fn sort_children(children: &mut [(JsonDirectoryChild, Option<SystemTime>)], order: SortOrder) {
+    match order {
+        SortOrder::Name => children.sort_by_cached_key(|(child, _)| child.display_name()),
+        SortOrder::NameDescending => {
+            children.sort_by_cached_key(|(child, _)| Reverse(child.display_name()))
+        }
+        SortOrder::Newest => children.sort_by_key(|(_, modified)| Reverse(*modified)),
+        SortOrder::Oldest => children.sort_by_key(|(_, modified)| *modified),
+    }
+}
+
+/// Links to something the CDN serves for a file, given the file's url encoded
+/// path with a leading slash, signed for the file if signing is enabled.
+fn cdn_url(
+    config: &Config,
+    client: Option<IpAddr>,
+    prefix: &str,
+    path: &str,
+    suffix: &str,
+) -> String {
+    // signed for the path as the CDN will see it
+    let relative_path = parse_path(&percent_decode_str(path).decode_utf8_lossy(), false)
+        .map(|relative_path| relative_path.to_slash_lossy())
+        .unwrap_or_default();
+
+    signing::sign_url(
+        config,
+        format!("{}{}{}", prefix, path, suffix),
+        &relative_path,
+        client,
+    )
+}
+
+/// Resolves a path relative to a directory under `base_dir` into a CDN url,
+/// provided it points to a legal file. These urls are embedded in shared
+/// metadata, so they are never bound to a client.
+fn local_file_url(config: &Config, relative_dir: &Path, path: &str) -> Option<String> {
+    let relative_path = parse_path(
+        &format!("{}/{}", relative_dir.to_slash_lossy(), path),
+        false,
+    )
+    .ok()?;
+
+    if config.is_legal_path(&relative_path.to_string_lossy())
+        && config.base_dir.join(&relative_path).is_file()
+    {
+        Some(signing::files_url(
+            config,
+            &relative_path.to_slash_lossy(),
+            None,
+        ))
+    } else {
+        None
+    }
+}
+
 fn unknown_err(req: &HttpRequest, msg: &str) -> ErrorKind {
     FilesIndexUnknownError(format!("{}: {}", msg, req.path()).into())
 }
@@ -304,6 +849,9 @@ fn response_from_io_error(
                     _ => unreachable!(),
                 },
                 JsonEntryInfo {
+                    title: None,
+                    description: None,
+                    cover: None,
                     detail: JsonEntryDetail::Error {
                         error: match e.kind() {
                             io::ErrorKind::NotFound => JsonIndexError::NotFound,
@@ -328,32 +876,91 @@ fn error_response(e: Error, http: &HttpRequest) -> ServiceResponse {
 
 #[derive(Debug, Serialize)]
 struct JsonEntryInfo {
+    title: Option<String>,
+    description: Option<String>,
+    cover: Option<String>,
     detail: JsonEntryDetail,
     name: String,
     path: String,
     path_pretty: String,
 }
 
+// only ever built to be serialized right away
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Serialize)]
 enum JsonEntryDetail {
-    Directory { children: Vec<JsonDirectoryChild> },
-    Error { error: JsonIndexError },
-    File { mime_type: String, url: String },
+    Directory {
+        children: Vec<JsonDirectoryChild>,
+        nfo: Option<Nfo>,
+    },
+    Error {
+        error: JsonIndexError,
+    },
+    Playlist {
+        /// The playlist file itself.
+        url: String,
+        children: Vec<JsonDirectoryChild>,
+    },
+    File {
+        mime_type: String,
+        url: String,
+        playback_url: String,
+        download_url: String,
+        hls_url: Option<String>,
+        subtitles: Vec<JsonSubtitleTrack>,
+        chapters_url: Option<String>,
+        loudness: Option<JsonLoudness>,
+        image_variants: Option<JsonImageVariants>,
+        exif: Option<ExifData>,
+        nfo: Option<Nfo>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct JsonLoudness {
+    #[serde(flatten)]
+    loudness: Loudness,
+    /// Gain in dB that brings the file to the configured target loudness.
+    /// Silent files have none.
+    suggested_gain: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonImageVariants {
+    thumbnail: String,
+    /// Sized for viewing the image on its own.
+    screen: String,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonSubtitleTrack {
+    #[serde(flatten)]
+    track: SubtitleTrack,
+    url: String,
 }
 
 #[derive(Debug, Serialize)]
 struct JsonDirectoryChild {
     name: String,
+    title: Option<String>,
+    thumbnail: Option<String>,
     #[serde(rename = "type")]
     ty: JsonEntryType,
     url: String,
     path: String,
 }
 
+impl JsonDirectoryChild {
+    fn display_name(&self) -> String {
+        self.title.as_ref().unwrap_or(&self.name).to_lowercase()
+    }
+}
+
 #[derive(Debug, Serialize)]
 enum JsonEntryType {
     Directory,
     File,
+    Playlist,
 }
 
 #[derive(Debug, Serialize)]
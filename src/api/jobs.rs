@@ -0,0 +1,74 @@
+use crate::{
+    config::Config,
+    error::{ErrorKind::NotFoundError, Result},
+    media::jobs::{self, JobId, JobKind, JobPriority},
+    util::web::{authorize_admin, json_ok},
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+/// Lists the queued and running ffmpeg jobs.
+#[get("/jobs")]
+pub async fn get_jobs(config: web::Data<Config>, req: HttpRequest) -> Result<HttpResponse> {
+    authorize_admin(&config, &req)?;
+
+    Ok(json_ok(
+        jobs::list()
+            .iter()
+            .map(|job| JsonJob {
+                id: job.id,
+                kind: job.kind,
+                description: job.description.clone(),
+                priority: job.priority,
+                state: if job.is_cancelled() {
+                    JsonJobState::Cancelling
+                } else if job.is_running() {
+                    JsonJobState::Running
+                } else if job.is_paused() {
+                    JsonJobState::Paused
+                } else {
+                    JsonJobState::Queued
+                },
+                progress: job.progress(),
+                age: job.age(),
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// Cancels a job. Queued jobs are dropped right away, running ones stop
+/// shortly after.
+#[delete("/jobs/{id}")]
+pub async fn delete_job(
+    config: web::Data<Config>,
+    req: HttpRequest,
+    id: web::Path<JobId>,
+) -> Result<HttpResponse> {
+    authorize_admin(&config, &req)?;
+    let job = jobs::find(id.into_inner()).ok_or(NotFoundError)?;
+    info!("Cancelling job {} ({:?})", job.id, job.description);
+    job.cancel();
+
+    Ok(json_ok(()))
+}
+
+#[derive(Debug, Serialize)]
+struct JsonJob {
+    id: JobId,
+    kind: JobKind,
+    description: String,
+    priority: JobPriority,
+    state: JsonJobState,
+    /// From 0 to 1.
+    progress: f64,
+    /// Seconds since the job was queued.
+    age: f64,
+}
+
+#[derive(Debug, Serialize)]
+enum JsonJobState {
+    Queued,
+    Running,
+    /// Waiting on a client rather than for a slot.
+    Paused,
+    Cancelling,
+}
@@ -1,4 +1,12 @@
+mod cache;
+mod chapters;
 mod index;
+mod jobs;
+mod music;
+mod optimize;
+mod playback;
+mod probe;
+mod sprites;
 mod status;
 
 use crate::config::Config;
@@ -7,5 +15,17 @@ use actix_web::{web, Scope};
 pub fn service(config: &Config) -> Scope {
     web::scope("api/v1")
         .service(index::service(config))
+        .service(cache::get_cache)
+        .service(chapters::get_chapters)
+        .service(jobs::get_jobs)
+        .service(jobs::delete_job)
+        .service(music::get_artists)
+        .service(music::get_albums)
+        .service(music::get_album)
+        .service(optimize::get_optimize)
+        .service(optimize::post_optimize)
+        .service(playback::get_playback)
+        .service(probe::get_probe)
+        .service(sprites::get_sprites)
         .service(status::get_status)
 }
@@ -0,0 +1,158 @@
+use crate::{
+    config::Config,
+    error::{ErrorKind::NotFoundError, Result},
+    media::music::{self, Album, Track},
+    util::{
+        path::PATH_SET,
+        signing,
+        web::{blocking, json_ok},
+    },
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+use percent_encoding::utf8_percent_encode;
+use std::net::IpAddr;
+
+const CDN_COVERS_URL: &str = "/cdn/covers";
+
+#[derive(Debug, Deserialize)]
+pub struct AlbumsQuery {
+    /// Only lists this artist's albums, ignoring case.
+    artist: Option<String>,
+}
+
+/// Lists the album artists in the music library.
+#[get("/music/artists")]
+pub async fn get_artists(config: web::Data<Config>) -> Result<HttpResponse> {
+    let config = config.into_inner();
+    let library = blocking(move || music::library(&config)).await?;
+
+    Ok(json_ok(library.artists()))
+}
+
+/// Lists the albums in the music library, optionally of a single artist.
+#[get("/music/albums")]
+pub async fn get_albums(
+    config: web::Data<Config>,
+    query: web::Query<AlbumsQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let client = req.peer_addr().map(|addr| addr.ip());
+    let config = config.into_inner();
+    let library = {
+        let config = config.clone();
+        blocking(move || music::library(&config)).await?
+    };
+
+    let albums: Vec<JsonAlbumSummary> = library
+        .albums
+        .iter()
+        .filter(|album| {
+            query
+                .artist
+                .as_ref()
+                .is_none_or(|artist| album.artist.eq_ignore_ascii_case(artist))
+        })
+        .map(|album| JsonAlbumSummary {
+            id: album.id.clone(),
+            title: album.title.clone(),
+            artist: album.artist.clone(),
+            year: album.year,
+            genre: album.genre.clone(),
+            tracks: album.tracks.len(),
+            duration: album.duration(),
+            cover: cover_url(&config, album, client),
+        })
+        .collect();
+
+    Ok(json_ok(albums))
+}
+
+/// Gets an album with its tracks, ordered by disc and track number.
+#[get("/music/albums/{id}")]
+pub async fn get_album(
+    config: web::Data<Config>,
+    id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let client = req.peer_addr().map(|addr| addr.ip());
+    let config = config.into_inner();
+    let library = {
+        let config = config.clone();
+        blocking(move || music::library(&config)).await?
+    };
+    let album = library.album(&id).ok_or(NotFoundError)?;
+
+    Ok(json_ok(JsonAlbum {
+        id: album.id.clone(),
+        title: album.title.clone(),
+        artist: album.artist.clone(),
+        year: album.year,
+        genre: album.genre.clone(),
+        duration: album.duration(),
+        cover: cover_url(&config, album, client),
+        tracks: album
+            .tracks
+            .iter()
+            .map(|track| JsonTrack {
+                url: signing::files_url(&config, &track.path, client),
+                cover: if track.has_cover {
+                    Some(track_cover_url(&config, track, client))
+                } else {
+                    None
+                },
+                track: track.clone(),
+            })
+            .collect(),
+    }))
+}
+
+fn cover_url(config: &Config, album: &Album, client: Option<IpAddr>) -> Option<String> {
+    album
+        .cover_track()
+        .map(|track| track_cover_url(config, track, client))
+}
+
+fn track_cover_url(config: &Config, track: &Track, client: Option<IpAddr>) -> String {
+    signing::sign_url(
+        config,
+        format!(
+            "{}/{}",
+            CDN_COVERS_URL,
+            utf8_percent_encode(&track.path, &PATH_SET)
+        ),
+        &track.path,
+        client,
+    )
+}
+
+#[derive(Debug, Serialize)]
+struct JsonAlbumSummary {
+    id: String,
+    title: String,
+    artist: String,
+    year: Option<i32>,
+    genre: Option<String>,
+    tracks: usize,
+    duration: Option<f64>,
+    cover: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonAlbum {
+    id: String,
+    title: String,
+    artist: String,
+    year: Option<i32>,
+    genre: Option<String>,
+    duration: Option<f64>,
+    cover: Option<String>,
+    tracks: Vec<JsonTrack>,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonTrack {
+    #[serde(flatten)]
+    track: Track,
+    url: String,
+    cover: Option<String>,
+}
@@ -0,0 +1,37 @@
+use crate::{
+    config::Config,
+    error::Result,
+    media::optimize,
+    util::web::{authorize_admin, blocking, json_ok},
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+/// Reports how far optimizing the queued files has come.
+#[get("/optimize")]
+pub async fn get_optimize(config: web::Data<Config>) -> HttpResponse {
+    json_ok(optimize::status(&config))
+}
+
+/// Queues every video file in a directory, or a single file, for
+/// pre-transcoding into a version the configured profile can play directly.
+/// Only admins may, as it can keep the server busy for days.
+#[post("/optimize/{path:.*}")]
+pub async fn post_optimize(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    authorize_admin(&config, &req)?;
+    let config = config.into_inner();
+    let queued = blocking(move || optimize::queue(&config, &path)).await?;
+    info!("Queued {} files for optimizing", queued);
+
+    Ok(json_ok(JsonOptimizeQueued { queued }))
+}
+
+#[derive(Debug, Serialize)]
+struct JsonOptimizeQueued {
+    /// How many files were added to the queue. Files that were queued already
+    /// are not counted.
+    queued: usize,
+}
@@ -0,0 +1,205 @@
+#[cfg(not(feature = "ffmpeg"))]
+use crate::error::ErrorKind::FeatureUnavailableError;
+use crate::{
+    config::{Config, DeviceProfile},
+    error::Result,
+};
+#[cfg(feature = "ffmpeg")]
+use crate::{
+    media::{
+        optimize,
+        playback::{self, PlaybackMethod},
+        probe,
+    },
+    util::{
+        path::{file_extension, parse_path, resolve_file, PATH_SET},
+        signing,
+        web::{blocking, json_ok},
+    },
+};
+use actix_web::{http::header, web, HttpRequest, HttpResponse};
+#[cfg(feature = "ffmpeg")]
+use path_slash::PathExt;
+#[cfg(feature = "ffmpeg")]
+use percent_encoding::utf8_percent_encode;
+
+#[cfg(feature = "ffmpeg")]
+const CDN_REMUX_URL: &str = "/cdn/remux";
+#[cfg(feature = "ffmpeg")]
+const CDN_HLS_URL: &str = "/cdn/hls";
+#[cfg(feature = "ffmpeg")]
+const CDN_OPTIMIZED_URL: &str = "/cdn/optimized";
+
+/// A client's declared capabilities. Lists are comma separated, and anything
+/// left out comes from the profile picked by name or User-Agent.
+#[derive(Debug, Deserialize)]
+pub struct PlaybackQuery {
+    profile: Option<String>,
+    containers: Option<String>,
+    video_codecs: Option<String>,
+    audio_codecs: Option<String>,
+    max_height: Option<u32>,
+    /// Maximum overall bitrate in kbit/s.
+    max_bitrate: Option<u32>,
+}
+
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Serialize)]
+struct JsonPlaybackPlan {
+    method: PlaybackMethod,
+    url: String,
+    profile: String,
+    reasons: Vec<String>,
+}
+
+/// Decides whether a file can be played directly by the requesting client,
+/// or has to be remuxed or transcoded first, and links to the right url. An
+/// optimized version of the file is preferred over remuxing or transcoding if
+/// the client can play it directly.
+#[get("/playback/{path:.*}")]
+pub async fn get_playback(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<PlaybackQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let user_agent = req
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let profile = client_profile(&config, &query, user_agent);
+
+    #[cfg(feature = "ffmpeg")]
+    {
+        let file_path = resolve_file(&config, &path)?;
+        let relative_path = parse_path(&path, false)?.to_slash_lossy();
+        let url_path = utf8_percent_encode(&relative_path, &PATH_SET).to_string();
+        let extension = file_extension(&relative_path).unwrap_or("").to_lowercase();
+
+        let config = config.into_inner();
+        let (info, optimized_info) = {
+            let config = config.clone();
+            blocking(move || {
+                let info = probe::probe_cached(&config, &file_path)?;
+                let optimized_info = optimize::optimized(&config, &file_path)
+                    .map(|optimized_path| probe::probe_cached(&config, &optimized_path))
+                    .transpose()?;
+                Ok((info, optimized_info))
+            })
+            .await?
+        };
+        let mut plan = playback::plan(&info, &extension, &profile);
+
+        let plays_optimized = plan.method != PlaybackMethod::DirectPlay
+            && optimized_info.is_some_and(|optimized_info| {
+                playback::plan(&optimized_info, "mp4", &profile).method
+                    == PlaybackMethod::DirectPlay
+            });
+        if plays_optimized {
+            plan.method = PlaybackMethod::DirectPlay;
+            plan.reasons
+                .push("Playing the optimized version instead".to_string());
+        }
+
+        let url = match plan.method {
+            PlaybackMethod::DirectPlay if plays_optimized => {
+                format!("{}/{}", CDN_OPTIMIZED_URL, url_path)
+            }
+            PlaybackMethod::DirectPlay => {
+                format!("{}/{}", signing::CDN_FILES_URL, url_path)
+            }
+            PlaybackMethod::Remux => {
+                // the remuxer has to play the audio stream the plan judged
+                let mut params = vec![];
+                if let Some(index) = plan.audio_stream {
+                    params.push(format!("audio_stream={}", index));
+                }
+                if let Some(codec) = plan.audio_codec {
+                    params.push(format!("audio_codec={}", codec.name()));
+                    if plan.downmix {
+                        params.push("downmix=true".to_string());
+                    }
+                }
+
+                if params.is_empty() {
+                    format!("{}/{}", CDN_REMUX_URL, url_path)
+                } else {
+                    format!("{}/{}?{}", CDN_REMUX_URL, url_path, params.join("&"))
+                }
+            }
+            PlaybackMethod::Transcode => match plan.audio_stream {
+                Some(index) => format!(
+                    "{}/{}/master.m3u8?audio_stream={}",
+                    CDN_HLS_URL, url_path, index
+                ),
+                None => format!("{}/{}/master.m3u8", CDN_HLS_URL, url_path),
+            },
+        };
+        let url = signing::sign_url(
+            &config,
+            url,
+            &relative_path,
+            req.peer_addr().map(|addr| addr.ip()),
+        );
+
+        Ok(json_ok(JsonPlaybackPlan {
+            method: plan.method,
+            url,
+            profile: profile.name,
+            reasons: plan.reasons,
+        }))
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = (path, profile);
+        bail!(FeatureUnavailableError("ffmpeg"))
+    }
+}
+
+/// Picks the profile by name or User-Agent and overrides it with whatever the
+/// client declared itself.
+fn client_profile(config: &Config, query: &PlaybackQuery, user_agent: &str) -> DeviceProfile {
+    let mut profile = query
+        .profile
+        .as_ref()
+        .and_then(|name| config.profiles.iter().find(|profile| &profile.name == name))
+        .cloned()
+        .unwrap_or_else(|| config.device_profile(user_agent));
+
+    let list = |values: &str| {
+        values
+            .split(',')
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let declared = query.containers.is_some()
+        || query.video_codecs.is_some()
+        || query.audio_codecs.is_some()
+        || query.max_height.is_some()
+        || query.max_bitrate.is_some();
+
+    if let Some(containers) = &query.containers {
+        profile.containers = list(containers);
+    }
+    if let Some(video_codecs) = &query.video_codecs {
+        profile.video_codecs = list(video_codecs);
+    }
+    if let Some(audio_codecs) = &query.audio_codecs {
+        profile.audio_codecs = list(audio_codecs);
+    }
+    if query.max_height.is_some() {
+        profile.max_height = query.max_height;
+    }
+    if query.max_bitrate.is_some() {
+        profile.max_bitrate = query.max_bitrate;
+    }
+    if declared {
+        profile.name = format!("{} (declared)", profile.name);
+    }
+
+    profile
+}
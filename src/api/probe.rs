@@ -0,0 +1,32 @@
+#[cfg(not(feature = "ffmpeg"))]
+use crate::error::ErrorKind::FeatureUnavailableError;
+use crate::{config::Config, error::Result};
+#[cfg(feature = "ffmpeg")]
+use crate::{
+    media::probe,
+    util::{
+        path::resolve_file,
+        web::{blocking, json_ok},
+    },
+};
+use actix_web::{web, HttpResponse};
+
+/// Probes a media file with ffmpeg, describing its container, streams and
+/// chapters.
+#[get("/probe/{path:.*}")]
+pub async fn get_probe(config: web::Data<Config>, path: web::Path<String>) -> Result<HttpResponse> {
+    #[cfg(feature = "ffmpeg")]
+    {
+        let file_path = resolve_file(&config, &path)?;
+        let config = config.into_inner();
+        let info = blocking(move || probe::probe_cached(&config, &file_path)).await?;
+
+        Ok(json_ok(&*info))
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = (config, path);
+        bail!(FeatureUnavailableError("ffmpeg"))
+    }
+}
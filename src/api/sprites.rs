@@ -0,0 +1,52 @@
+#[cfg(not(feature = "ffmpeg"))]
+use crate::error::ErrorKind::FeatureUnavailableError;
+use crate::{config::Config, error::Result};
+#[cfg(feature = "ffmpeg")]
+use crate::{
+    media::sprites::{self, SpriteStatus, SPRITE_VTT_FILE_NAME},
+    util::{
+        path::resolve_file,
+        web::{blocking, json_ok},
+    },
+};
+use actix_web::{web, HttpResponse};
+
+#[cfg(feature = "ffmpeg")]
+const CDN_SPRITES_URL: &str = "/cdn/sprites";
+
+/// Gets the status of a video's seek-preview sprites, starting their
+/// generation if needed. Once done, this links to the WebVTT thumbnail track.
+#[get("/sprites/{path:.*}")]
+pub async fn get_sprites(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+) -> Result<HttpResponse> {
+    #[cfg(feature = "ffmpeg")]
+    {
+        let file_path = resolve_file(&config, &path)?;
+        let config = config.into_inner();
+        let status = blocking(move || sprites::sprites_status(&config, &file_path)).await?;
+
+        Ok(json_ok(match status {
+            SpriteStatus::Done { key } => JsonSpriteStatus::Done {
+                vtt_url: format!("{}/{}/{}", CDN_SPRITES_URL, key, SPRITE_VTT_FILE_NAME),
+            },
+            SpriteStatus::Generating { progress } => JsonSpriteStatus::Generating { progress },
+            SpriteStatus::Failed { error } => JsonSpriteStatus::Failed { error },
+        }))
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = (config, path);
+        bail!(FeatureUnavailableError("ffmpeg"))
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Serialize)]
+enum JsonSpriteStatus {
+    Done { vtt_url: String },
+    Generating { progress: f64 },
+    Failed { error: String },
+}
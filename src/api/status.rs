@@ -1,8 +1,14 @@
+#[cfg(feature = "ffmpeg")]
+use crate::util::ffmpeg;
 use crate::{config::Config, util::web::json_ok};
 use actix_web::{web, HttpResponse};
+use chrono::{TimeZone, Utc};
+use std::collections::BTreeMap;
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 const NAME: &'static str = env!("CARGO_PKG_NAME");
+const GIT_COMMIT: &str = env!("BUILD_GIT_COMMIT");
+const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
 
 #[get("/status")]
 pub async fn get_status(config: web::Data<Config>) -> HttpResponse {
@@ -11,13 +17,86 @@ pub async fn get_status(config: web::Data<Config>) -> HttpResponse {
         version: VERSION,
         welcome_title: config.welcome_title.clone(),
         welcome_content: config.welcome_content.clone(),
+        build: BuildInfo {
+            git_commit: Some(GIT_COMMIT).filter(|commit| !commit.is_empty()),
+            build_time: BUILD_TIMESTAMP
+                .parse()
+                .ok()
+                .and_then(|timestamp| Utc.timestamp_opt(timestamp, 0).single())
+                .map(|time| time.to_rfc3339()),
+        },
+        capabilities: capabilities(&config),
     })
 }
 
+/// Collects what this build and configuration of the server can do.
+fn capabilities(config: &Config) -> Capabilities {
+    let mut features = vec![];
+    let mut streaming = vec!["direct"];
+    if cfg!(feature = "ffmpeg") {
+        features.push("ffmpeg");
+        streaming.extend(&["hls", "remux"]);
+    }
+    let mut auth = vec![];
+    if config.signing.enabled {
+        auth.push("signed-urls");
+    }
+    if !config.jobs.admin_token.is_empty() {
+        auth.push("admin-token");
+    }
+
+    Capabilities {
+        features,
+        #[cfg(feature = "ffmpeg")]
+        ffmpeg: {
+            let (decoders, encoders) = ffmpeg::codecs();
+            Some(FfmpegCapabilities {
+                versions: ffmpeg::library_versions(),
+                decoders,
+                encoders,
+            })
+        },
+        #[cfg(not(feature = "ffmpeg"))]
+        ffmpeg: None,
+        streaming,
+        auth,
+        loudness_analysis: cfg!(feature = "ffmpeg") && config.loudness.analyze,
+        loudness_normalization: cfg!(feature = "ffmpeg") && config.loudness.normalize,
+    }
+}
+
 #[derive(Debug, Serialize)]
 struct ServerStatus {
     name: &'static str,
     version: &'static str,
     welcome_title: String,
     welcome_content: String,
+    build: BuildInfo,
+    capabilities: Capabilities,
+}
+
+#[derive(Debug, Serialize)]
+struct BuildInfo {
+    git_commit: Option<&'static str>,
+    build_time: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Capabilities {
+    /// The optional cargo features this build was compiled with.
+    features: Vec<&'static str>,
+    ffmpeg: Option<FfmpegCapabilities>,
+    /// How media can be streamed: as is, as HLS or remuxed into MP4.
+    streaming: Vec<&'static str>,
+    /// The ways requests can be authenticated.
+    auth: Vec<&'static str>,
+    loudness_analysis: bool,
+    loudness_normalization: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct FfmpegCapabilities {
+    versions: BTreeMap<&'static str, String>,
+    decoders: Vec<String>,
+    encoders: Vec<String>,
 }
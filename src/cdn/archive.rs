@@ -0,0 +1,139 @@
+use crate::{
+    config::Config,
+    error::{ErrorKind::NotFoundError, Result},
+    util::{
+        path::parse_path,
+        signing,
+        web::{attachment, blocking},
+        zip::{self, ZipEntry},
+    },
+};
+use actix_web::{
+    body::SizedStream,
+    web::{self, Bytes},
+    HttpRequest, HttpResponse,
+};
+use futures::{channel::mpsc, executor::block_on, SinkExt, StreamExt};
+use path_slash::PathExt;
+use std::{path::Path, thread};
+use walkdir::WalkDir;
+
+const ARCHIVE_EXTENSION: &str = ".zip";
+/// Names longer than this do not fit into ZIP headers.
+const MAX_NAME_LENGTH: usize = u16::MAX as usize;
+/// How many chunks of the archive can be waiting to be sent before reading
+/// files has to wait for the client.
+const ARCHIVE_BUFFER_CHUNKS: usize = 32;
+
+#[derive(Debug, Deserialize)]
+pub struct ArchiveQuery {
+    /// Includes the contents of subdirectories too.
+    #[serde(default)]
+    recursive: bool,
+}
+
+/// Streams an uncompressed ZIP archive of the files in a directory. Entries
+/// are named relative to the directory's parent, so extracting the archive
+/// recreates the directory. Archives are signed like the files in them, for
+/// the directory's path.
+#[get("/{path:.*}")]
+pub async fn get_archive(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<ArchiveQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    let dir = path.strip_suffix(ARCHIVE_EXTENSION).ok_or(NotFoundError)?;
+    signing::verify_request(&config, dir, &req)?;
+    let relative_path = parse_path(dir, false)?;
+    let dir_path = config.base_dir.join(&relative_path);
+    if !config.is_legal_path(&relative_path.to_string_lossy()) || !dir_path.is_dir() {
+        bail!(NotFoundError)
+    }
+
+    let dir_name = relative_path
+        .file_name()
+        .or_else(|| config.base_dir.file_name())
+        .map_or("archive".to_string(), |name| {
+            name.to_string_lossy().to_string()
+        });
+    let recursive = query.recursive;
+    let entries = {
+        let config = config.clone().into_inner();
+        let dir_name = dir_name.clone();
+        blocking(move || {
+            Ok(archive_entries(
+                &config,
+                &dir_path,
+                &relative_path,
+                &dir_name,
+                recursive,
+            ))
+        })
+        .await?
+    };
+    let size = zip::archive_size(&entries);
+
+    let (mut sender, receiver) = mpsc::channel::<Bytes>(ARCHIVE_BUFFER_CHUNKS);
+    thread::spawn(move || {
+        let sink = |data: &[u8]| block_on(sender.send(Bytes::copy_from_slice(data))).is_ok();
+        if let Err(e) = zip::write_archive(&entries, sink) {
+            e.log();
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .set(attachment(&format!("{}{}", dir_name, ARCHIVE_EXTENSION)))
+        .body(SizedStream::new(size, receiver.map(Ok))))
+}
+
+/// Lists the legal, non-hidden files in a directory, ordered by name.
+fn archive_entries(
+    config: &Config,
+    dir_path: &Path,
+    relative_dir: &Path,
+    dir_name: &str,
+    recursive: bool,
+) -> Vec<ZipEntry> {
+    let mut entries: Vec<ZipEntry> = WalkDir::new(dir_path)
+        .min_depth(1)
+        .max_depth(if recursive { usize::MAX } else { 1 })
+        .into_iter()
+        // hidden and illegal directories are not descended into
+        .filter_entry(|entry| {
+            entry
+                .path()
+                .strip_prefix(dir_path)
+                .is_ok_and(|path| is_archivable(config, &relative_dir.join(path)))
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            let name = format!(
+                "{}/{}",
+                dir_name,
+                entry.path().strip_prefix(dir_path).ok()?.to_slash_lossy()
+            );
+
+            Some(ZipEntry {
+                name,
+                path: entry.path().to_path_buf(),
+                size: metadata.len(),
+                modified: metadata.modified().ok()?,
+            })
+        })
+        .filter(|entry| entry.name.len() <= MAX_NAME_LENGTH)
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    entries
+}
+
+/// Whether a path relative to `base_dir` may go into an archive, which are the
+/// same paths `/cdn/files` serves.
+fn is_archivable(config: &Config, relative_path: &Path) -> bool {
+    parse_path(&relative_path.to_slash_lossy(), false).is_ok()
+        && config.is_legal_path(&relative_path.to_string_lossy())
+}
@@ -0,0 +1,102 @@
+use crate::config::Config;
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    http::{header, HeaderValue, StatusCode},
+};
+use futures::{
+    future,
+    future::{LocalBoxFuture, Ready},
+    task::{Context, Poll},
+    FutureExt,
+};
+use percent_encoding::percent_decode_str;
+use std::result;
+
+/// For generated assets whose url changes whenever their content does.
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+const CDN_PREFIX: &str = "/cdn/";
+
+/// Adds the configured `Cache-Control` header to successful CDN responses
+/// that do not set one themselves.
+pub struct CacheControl {
+    pub config: Config,
+}
+
+impl<S, B> Transform<S> for CacheControl
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = CacheControlMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<result::Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(CacheControlMiddleware {
+            service,
+            config: self.config.clone(),
+        })
+    }
+}
+
+pub struct CacheControlMiddleware<S> {
+    service: S,
+    config: Config,
+}
+
+impl<S, B> Service for CacheControlMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, result::Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<result::Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let path = percent_decode_str(req.path().strip_prefix(CDN_PREFIX).unwrap_or(""))
+            .decode_utf8_lossy()
+            .into_owned();
+        let config = self.config.clone();
+
+        self.service
+            .call(req)
+            .map(move |res| {
+                res.map(|mut res| {
+                    let status = res.status();
+                    if !(status.is_success() || status == StatusCode::NOT_MODIFIED)
+                        || res.headers().contains_key(header::CACHE_CONTROL)
+                    {
+                        return res;
+                    }
+
+                    let mime = res
+                        .headers()
+                        .get(header::CONTENT_TYPE)
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or("");
+                    if let Some(policy) = config
+                        .cache_control_policy(&path, mime)
+                        .and_then(|policy| HeaderValue::from_str(policy).ok())
+                    {
+                        res.headers_mut().insert(header::CACHE_CONTROL, policy);
+                    }
+
+                    res
+                })
+            })
+            .boxed_local()
+    }
+}
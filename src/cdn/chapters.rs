@@ -0,0 +1,27 @@
+use crate::{
+    config::Config,
+    error::Result,
+    media::chapters,
+    util::{path::resolve_file, signing, web::blocking},
+};
+use actix_web::{web, HttpRequest, HttpResponse};
+
+/// Serves a media file's chapters as a WebVTT chapters track.
+#[get("/chapters/{path:.*}")]
+pub async fn get_chapters(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    signing::verify_request(&config, &path, &req)?;
+    let file_path = resolve_file(&config, &path)?;
+    let config = config.into_inner();
+    let vtt = blocking(move || {
+        Ok(chapters::chapters_vtt(&chapters::find_chapters(
+            &config, &file_path,
+        )))
+    })
+    .await?;
+
+    Ok(HttpResponse::Ok().content_type("text/vtt").body(vtt))
+}
@@ -0,0 +1,25 @@
+use crate::{
+    config::Config,
+    error::{ErrorKind::NotFoundError, Result, ResultExt},
+    media::music,
+    util::{path::resolve_file, signing, web::blocking},
+};
+use actix_files::NamedFile;
+use actix_web::{web, HttpRequest};
+
+/// Serves the cover art embedded in an audio file.
+#[get("/covers/{path:.*}")]
+pub async fn get_cover(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<NamedFile> {
+    signing::verify_request(&config, &path, &req)?;
+    let file_path = resolve_file(&config, &path)?;
+    let config = config.into_inner();
+    let cover_path = blocking(move || music::cover_cached(&config, &file_path))
+        .await?
+        .ok_or(NotFoundError)?;
+
+    NamedFile::open(&cover_path).chain_err(|| format!("Error opening cover {:?}", cover_path))
+}
@@ -1,18 +1,21 @@
 use crate::{
+    cdn::throttle::Throttle,
     config::Config,
     error::{Error, ErrorKind},
-    util::path::parse_path,
+    util::{path::parse_path, signing, web::attachment},
 };
 use actix_files::Files;
 use actix_service::ServiceFactory;
 use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{self, IntoHeaderValue},
     web, Scope,
 };
 use futures::{
     future,
     future::{ok, Either, Ready},
     task::{Context, Poll},
+    FutureExt,
 };
 use std::{future::Future, pin::Pin, result};
 
@@ -31,9 +34,18 @@ pub fn service(
         .wrap(FilesLimiter {
             config: config.clone(),
         })
+        .wrap(Throttle {
+            config: config.clone(),
+        })
         .service(Files::new("", &config.base_dir))
 }
 
+/// `?download=1` makes browsers save a file instead of showing it.
+#[derive(Debug, Deserialize)]
+struct DownloadQuery {
+    download: Option<String>,
+}
+
 struct FilesLimiter {
     config: Config,
 }
@@ -90,12 +102,40 @@ where
 
         let path_str = real_path.to_string_lossy();
 
-        if self.config.is_legal_path(&path_str) {
-            Either::Right(Box::pin(self.service.call(req)))
-        } else {
-            Either::Left(ok(
+        if !self.config.is_legal_path(&path_str) {
+            return Either::Left(ok(
                 req.error_response(Error::from_kind(ErrorKind::FilesLimiterError))
-            ))
+            ));
         }
+
+        if let Err(e) = signing::verify_query(
+            &self.config,
+            req.match_info().path(),
+            req.query_string(),
+            req.peer_addr().map(|addr| addr.ip()),
+        ) {
+            return Either::Left(ok(req.error_response(e)));
+        }
+
+        let download = web::Query::<DownloadQuery>::from_query(req.query_string())
+            .ok()
+            .and_then(|query| query.into_inner().download)
+            .is_some_and(|download| download == "1" || download == "true");
+        let disposition = real_path
+            .file_name()
+            .filter(|_| download)
+            .and_then(|name| attachment(&name.to_string_lossy()).try_into().ok());
+
+        Either::Right(Box::pin(self.service.call(req).map(move |res| {
+            res.map(|mut res| {
+                // actix-files decides by mime type whether files show inline
+                if let Some(disposition) = disposition.filter(|_| res.status().is_success()) {
+                    res.headers_mut()
+                        .insert(header::CONTENT_DISPOSITION, disposition);
+                }
+
+                res
+            })
+        })))
     }
 }
@@ -0,0 +1,135 @@
+#[cfg(not(feature = "ffmpeg"))]
+use crate::error::ErrorKind::FeatureUnavailableError;
+use crate::{config::Config, error::Result};
+#[cfg(feature = "ffmpeg")]
+use crate::{
+    error::{
+        ErrorKind::{MediaProcessingError, NotFoundError},
+        ResultExt,
+    },
+    media::hls,
+    util::{path::resolve_file, signing, web::blocking},
+};
+#[cfg(feature = "ffmpeg")]
+use actix_web::rt::time::delay_for;
+use actix_web::{web, HttpRequest, HttpResponse};
+#[cfg(feature = "ffmpeg")]
+use std::{fs, time::Duration};
+
+#[cfg(feature = "ffmpeg")]
+const PLAYLIST_CONTENT_TYPE: &str = "application/vnd.apple.mpegurl";
+#[cfg(feature = "ffmpeg")]
+const SEGMENT_CONTENT_TYPE: &str = "video/mp2t";
+#[cfg(feature = "ffmpeg")]
+const MASTER_PLAYLIST_NAME: &str = "master.m3u8";
+#[cfg(feature = "ffmpeg")]
+const VARIANT_PLAYLIST_NAME: &str = "index.m3u8";
+#[cfg(feature = "ffmpeg")]
+const SEGMENT_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How long a request waits for its segment to be encoded before giving up.
+#[cfg(feature = "ffmpeg")]
+const SEGMENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+#[derive(Debug, Deserialize)]
+pub struct HlsQuery {
+    /// Index of the audio stream to play, instead of the default one.
+    audio_stream: Option<usize>,
+}
+
+/// Serves HLS playlists and segments for a video. Playlists link to each
+/// other relatively, so the layout is:
+///
+/// * `{path}/master.m3u8`
+/// * `{path}/{rendition}/index.m3u8`
+/// * `{path}/{rendition}/{segment}.ts`
+///
+/// Playlists pass the query they were requested with on to everything they
+/// link to, so the chosen audio stream carries over.
+#[get("/hls/{tail:.*}")]
+pub async fn get_hls(
+    config: web::Data<Config>,
+    tail: web::Path<String>,
+    query: web::Query<HlsQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    #[cfg(feature = "ffmpeg")]
+    {
+        let link_query = req.query_string().to_string();
+        if let Some(path) = tail.strip_suffix(&format!("/{}", MASTER_PLAYLIST_NAME)) {
+            signing::verify_request(&config, path, &req)?;
+            let file_path = resolve_file(&config, path)?;
+            let config = config.into_inner();
+            let playlist =
+                blocking(move || hls::master_playlist(&config, &file_path, &link_query)).await?;
+
+            return Ok(HttpResponse::Ok()
+                .content_type(PLAYLIST_CONTENT_TYPE)
+                .body(playlist));
+        }
+
+        let mut parts = tail.rsplitn(3, '/');
+        let (file, rendition, path) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(file), Some(rendition), Some(path)) => {
+                (file.to_string(), rendition.to_string(), path)
+            }
+            _ => bail!(NotFoundError),
+        };
+        signing::verify_request(&config, path, &req)?;
+        let file_path = resolve_file(&config, path)?;
+
+        if file == VARIANT_PLAYLIST_NAME {
+            let config = config.into_inner();
+            let playlist = blocking(move || {
+                hls::variant_playlist(&config, &file_path, &rendition, &link_query)
+            })
+            .await?;
+
+            return Ok(HttpResponse::Ok()
+                .content_type(PLAYLIST_CONTENT_TYPE)
+                .body(playlist));
+        }
+
+        let index: usize = match file.strip_suffix(".ts").map(str::parse) {
+            Some(Ok(index)) => index,
+            _ => bail!(NotFoundError),
+        };
+
+        let config = config.into_inner();
+        let audio_stream = query.audio_stream;
+        let mut waited = Duration::from_secs(0);
+        loop {
+            let (config, file_path, rendition) =
+                (config.clone(), file_path.clone(), rendition.clone());
+            let segment = blocking(move || {
+                hls::request_segment(&config, &file_path, &rendition, audio_stream, index)?
+                    .map(|segment_path| {
+                        fs::read(&segment_path)
+                            .chain_err(|| format!("Error reading HLS segment {:?}", segment_path))
+                    })
+                    .transpose()
+            })
+            .await?;
+
+            if let Some(segment) = segment {
+                return Ok(HttpResponse::Ok()
+                    .content_type(SEGMENT_CONTENT_TYPE)
+                    .body(segment));
+            }
+
+            if waited >= SEGMENT_TIMEOUT {
+                bail!(MediaProcessingError(
+                    format!("Timed out waiting for HLS segment {}", index).into()
+                ))
+            }
+            delay_for(SEGMENT_POLL_INTERVAL).await;
+            waited += SEGMENT_POLL_INTERVAL;
+        }
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = (config, tail, query, req);
+        bail!(FeatureUnavailableError("ffmpeg"))
+    }
+}
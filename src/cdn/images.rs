@@ -0,0 +1,95 @@
+#[cfg(not(feature = "ffmpeg"))]
+use crate::error::ErrorKind::FeatureUnavailableError;
+use crate::{
+    config::{Config, ThumbnailFormat},
+    error::Result,
+    util::signing,
+};
+#[cfg(feature = "ffmpeg")]
+use crate::{
+    error::{ErrorKind::NotFoundError, ResultExt},
+    media::{
+        image,
+        jobs::{self, CancelOnDrop, JobKind, JobPriority},
+    },
+    util::{
+        path::{is_image, resolve_file},
+        web::blocking,
+    },
+};
+use actix_files::NamedFile;
+use actix_web::{web, HttpRequest};
+
+/// The standard sizes photos come in.
+#[derive(Debug, Copy, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageVariant {
+    Thumbnail,
+    Screen,
+}
+
+/// Either a variant or a size to fit the image into. Without either, this is
+/// the screen variant.
+#[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+#[derive(Debug, Deserialize)]
+pub struct ImageQuery {
+    variant: Option<ImageVariant>,
+    width: Option<u32>,
+    height: Option<u32>,
+    format: Option<ThumbnailFormat>,
+}
+
+/// Serves an image resized to fit within a variant's size or the requested
+/// width and height, turned the way its EXIF orientation says. Requested
+/// sizes are rounded up to the configured sizes and capped at the maximum.
+#[get("/images/{path:.*}")]
+pub async fn get_image(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<ImageQuery>,
+    req: HttpRequest,
+) -> Result<NamedFile> {
+    signing::verify_request(&config, &path, &req)?;
+
+    #[cfg(feature = "ffmpeg")]
+    {
+        let file_path = resolve_file(&config, &path)?;
+        if !is_image(&path) {
+            bail!(NotFoundError)
+        }
+
+        let settings = &config.images;
+        let (max_width, max_height) = match (query.variant, query.width, query.height) {
+            (Some(ImageVariant::Thumbnail), _, _) => {
+                (settings.thumbnail_size, settings.thumbnail_size)
+            }
+            (Some(ImageVariant::Screen), _, _) | (None, None, None) => {
+                (settings.screen_size, settings.screen_size)
+            }
+            // every distinct size is another resize and another cache entry
+            (None, width, height) => (config.image_size(width), config.image_size(height)),
+        };
+        let format = query.format.unwrap_or(settings.format);
+
+        let job = jobs::submit(
+            JobKind::Image,
+            format!("{:?}", file_path),
+            JobPriority::Normal,
+        );
+        let _cancel = CancelOnDrop(job.clone());
+
+        let config = config.into_inner();
+        let image_path = blocking(move || {
+            image::image_cached(&config, &file_path, max_width, max_height, format, &job)
+        })
+        .await?;
+
+        NamedFile::open(&image_path).chain_err(|| format!("Error opening image {:?}", image_path))
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = (config, path, query);
+        bail!(FeatureUnavailableError("ffmpeg"))
+    }
+}
@@ -1,8 +1,57 @@
+mod archive;
+pub mod cache_control;
+mod chapters;
+mod covers;
 mod files;
+mod hls;
+mod images;
+mod optimized;
+mod remux;
+mod sprites;
+mod subtitles;
+mod throttle;
+mod thumbnails;
 
 use crate::config::Config;
-use actix_web::{web, Scope};
+use actix_service::ServiceFactory;
+use actix_web::{
+    dev::{ServiceRequest, ServiceResponse},
+    web, Scope,
+};
+use cache_control::CacheControl;
+use throttle::Throttle;
 
-pub fn services(config: &Config) -> Scope {
-    web::scope("/cdn").service(files::service(config))
+pub fn services(
+    config: &Config,
+) -> Scope<
+    impl ServiceFactory<
+        Config = (),
+        Request = ServiceRequest,
+        Response = ServiceResponse,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    web::scope("/cdn")
+        .wrap(CacheControl {
+            config: config.clone(),
+        })
+        .service(files::service(config))
+        // archives are bulk downloads as much as files are
+        .service(
+            web::scope("/archive")
+                .wrap(Throttle {
+                    config: config.clone(),
+                })
+                .service(archive::get_archive),
+        )
+        .service(chapters::get_chapters)
+        .service(covers::get_cover)
+        .service(hls::get_hls)
+        .service(images::get_image)
+        .service(optimized::get_optimized)
+        .service(remux::get_remux)
+        .service(sprites::get_sprite_file)
+        .service(subtitles::get_subtitles)
+        .service(thumbnails::get_thumbnail)
 }
@@ -0,0 +1,23 @@
+use crate::{
+    config::Config,
+    error::{ErrorKind::NotFoundError, Result, ResultExt},
+    media::optimize,
+    util::{path::resolve_file, signing},
+};
+use actix_files::NamedFile;
+use actix_web::{web, HttpRequest};
+
+/// Serves the optimized version of a file, if it has been optimized.
+#[get("/optimized/{path:.*}")]
+pub async fn get_optimized(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> Result<NamedFile> {
+    signing::verify_request(&config, &path, &req)?;
+    let file_path = resolve_file(&config, &path)?;
+    let optimized_path = optimize::optimized(&config, &file_path).ok_or(NotFoundError)?;
+
+    NamedFile::open(&optimized_path)
+        .chain_err(|| format!("Error opening optimized file {:?}", optimized_path))
+}
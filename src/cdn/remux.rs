@@ -0,0 +1,116 @@
+#[cfg(not(feature = "ffmpeg"))]
+use crate::error::ErrorKind::FeatureUnavailableError;
+use crate::{
+    config::{AudioCodec, Config},
+    error::Result,
+    util::signing,
+};
+#[cfg(feature = "ffmpeg")]
+use crate::{
+    media::{
+        jobs::{self, JobKind, JobPriority},
+        loudness,
+        remux::{RemuxOptions, Remuxer},
+        transcode::AudioSettings,
+    },
+    util::{path::resolve_file, web::blocking},
+};
+#[cfg(feature = "ffmpeg")]
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+#[cfg(feature = "ffmpeg")]
+use futures::{channel::mpsc, executor::block_on, SinkExt};
+#[cfg(feature = "ffmpeg")]
+use std::thread;
+
+/// How many chunks of muxed data can be waiting to be sent before the
+/// remuxer has to wait for the client.
+#[cfg(feature = "ffmpeg")]
+const REMUX_BUFFER_CHUNKS: usize = 32;
+
+/// Stereo is what browsers and most headphones actually play.
+#[cfg(feature = "ffmpeg")]
+const DOWNMIX_CHANNELS: u32 = 2;
+
+#[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+#[derive(Debug, Deserialize)]
+pub struct RemuxQuery {
+    /// Offset into the file in seconds to start streaming from.
+    start: Option<f64>,
+    /// Index of the audio stream to play.
+    audio_stream: Option<usize>,
+    /// Language of the audio stream to play, if `audio_stream` is not given.
+    language: Option<String>,
+    /// Transcodes the audio into this codec instead of copying it.
+    audio_codec: Option<AudioCodec>,
+    /// Mixes transcoded audio down to stereo.
+    #[serde(default)]
+    downmix: bool,
+}
+
+/// Streams a video remuxed into fragmented MP4 without re-encoding the video.
+/// The audio is copied too, unless an audio codec to transcode it to is
+/// requested.
+#[get("/remux/{path:.*}")]
+pub async fn get_remux(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<RemuxQuery>,
+    req: HttpRequest,
+) -> Result<HttpResponse> {
+    signing::verify_request(&config, &path, &req)?;
+
+    #[cfg(feature = "ffmpeg")]
+    {
+        let file_path = resolve_file(&config, &path)?;
+        let query = query.into_inner();
+        let options = RemuxOptions {
+            start: query.start.unwrap_or(0.0).max(0.0),
+            audio_stream: query.audio_stream,
+            language: query.language,
+            audio: query.audio_codec.map(|codec| AudioSettings {
+                codec,
+                channels: Some(DOWNMIX_CHANNELS).filter(|_| query.downmix),
+                bit_rate: None,
+                global_header: true,
+            }),
+            gain: loudness::transcode_gain(&config, &file_path),
+        };
+
+        let (mut sender, receiver) =
+            mpsc::channel::<std::result::Result<Bytes, actix_web::Error>>(REMUX_BUFFER_CHUNKS);
+        let sink = Box::new(move |data: &[u8]| {
+            block_on(sender.send(Ok(Bytes::copy_from_slice(data)))).is_ok()
+        });
+
+        // opened before responding so that files that can not be remuxed get
+        // a proper error response
+        let description = format!("{:?}", file_path);
+        let remuxer = blocking(move || Remuxer::open(&file_path, &options, sink)).await?;
+        thread::spawn(move || {
+            // only transcoding audio is real work, copying streams is not
+            // held back by the job limit
+            let result = if remuxer.is_transcoding() {
+                let job = jobs::submit(JobKind::Remux, description, JobPriority::Interactive);
+                job.wait_for_slot()
+                    .and_then(|_slot| remuxer.run(Some(&job)))
+            } else {
+                remuxer.run(None)
+            };
+
+            if let Err(e) = result {
+                e.log();
+            }
+        });
+
+        Ok(HttpResponse::Ok()
+            .content_type("video/mp4")
+            .streaming(receiver))
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = (config, path, query);
+        bail!(FeatureUnavailableError("ffmpeg"))
+    }
+}
@@ -0,0 +1,33 @@
+use crate::{
+    cdn::cache_control::IMMUTABLE,
+    config::Config,
+    error::{ErrorKind::NotFoundError, Result, ResultExt},
+    media::cache::AssetType,
+    util::path::parse_path,
+};
+use actix_files::NamedFile;
+use actix_web::{http::header, web, Responder};
+
+/// Serves generated seek-preview sprite sheets and their WebVTT files out of
+/// the cache. Their urls contain the video's cache key, which changes with the
+/// video, so they can be cached for good.
+#[get("/sprites/{key}/{file}")]
+pub async fn get_sprite_file(
+    config: web::Data<Config>,
+    path: web::Path<(String, String)>,
+) -> Result<impl Responder> {
+    let (key, file) = path.into_inner();
+    let relative_path = parse_path(&format!("{}/{}", key, file), false)?;
+    if relative_path.components().count() != 2 {
+        bail!(NotFoundError)
+    }
+
+    let sprite_path = AssetType::Sprites.dir(&config).join(relative_path);
+    if !sprite_path.is_file() {
+        bail!(NotFoundError)
+    }
+
+    Ok(NamedFile::open(&sprite_path)
+        .chain_err(|| format!("Error opening sprite {:?}", sprite_path))?
+        .with_header(header::CACHE_CONTROL, IMMUTABLE))
+}
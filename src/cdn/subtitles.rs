@@ -0,0 +1,33 @@
+use crate::{
+    config::Config,
+    error::{Result, ResultExt},
+    media::subtitles,
+    util::{path::resolve_file, signing, web::blocking},
+};
+use actix_files::NamedFile;
+use actix_web::{web, HttpRequest};
+
+#[derive(Debug, Deserialize)]
+pub struct SubtitleQuery {
+    /// The track id, as listed in the file's index entry.
+    track: String,
+}
+
+/// Serves a subtitle track of a video converted to WebVTT, converting and
+/// caching it first if needed.
+#[get("/subtitles/{path:.*}")]
+pub async fn get_subtitles(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<SubtitleQuery>,
+    req: HttpRequest,
+) -> Result<NamedFile> {
+    signing::verify_request(&config, &path, &req)?;
+    let file_path = resolve_file(&config, &path)?;
+    let track = query.into_inner().track;
+
+    let config = config.into_inner();
+    let vtt_path = blocking(move || subtitles::vtt_cached(&config, &file_path, &track)).await?;
+
+    NamedFile::open(&vtt_path).chain_err(|| format!("Error opening subtitles {:?}", vtt_path))
+}
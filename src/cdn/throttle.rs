@@ -0,0 +1,295 @@
+use crate::{
+    config::{Config, ConfigThrottle},
+    util::path::file_extension,
+};
+use actix_service::{Service, Transform};
+use actix_web::{
+    body::{Body, BodySize, MessageBody, ResponseBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    rt::time::{delay_for, Delay},
+    web::Bytes,
+};
+use futures::{
+    future,
+    future::{LocalBoxFuture, Ready},
+    ready,
+    task::{Context, Poll},
+    Future, FutureExt,
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    pin::Pin,
+    result,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    /// Buckets shared by all responses, across workers.
+    static ref SHARED_BUCKETS: Mutex<SharedBuckets> = Mutex::new(SharedBuckets::default());
+}
+
+#[derive(Debug, Default)]
+struct SharedBuckets {
+    global: Option<Bucket>,
+    clients: HashMap<IpAddr, Bucket>,
+}
+
+/// A token bucket holding up to a second's worth of bytes. Taking more than
+/// it holds puts it in debt, which has to be waited off.
+#[derive(Debug)]
+struct Bucket {
+    /// Bytes per second.
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Creates a full bucket for a limit in kbit/s.
+    fn new(limit: u32) -> Bucket {
+        let rate = f64::from(limit) * 1000.0 / 8.0;
+
+        Bucket {
+            rate,
+            tokens: rate,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        self.tokens = (self.tokens + (now - self.updated).as_secs_f64() * self.rate).min(self.rate);
+        self.updated = now;
+    }
+
+    /// Takes bytes out of the bucket, giving how long to wait until they are
+    /// paid for. Exempt bytes are not waited for, so they put the bucket at
+    /// most a second into debt, or paced downloads could stall for long after
+    /// playback stops.
+    fn take(&mut self, bytes: usize, exempt: bool) -> Duration {
+        self.refill();
+        self.tokens -= bytes as f64;
+        if exempt {
+            self.tokens = self.tokens.max(-self.rate);
+        }
+
+        if self.tokens < 0.0 {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        } else {
+            Duration::from_secs(0)
+        }
+    }
+
+    fn is_full(&mut self) -> bool {
+        self.refill();
+        self.tokens >= self.rate
+    }
+}
+
+/// Paces file downloads to the configured bandwidth limits.
+pub struct Throttle {
+    pub config: Config,
+}
+
+impl<S> Transform<S> for Throttle
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Transform = ThrottleMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<result::Result<Self::Transform, ()>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(ThrottleMiddleware {
+            service,
+            limits: self.config.throttle.clone(),
+        })
+    }
+}
+
+pub struct ThrottleMiddleware<S> {
+    service: S,
+    limits: ConfigThrottle,
+}
+
+impl<S> Service for ThrottleMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = actix_web::Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, result::Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, ctx: &mut Context<'_>) -> Poll<result::Result<(), Self::Error>> {
+        self.service.poll_ready(ctx)
+    }
+
+    fn call(&mut self, req: Self::Request) -> Self::Future {
+        let limits = self.limits.clone();
+        if limits.global_limit == 0 && limits.connection_limit == 0 && limits.client_limit == 0 {
+            return self.service.call(req).boxed_local();
+        }
+
+        let exempt = limits.exempt_playback && looks_like_playback(&req);
+        let client = if limits.client_limit > 0 {
+            // buckets that filled back up are no different from new ones
+            let mut shared = SHARED_BUCKETS.lock().unwrap();
+            shared.clients.retain(|_, bucket| !bucket.is_full());
+
+            req.peer_addr().map(|addr| addr.ip())
+        } else {
+            None
+        };
+
+        self.service
+            .call(req)
+            .map(move |res| {
+                res.map(|res| {
+                    res.map_body(|_, body| {
+                        ResponseBody::Other(Body::from_message(ThrottledBody::new(
+                            body, &limits, exempt, client,
+                        )))
+                    })
+                })
+            })
+            .boxed_local()
+    }
+}
+
+/// Players stream audio and video with range requests, so those are told
+/// apart from downloads, which fetch the whole file. Only ranges with an end
+/// count, as an open one like `bytes=0-` fetches the whole file just the same.
+fn looks_like_playback(req: &ServiceRequest) -> bool {
+    let bounded_range = req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|range| range.strip_prefix("bytes="))
+        .and_then(|range| range.split_once('-'))
+        .is_some_and(|(start, end)| {
+            start.trim().parse::<u64>().is_ok() && end.trim().parse::<u64>().is_ok()
+        });
+    let mime = actix_files::file_extension_to_mime(file_extension(req.path()).unwrap_or(""));
+
+    bounded_range && (mime.type_() == "video" || mime.type_() == "audio")
+}
+
+/// Holds back each chunk of a response body until every bucket it counts
+/// against has paid for it. Exempt bodies are never held back, but still
+/// draw from the global and per-client buckets, so throttled downloads make
+/// room for them.
+struct ThrottledBody {
+    body: ResponseBody<Body>,
+    exempt: bool,
+    client: Option<IpAddr>,
+    connection: Option<Bucket>,
+    global_limit: u32,
+    client_limit: u32,
+    delay: Option<Pin<Box<Delay>>>,
+    pending: Option<Bytes>,
+}
+
+impl ThrottledBody {
+    fn new(
+        body: ResponseBody<Body>,
+        limits: &ConfigThrottle,
+        exempt: bool,
+        client: Option<IpAddr>,
+    ) -> ThrottledBody {
+        ThrottledBody {
+            body,
+            exempt,
+            client,
+            connection: Some(limits.connection_limit)
+                .filter(|limit| *limit > 0)
+                .map(Bucket::new),
+            global_limit: limits.global_limit,
+            client_limit: limits.client_limit,
+            delay: None,
+            pending: None,
+        }
+    }
+
+    fn take(&mut self, bytes: usize) -> Duration {
+        let exempt = self.exempt;
+        let mut wait = self
+            .connection
+            .as_mut()
+            .map_or(Duration::from_secs(0), |bucket| bucket.take(bytes, exempt));
+
+        let mut shared = SHARED_BUCKETS.lock().unwrap();
+        if self.global_limit > 0 {
+            let global_limit = self.global_limit;
+            wait = wait.max(
+                shared
+                    .global
+                    .get_or_insert_with(|| Bucket::new(global_limit))
+                    .take(bytes, exempt),
+            );
+        }
+        if let Some(client) = self.client {
+            let client_limit = self.client_limit;
+            wait = wait.max(
+                shared
+                    .clients
+                    .entry(client)
+                    .or_insert_with(|| Bucket::new(client_limit))
+                    .take(bytes, exempt),
+            );
+        }
+
+        if exempt {
+            Duration::from_secs(0)
+        } else {
+            wait
+        }
+    }
+}
+
+impl MessageBody for ThrottledBody {
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<result::Result<Bytes, actix_web::Error>>> {
+        let this = self.get_mut();
+
+        if let Some(delay) = &mut this.delay {
+            ready!(delay.as_mut().poll(cx));
+            this.delay = None;
+            return Poll::Ready(this.pending.take().map(Ok));
+        }
+
+        match ready!(Pin::new(&mut this.body).poll_next(cx)) {
+            Some(Ok(chunk)) => {
+                let wait = this.take(chunk.len());
+                if wait == Duration::from_secs(0) {
+                    return Poll::Ready(Some(Ok(chunk)));
+                }
+
+                // polling registers for a wakeup once the delay has passed
+                let mut delay = Box::pin(delay_for(wait));
+                if delay.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Some(Ok(chunk)));
+                }
+                this.delay = Some(delay);
+                this.pending = Some(chunk);
+
+                Poll::Pending
+            }
+            other => Poll::Ready(other),
+        }
+    }
+}
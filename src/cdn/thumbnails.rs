@@ -0,0 +1,65 @@
+#[cfg(not(feature = "ffmpeg"))]
+use crate::error::ErrorKind::FeatureUnavailableError;
+use crate::{
+    config::{Config, ThumbnailFormat},
+    error::Result,
+    util::signing,
+};
+#[cfg(feature = "ffmpeg")]
+use crate::{
+    error::ResultExt,
+    media::{
+        jobs::{self, CancelOnDrop, JobKind, JobPriority},
+        thumbnail,
+    },
+    util::{path::resolve_file, web::blocking},
+};
+use actix_files::NamedFile;
+use actix_web::{web, HttpRequest};
+
+#[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+#[derive(Debug, Deserialize)]
+pub struct ThumbnailQuery {
+    width: Option<u32>,
+    format: Option<ThumbnailFormat>,
+}
+
+/// Serves a thumbnail of a video file, generating and caching it first if
+/// needed. Generating is cancelled if the client goes away first.
+#[get("/thumbnails/{path:.*}")]
+pub async fn get_thumbnail(
+    config: web::Data<Config>,
+    path: web::Path<String>,
+    query: web::Query<ThumbnailQuery>,
+    req: HttpRequest,
+) -> Result<NamedFile> {
+    signing::verify_request(&config, &path, &req)?;
+
+    #[cfg(feature = "ffmpeg")]
+    {
+        let file_path = resolve_file(&config, &path)?;
+        let width = config.thumbnail_size(query.width);
+        let format = query.format.unwrap_or(config.thumbnails.format);
+
+        let job = jobs::submit(
+            JobKind::Thumbnail,
+            format!("{:?}", file_path),
+            JobPriority::Normal,
+        );
+        let _cancel = CancelOnDrop(job.clone());
+
+        let config = config.into_inner();
+        let thumbnail_path =
+            blocking(move || thumbnail::thumbnail_cached(&config, &file_path, width, format, &job))
+                .await?;
+
+        NamedFile::open(&thumbnail_path)
+            .chain_err(|| format!("Error opening thumbnail {:?}", thumbnail_path))
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = (config, path, query);
+        bail!(FeatureUnavailableError("ffmpeg"))
+    }
+}
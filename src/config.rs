@@ -1,18 +1,49 @@
-use crate::error::{ErrorKind::ConfigLoadError, Result, ResultExt};
-use regex::RegexSet;
+use crate::{
+    error::{ErrorKind::ConfigLoadError, Result, ResultExt},
+    metadata::folder::is_folder_metadata_file,
+    util::signing::generate_secret,
+};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::{Regex, RegexSet};
 use serde::{Deserialize, Serialize};
 use std::{
+    env,
     fs::{File, OpenOptions},
     io::{Read, Write},
     path::{Path, PathBuf},
 };
 
 const CONFIG_FILE_NAME: &str = "media-server-1.toml";
+const DEFAULT_THUMBNAIL_SIZE: u32 = 320;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct ConfigRaw {
     #[serde(default)]
     general: ConfigGeneral,
+    #[serde(default)]
+    cache: ConfigCache,
+    #[serde(default)]
+    thumbnails: ConfigThumbnails,
+    #[serde(default)]
+    images: ConfigImages,
+    #[serde(default)]
+    sprites: ConfigSprites,
+    #[serde(default)]
+    hls: ConfigHls,
+    #[serde(default)]
+    jobs: ConfigJobs,
+    #[serde(default)]
+    loudness: ConfigLoudness,
+    #[serde(default)]
+    optimize: ConfigOptimize,
+    #[serde(rename = "cache-control", default)]
+    cache_control: ConfigCacheControl,
+    #[serde(default)]
+    throttle: ConfigThrottle,
+    #[serde(default)]
+    signing: ConfigSigning,
+    #[serde(default = "default_profiles")]
+    profiles: Vec<DeviceProfile>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -21,6 +52,8 @@ struct ConfigGeneral {
     base_dir: String,
     #[serde(rename = "exclude-patterns", default = "default_exclude_patterns")]
     exclude_patterns: Vec<String>,
+    #[serde(rename = "cache-dir", default = "default_cache_dir")]
+    cache_dir: String,
     #[serde(default = "default_bindings")]
     bindings: Vec<String>,
     #[serde(rename = "welcome-title", default = "default_welcome_title")]
@@ -29,13 +62,309 @@ struct ConfigGeneral {
     welcome_content: String,
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigCache {
+    /// How many megabytes the cache may use before the least recently used
+    /// assets are evicted. Optimized files are not counted, and are never
+    /// evicted.
+    #[serde(rename = "max-size", default = "default_cache_max_size")]
+    pub max_size: u64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigThumbnails {
+    /// How far into a video, in percent, the thumbnail frame is taken from.
+    #[serde(rename = "seek-percent", default = "default_thumbnail_seek_percent")]
+    pub seek_percent: f64,
+    /// Whether to keep looking further into a video when the thumbnail frame
+    /// is (nearly) black.
+    #[serde(rename = "avoid-black-frames", default = "default_true")]
+    pub avoid_black_frames: bool,
+    /// The widths thumbnails can be generated at. Requested widths are rounded
+    /// up to the nearest of these.
+    #[serde(default = "default_thumbnail_sizes")]
+    pub sizes: Vec<u32>,
+    #[serde(default)]
+    pub format: ThumbnailFormat,
+}
+
+/// Resized variants of the photos in the library. Sizes are the longest side
+/// in pixels, and images are never scaled up.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigImages {
+    #[serde(rename = "thumbnail-size", default = "default_image_thumbnail_size")]
+    pub thumbnail_size: u32,
+    /// The size photos are shown at when viewed on their own.
+    #[serde(rename = "screen-size", default = "default_image_screen_size")]
+    pub screen_size: u32,
+    /// The widths and heights images can be resized to. Requested sizes are
+    /// rounded up to the nearest of these, so each image has only a few sizes.
+    #[serde(default = "default_image_sizes")]
+    pub sizes: Vec<u32>,
+    /// The largest width or height that can be asked for when resizing.
+    #[serde(rename = "max-size", default = "default_image_max_size")]
+    pub max_size: u32,
+    #[serde(default)]
+    pub format: ThumbnailFormat,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigSprites {
+    /// Seconds between seek-preview frames.
+    #[serde(default = "default_sprite_interval")]
+    pub interval: u32,
+    #[serde(rename = "tile-width", default = "default_sprite_tile_width")]
+    pub tile_width: u32,
+    /// How many tiles wide each sprite sheet is.
+    #[serde(default = "default_sprite_grid_size")]
+    pub columns: u32,
+    /// How many tiles tall each sprite sheet is.
+    #[serde(default = "default_sprite_grid_size")]
+    pub rows: u32,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigHls {
+    /// Seconds of video in each HLS segment.
+    #[serde(rename = "segment-length", default = "default_hls_segment_length")]
+    pub segment_length: u32,
+    /// Seconds after the last request before a stream's encoder is stopped and
+    /// its segments are deleted.
+    #[serde(rename = "idle-timeout", default = "default_hls_idle_timeout")]
+    pub idle_timeout: u64,
+    /// The qualities offered. Renditions taller than the source video are left
+    /// out.
+    #[serde(default = "default_hls_renditions")]
+    pub renditions: Vec<HlsRendition>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigJobs {
+    /// How many ffmpeg jobs may run at once. Further jobs wait in a queue.
+    #[serde(rename = "max-concurrent", default = "default_jobs_max_concurrent")]
+    pub max_concurrent: usize,
+    /// Lets requests with an `Authorization: Bearer` header carrying this
+    /// token list and cancel jobs, and queue files for optimizing. Without
+    /// one, only trusted local requests can.
+    #[serde(rename = "admin-token", default)]
+    pub admin_token: String,
+    /// Whether requests from localhost need no token. Everything that comes
+    /// through a local reverse proxy is from localhost too, so this is only
+    /// safe without one.
+    #[serde(rename = "trust-localhost", default)]
+    pub trust_localhost: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigLoudness {
+    /// Whether audio and video files are analyzed in the background, starting
+    /// with a scan of the whole library.
+    #[serde(default = "default_true")]
+    pub analyze: bool,
+    /// The integrated loudness, in LUFS, suggested gains aim for.
+    #[serde(default = "default_loudness_target")]
+    pub target: f64,
+    /// The true peak, in dBTP, suggested gains never push a file above.
+    #[serde(rename = "max-true-peak", default = "default_loudness_max_true_peak")]
+    pub max_true_peak: f64,
+    /// The most, in dB, suggested gains boost quiet files by, so near silence
+    /// is not turned into loud noise.
+    #[serde(rename = "max-gain", default = "default_loudness_max_gain")]
+    pub max_gain: f64,
+    /// Whether transcoded audio gets the suggested gain applied.
+    #[serde(default)]
+    pub normalize: bool,
+}
+
+/// Pre-encoding of files for playback without live transcoding. Optimized
+/// files are H.264 and AAC in MP4, kept in the cache directory but outside of
+/// its quota, and are never evicted.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigOptimize {
+    /// The name of the device profile files are optimized for. Files it can
+    /// already play directly are left alone.
+    #[serde(default = "default_optimize_profile")]
+    pub profile: String,
+    #[serde(rename = "max-height", default = "default_optimize_max_height")]
+    pub max_height: u32,
+    /// Video bitrate in kbit/s.
+    #[serde(rename = "video-bitrate", default = "default_optimize_video_bitrate")]
+    pub video_bitrate: u32,
+    /// Audio bitrate in kbit/s.
+    #[serde(rename = "audio-bitrate", default = "default_optimize_audio_bitrate")]
+    pub audio_bitrate: u32,
+}
+
+/// `Cache-Control` policies for CDN responses. The first rule whose
+/// conditions all match a response decides its policy. Generated assets with
+/// content-hash urls, like sprite sheets, are always `immutable`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ConfigCacheControl {
+    /// The policy for responses no rule matches. Without one, no
+    /// `Cache-Control` header is sent and browsers revalidate with the
+    /// `ETag` and `Last-Modified` headers as they see fit.
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub rules: Vec<CacheControlRule>,
+}
+
+/// Bandwidth limits for downloads from `/cdn/files`, in kbit/s. 0 leaves a
+/// limit out.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigThrottle {
+    /// Shared by all downloads.
+    #[serde(rename = "global-limit", default)]
+    pub global_limit: u32,
+    #[serde(rename = "connection-limit", default)]
+    pub connection_limit: u32,
+    /// Shared by all downloads to the same client IP address.
+    #[serde(rename = "client-limit", default)]
+    pub client_limit: u32,
+    /// Whether bounded range requests for audio and video files, which is how
+    /// players stream them, are left unpaced. They still use up the global
+    /// and per-client bandwidth, so downloads are paced around them.
+    #[serde(rename = "exempt-playback", default = "default_true")]
+    pub exempt_playback: bool,
+}
+
+/// Signed, expiring CDN urls, for handing out links to single files. A
+/// signature is for a file's path, and is good for everything the CDN serves
+/// for that file: the file itself, its remuxed, transcoded and optimized
+/// versions, images, covers, subtitles, chapters and thumbnails. Directory
+/// archives are signed for the directory's path.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ConfigSigning {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Whether unsigned requests are refused. Signed ones are checked either
+    /// way. Sprite sheets are left open, as their urls hold a key only the
+    /// API hands out.
+    #[serde(default)]
+    pub required: bool,
+    /// The HMAC key. A random one is generated when signing is enabled
+    /// without one.
+    #[serde(default)]
+    pub secret: String,
+    /// How long the urls the index hands out stay valid, in seconds.
+    #[serde(rename = "url-lifetime", default = "default_signing_url_lifetime")]
+    pub url_lifetime: u64,
+    /// Whether the urls the index hands out only work for the client IP
+    /// address that requested them.
+    #[serde(rename = "bind-ip", default)]
+    pub bind_ip: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CacheControlRule {
+    /// A MIME type like `video/mp4`, or a wildcard like `video/*`.
+    #[serde(default)]
+    pub mime: Option<String>,
+    /// A glob matched against the path below `/cdn/`, like `files/Music/**`
+    /// or `hls/**/*.m3u8`.
+    #[serde(default)]
+    pub glob: Option<String>,
+    /// The `Cache-Control` header's value, like `public, max-age=604800` or
+    /// `no-store`.
+    pub policy: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HlsRendition {
+    pub name: String,
+    pub height: u32,
+    /// Video bitrate in kbit/s.
+    #[serde(rename = "video-bitrate")]
+    pub video_bitrate: u32,
+    /// Audio bitrate in kbit/s.
+    #[serde(rename = "audio-bitrate")]
+    pub audio_bitrate: u32,
+}
+
+/// What a kind of client can play. The first profile whose `user-agent`
+/// pattern matches a request's User-Agent is used for it, and a profile
+/// without a pattern matches everything.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DeviceProfile {
+    pub name: String,
+    #[serde(
+        rename = "user-agent",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub user_agent: Option<String>,
+    /// File extensions of the containers that can be played directly.
+    pub containers: Vec<String>,
+    /// Video codecs, as ffmpeg names them.
+    #[serde(rename = "video-codecs")]
+    pub video_codecs: Vec<String>,
+    /// Audio codecs, as ffmpeg names them.
+    #[serde(rename = "audio-codecs")]
+    pub audio_codecs: Vec<String>,
+    #[serde(
+        rename = "max-height",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_height: Option<u32>,
+    /// Maximum overall bitrate in kbit/s.
+    #[serde(
+        rename = "max-bitrate",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_bitrate: Option<u32>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default, Deserialize, Serialize)]
+pub enum ThumbnailFormat {
+    #[default]
+    #[serde(rename = "jpeg")]
+    Jpeg,
+    #[serde(rename = "webp")]
+    Webp,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub enum AudioCodec {
+    #[serde(rename = "aac")]
+    Aac,
+    #[serde(rename = "opus")]
+    Opus,
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub base_dir: PathBuf,
     pub exclude_patterns: RegexSet,
+    pub cache_dir: PathBuf,
+    /// Where the cache directory is relative to `base_dir`, if it is inside
+    /// of it. Its contents are no part of the library.
+    cache_dir_in_base_dir: Option<PathBuf>,
     pub bindings: Vec<String>,
     pub welcome_title: String,
     pub welcome_content: String,
+    pub cache: ConfigCache,
+    #[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+    pub thumbnails: ConfigThumbnails,
+    #[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+    pub images: ConfigImages,
+    #[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+    pub sprites: ConfigSprites,
+    #[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+    pub hls: ConfigHls,
+    pub jobs: ConfigJobs,
+    pub loudness: ConfigLoudness,
+    #[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+    pub optimize: ConfigOptimize,
+    pub cache_control: ConfigCacheControl,
+    /// The cache control rules' compiled globs, in the same order.
+    pub cache_control_globs: Vec<Option<GlobMatcher>>,
+    pub throttle: ConfigThrottle,
+    pub signing: ConfigSigning,
+    pub profiles: Vec<DeviceProfile>,
+    /// The profiles' compiled `user-agent` patterns, in the same order.
+    pub profile_patterns: Vec<Option<Regex>>,
 }
 
 impl Default for ConfigGeneral {
@@ -43,6 +372,7 @@ impl Default for ConfigGeneral {
         ConfigGeneral {
             base_dir: default_base_dir(),
             exclude_patterns: default_exclude_patterns(),
+            cache_dir: default_cache_dir(),
             bindings: default_bindings(),
             welcome_title: default_welcome_title(),
             welcome_content: default_welcome_content(),
@@ -50,13 +380,142 @@ impl Default for ConfigGeneral {
     }
 }
 
+impl Default for ConfigCache {
+    fn default() -> Self {
+        ConfigCache {
+            max_size: default_cache_max_size(),
+        }
+    }
+}
+
+impl Default for ConfigThumbnails {
+    fn default() -> Self {
+        ConfigThumbnails {
+            seek_percent: default_thumbnail_seek_percent(),
+            avoid_black_frames: default_true(),
+            sizes: default_thumbnail_sizes(),
+            format: ThumbnailFormat::default(),
+        }
+    }
+}
+
+impl Default for ConfigImages {
+    fn default() -> Self {
+        ConfigImages {
+            thumbnail_size: default_image_thumbnail_size(),
+            screen_size: default_image_screen_size(),
+            sizes: default_image_sizes(),
+            max_size: default_image_max_size(),
+            format: ThumbnailFormat::default(),
+        }
+    }
+}
+
+impl Default for ConfigSprites {
+    fn default() -> Self {
+        ConfigSprites {
+            interval: default_sprite_interval(),
+            tile_width: default_sprite_tile_width(),
+            columns: default_sprite_grid_size(),
+            rows: default_sprite_grid_size(),
+        }
+    }
+}
+
+impl Default for ConfigHls {
+    fn default() -> Self {
+        ConfigHls {
+            segment_length: default_hls_segment_length(),
+            idle_timeout: default_hls_idle_timeout(),
+            renditions: default_hls_renditions(),
+        }
+    }
+}
+
+impl Default for ConfigJobs {
+    fn default() -> Self {
+        ConfigJobs {
+            max_concurrent: default_jobs_max_concurrent(),
+            admin_token: String::new(),
+            trust_localhost: false,
+        }
+    }
+}
+
+impl Default for ConfigLoudness {
+    fn default() -> Self {
+        ConfigLoudness {
+            analyze: default_true(),
+            target: default_loudness_target(),
+            max_true_peak: default_loudness_max_true_peak(),
+            max_gain: default_loudness_max_gain(),
+            normalize: false,
+        }
+    }
+}
+
+impl Default for ConfigOptimize {
+    fn default() -> Self {
+        ConfigOptimize {
+            profile: default_optimize_profile(),
+            max_height: default_optimize_max_height(),
+            video_bitrate: default_optimize_video_bitrate(),
+            audio_bitrate: default_optimize_audio_bitrate(),
+        }
+    }
+}
+
+impl Default for ConfigThrottle {
+    fn default() -> Self {
+        ConfigThrottle {
+            global_limit: 0,
+            connection_limit: 0,
+            client_limit: 0,
+            exempt_playback: default_true(),
+        }
+    }
+}
+
+impl Default for ConfigSigning {
+    fn default() -> Self {
+        ConfigSigning {
+            enabled: false,
+            required: false,
+            secret: String::new(),
+            url_lifetime: default_signing_url_lifetime(),
+            bind_ip: false,
+        }
+    }
+}
+
+impl ThumbnailFormat {
+    #[cfg(feature = "ffmpeg")]
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Jpeg => "jpg",
+            ThumbnailFormat::Webp => "webp",
+        }
+    }
+}
+
+impl AudioCodec {
+    /// The codec's name, as ffmpeg and the query strings name it.
+    #[cfg(feature = "ffmpeg")]
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "opus",
+        }
+    }
+}
+
 impl Config {
     pub fn load() -> Result<Config> {
         info!("Loading config: {}", CONFIG_FILE_NAME);
 
         let cfg_path = Path::new(CONFIG_FILE_NAME);
 
-        let cfg_raw: ConfigRaw = if cfg_path.exists() {
+        let mut cfg_raw: ConfigRaw = if cfg_path.exists() {
             let mut cfg_file = File::open(CONFIG_FILE_NAME)
                 .chain_err(|| ConfigLoadError("Error opening config file".into()))?;
             let mut cfg_string = String::new();
@@ -70,6 +529,17 @@ impl Config {
             toml::from_str("").chain_err(|| ConfigLoadError("Error loading blank config".into()))?
         };
 
+        if cfg_raw.images.max_size == 0 {
+            bail!(ConfigLoadError(
+                "images.max-size has to be at least 1".into()
+            ))
+        }
+
+        if cfg_raw.signing.enabled && cfg_raw.signing.secret.is_empty() {
+            info!("Generating a secret for signing urls");
+            cfg_raw.signing.secret = generate_secret();
+        }
+
         debug!("Writing config file...");
         let new_cfg_string = toml::to_string_pretty(&cfg_raw)
             .chain_err(|| ConfigLoadError("Error re-encoding config file".into()))?;
@@ -82,18 +552,177 @@ impl Config {
             .write_all(new_cfg_string.as_bytes())
             .chain_err(|| ConfigLoadError("Error re-writing config file".into()))?;
 
+        // cached assets are keyed by path, so every file has to be reached by
+        // the same one
+        let base_dir = PathBuf::from(&cfg_raw.general.base_dir);
+        let base_dir = base_dir.canonicalize().unwrap_or_else(|e| {
+            warn!("Could not canonicalize base dir {:?}: {}", base_dir, e);
+            base_dir
+        });
+        let cache_dir = PathBuf::from(&cfg_raw.general.cache_dir);
+        // the cache directory may not have been created yet
+        let cache_dir_in_base_dir = cache_dir
+            .canonicalize()
+            .or_else(|_| env::current_dir().map(|dir| dir.join(&cache_dir)))
+            .ok()
+            .and_then(|cache_dir| {
+                cache_dir
+                    .strip_prefix(&base_dir)
+                    .ok()
+                    .map(Path::to_path_buf)
+            })
+            .filter(|relative_path| relative_path.components().next().is_some());
+
         Ok(Config {
-            base_dir: cfg_raw.general.base_dir.into(),
+            base_dir,
             exclude_patterns: RegexSet::new(cfg_raw.general.exclude_patterns)
                 .chain_err(|| ConfigLoadError("Error parsing regex".into()))?,
+            cache_dir,
+            cache_dir_in_base_dir,
             bindings: cfg_raw.general.bindings,
             welcome_title: cfg_raw.general.welcome_title,
             welcome_content: cfg_raw.general.welcome_content,
+            cache: cfg_raw.cache,
+            thumbnails: cfg_raw.thumbnails,
+            images: cfg_raw.images,
+            sprites: cfg_raw.sprites,
+            hls: cfg_raw.hls,
+            jobs: cfg_raw.jobs,
+            loudness: cfg_raw.loudness,
+            optimize: cfg_raw.optimize,
+            cache_control_globs: cfg_raw
+                .cache_control
+                .rules
+                .iter()
+                .map(|rule| {
+                    rule.glob
+                        .as_deref()
+                        .map(|glob| {
+                            GlobBuilder::new(glob)
+                                .literal_separator(true)
+                                .build()
+                                .map(|glob| glob.compile_matcher())
+                        })
+                        .transpose()
+                })
+                .collect::<std::result::Result<_, _>>()
+                .chain_err(|| ConfigLoadError("Error parsing cache-control glob".into()))?,
+            cache_control: cfg_raw.cache_control,
+            throttle: cfg_raw.throttle,
+            signing: cfg_raw.signing,
+            profile_patterns: cfg_raw
+                .profiles
+                .iter()
+                .map(|profile| profile.user_agent.as_deref().map(Regex::new).transpose())
+                .collect::<std::result::Result<_, _>>()
+                .chain_err(|| ConfigLoadError("Error parsing profile user-agent regex".into()))?,
+            profiles: cfg_raw.profiles,
         })
     }
 
     pub fn is_legal_path(&self, path: &str) -> bool {
         !self.exclude_patterns.is_match(path)
+            && !is_folder_metadata_file(Path::new(path))
+            && !self
+                .cache_dir_in_base_dir
+                .as_ref()
+                .is_some_and(|cache_dir| Path::new(path).starts_with(cache_dir))
+    }
+
+    /// Finds a device profile by name, falling back to the generic built-in
+    /// profile when there is none by that name.
+    #[cfg(feature = "ffmpeg")]
+    pub fn named_profile(&self, name: &str) -> DeviceProfile {
+        self.profiles
+            .iter()
+            .find(|profile| profile.name == name)
+            .cloned()
+            .unwrap_or_else(generic_profile)
+    }
+
+    /// Finds the device profile for a User-Agent, falling back to the generic
+    /// built-in profile when none matches.
+    pub fn device_profile(&self, user_agent: &str) -> DeviceProfile {
+        self.profiles
+            .iter()
+            .zip(&self.profile_patterns)
+            .find(|(_, pattern)| {
+                pattern
+                    .as_ref()
+                    .is_none_or(|pattern| pattern.is_match(user_agent))
+            })
+            .map(|(profile, _)| profile.clone())
+            .unwrap_or_else(generic_profile)
+    }
+
+    /// Finds the `Cache-Control` policy for a CDN response by its path below
+    /// `/cdn/` and its MIME type.
+    pub fn cache_control_policy(&self, path: &str, mime: &str) -> Option<&str> {
+        self.cache_control
+            .rules
+            .iter()
+            .zip(&self.cache_control_globs)
+            .find(|(rule, glob)| {
+                rule.mime
+                    .as_deref()
+                    .is_none_or(|pattern| mime_matches(pattern, mime))
+                    && glob.as_ref().is_none_or(|glob| glob.is_match(path))
+            })
+            .map(|(rule, _)| rule.policy.as_str())
+            .or(self.cache_control.default.as_deref())
+    }
+
+    /// Rounds a requested thumbnail width up to the nearest configured size,
+    /// or down to the largest one.
+    #[cfg(feature = "ffmpeg")]
+    pub fn thumbnail_size(&self, width: Option<u32>) -> u32 {
+        let mut sizes = self.thumbnails.sizes.clone();
+        sizes.sort_unstable();
+
+        match width {
+            None => sizes.first().copied(),
+            Some(width) => sizes
+                .iter()
+                .copied()
+                .find(|size| *size >= width)
+                .or_else(|| sizes.last().copied()),
+        }
+        .unwrap_or(DEFAULT_THUMBNAIL_SIZE)
+    }
+
+    /// Rounds a requested image width or height up to the nearest configured
+    /// size, or to the maximum size if none is that large.
+    #[cfg(feature = "ffmpeg")]
+    pub fn image_size(&self, size: Option<u32>) -> u32 {
+        let max_size = self.images.max_size;
+
+        size.and_then(|size| {
+            self.images
+                .sizes
+                .iter()
+                .copied()
+                .filter(|candidate| *candidate >= size && *candidate > 0)
+                .min()
+        })
+        .unwrap_or(max_size)
+        .min(max_size)
+    }
+}
+
+/// Matches a MIME type, ignoring any parameters, against a pattern that may
+/// have `*` as its subtype.
+fn mime_matches(pattern: &str, mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or("").trim();
+
+    match pattern.split_once('/') {
+        Some((ty, "*")) => {
+            ty == "*"
+                || essence
+                    .split('/')
+                    .next()
+                    .is_some_and(|essence_ty| essence_ty.eq_ignore_ascii_case(ty))
+        }
+        _ => pattern.eq_ignore_ascii_case(essence),
     }
 }
 
@@ -118,15 +747,181 @@ fn default_exclude_patterns() -> Vec<String> {
         "^media-server-1$".to_owned(),
         "\\.jar$".to_owned(),
         "\\.json$".to_owned(),
+        "\\.nfo$".to_owned(),
         "\\.toml$".to_owned(),
         "\\.ya?ml$".to_owned(),
     ]
 }
 
+fn default_cache_dir() -> String {
+    "cache".to_string()
+}
+
+fn default_cache_max_size() -> u64 {
+    10 * 1024
+}
+
 fn default_bindings() -> Vec<String> {
     vec!["127.0.0.1:9090".to_owned()]
 }
 
+fn default_signing_url_lifetime() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_thumbnail_seek_percent() -> f64 {
+    10.0
+}
+
+fn default_thumbnail_sizes() -> Vec<u32> {
+    vec![160, DEFAULT_THUMBNAIL_SIZE, 640]
+}
+
+fn default_image_thumbnail_size() -> u32 {
+    DEFAULT_THUMBNAIL_SIZE
+}
+
+fn default_image_screen_size() -> u32 {
+    1920
+}
+
+fn default_image_sizes() -> Vec<u32> {
+    vec![160, 320, 640, 960, 1280, 1920, 2560, 3840]
+}
+
+fn default_image_max_size() -> u32 {
+    4096
+}
+
+fn default_sprite_interval() -> u32 {
+    10
+}
+
+fn default_sprite_tile_width() -> u32 {
+    160
+}
+
+fn default_sprite_grid_size() -> u32 {
+    10
+}
+
+fn default_hls_segment_length() -> u32 {
+    6
+}
+
+fn default_hls_idle_timeout() -> u64 {
+    60
+}
+
+fn default_hls_renditions() -> Vec<HlsRendition> {
+    vec![
+        HlsRendition {
+            name: "1080p".to_string(),
+            height: 1080,
+            video_bitrate: 6000,
+            audio_bitrate: 192,
+        },
+        HlsRendition {
+            name: "720p".to_string(),
+            height: 720,
+            video_bitrate: 3000,
+            audio_bitrate: 128,
+        },
+        HlsRendition {
+            name: "480p".to_string(),
+            height: 480,
+            video_bitrate: 1200,
+            audio_bitrate: 96,
+        },
+    ]
+}
+
+fn default_jobs_max_concurrent() -> usize {
+    4
+}
+
+/// EBU R128's target level.
+fn default_loudness_target() -> f64 {
+    -23.0
+}
+
+fn default_loudness_max_true_peak() -> f64 {
+    -1.0
+}
+
+fn default_loudness_max_gain() -> f64 {
+    12.0
+}
+
+fn default_optimize_profile() -> String {
+    "generic".to_string()
+}
+
+fn default_optimize_max_height() -> u32 {
+    1080
+}
+
+fn default_optimize_video_bitrate() -> u32 {
+    5000
+}
+
+fn default_optimize_audio_bitrate() -> u32 {
+    192
+}
+
+fn default_profiles() -> Vec<DeviceProfile> {
+    let strings = |values: &[&str]| values.iter().map(|value| value.to_string()).collect();
+
+    vec![
+        DeviceProfile {
+            name: "chrome".to_string(),
+            user_agent: Some("Chrome/|Chromium/|Edg/".to_string()),
+            containers: strings(&["mp4", "m4v", "webm"]),
+            video_codecs: strings(&["h264", "vp8", "vp9", "av1"]),
+            audio_codecs: strings(&["aac", "mp3", "opus", "vorbis", "flac"]),
+            max_height: None,
+            max_bitrate: None,
+        },
+        DeviceProfile {
+            name: "firefox".to_string(),
+            user_agent: Some("Firefox/".to_string()),
+            containers: strings(&["mp4", "m4v", "webm"]),
+            video_codecs: strings(&["h264", "vp8", "vp9", "av1"]),
+            audio_codecs: strings(&["aac", "mp3", "opus", "vorbis", "flac"]),
+            max_height: None,
+            max_bitrate: None,
+        },
+        DeviceProfile {
+            name: "safari".to_string(),
+            // Chrome claims to be Safari too, so this has to come after it
+            user_agent: Some("Safari/".to_string()),
+            containers: strings(&["mp4", "m4v", "mov"]),
+            video_codecs: strings(&["h264", "hevc"]),
+            audio_codecs: strings(&["aac", "mp3", "alac", "flac"]),
+            max_height: None,
+            max_bitrate: None,
+        },
+        generic_profile(),
+    ]
+}
+
+/// Plain H.264 and AAC in MP4 is about the only thing every client plays.
+fn generic_profile() -> DeviceProfile {
+    DeviceProfile {
+        name: "generic".to_string(),
+        user_agent: None,
+        containers: vec!["mp4".to_string(), "m4v".to_string()],
+        video_codecs: vec!["h264".to_string()],
+        audio_codecs: vec!["aac".to_string(), "mp3".to_string()],
+        max_height: Some(1080),
+        max_bitrate: None,
+    }
+}
+
 fn default_welcome_title() -> String {
     "Media Server 1".to_string()
 }
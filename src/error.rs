@@ -5,14 +5,35 @@ use std::borrow::Cow;
 
 error_chain! {
     errors {
+        CacheError(msg: Cow<'static, str>) {
+            display("Error in cache: {}", msg)
+        }
         ConfigLoadError(msg: Cow<'static, str>) {
             display("Error loading config: {}", msg)
         }
+        FeatureUnavailableError(feature: &'static str) {
+            display("Feature unavailable: {}", feature)
+        }
         FilesIndexUnknownError(msg: Cow<'static, str>) {
             display("Error during index lookup: {}", msg)
         }
         FilesLimiterError {}
+        ForbiddenError {}
         InvalidMethodError {}
+        InvalidSignatureError(msg: Cow<'static, str>) {
+            display("Invalid signature: {}", msg)
+        }
+        JobCancelledError {}
+        MediaProcessingError(msg: Cow<'static, str>) {
+            display("Error processing media: {}", msg)
+        }
+        MediaProbeError(msg: Cow<'static, str>) {
+            display("Error probing media: {}", msg)
+        }
+        MetadataLoadError(msg: Cow<'static, str>) {
+            display("Error loading metadata: {}", msg)
+        }
+        NotFoundError {}
         UriSegmentError {}
     }
 }
@@ -20,8 +41,14 @@ error_chain! {
 impl ResponseError for Error {
     fn status_code(&self) -> StatusCode {
         match self.0 {
+            ErrorKind::FeatureUnavailableError(_) => StatusCode::NOT_IMPLEMENTED,
             ErrorKind::FilesLimiterError => StatusCode::NOT_FOUND,
+            ErrorKind::ForbiddenError => StatusCode::FORBIDDEN,
             ErrorKind::InvalidMethodError => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorKind::InvalidSignatureError(_) => StatusCode::FORBIDDEN,
+            ErrorKind::JobCancelledError => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::MediaProbeError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorKind::NotFoundError => StatusCode::NOT_FOUND,
             ErrorKind::UriSegmentError => StatusCode::BAD_REQUEST,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -39,8 +66,22 @@ impl ResponseError for Error {
 impl Error {
     fn handle(&self) -> Option<JsonError> {
         match self.0 {
+            ErrorKind::FeatureUnavailableError(feature) => {
+                Some(JsonError::FeatureUnavailableError { feature })
+            }
             ErrorKind::FilesLimiterError => None,
+            ErrorKind::ForbiddenError => Some(JsonError::ForbiddenError),
             ErrorKind::InvalidMethodError => Some(JsonError::InvalidMethodError),
+            ErrorKind::InvalidSignatureError(_) => {
+                debug!("{}", self.display_chain());
+                Some(JsonError::InvalidSignatureError)
+            }
+            ErrorKind::JobCancelledError => Some(JsonError::JobCancelledError),
+            ErrorKind::MediaProbeError(_) => {
+                debug!("{}", self.display_chain());
+                Some(JsonError::MediaProbeError)
+            }
+            ErrorKind::NotFoundError => Some(JsonError::NotFoundError),
             ErrorKind::UriSegmentError => None,
             _ => {
                 self.log();
@@ -54,9 +95,16 @@ impl Error {
     }
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
 pub enum JsonError {
+    FeatureUnavailableError { feature: &'static str },
+    ForbiddenError,
     InternalServerError,
     InvalidMethodError,
+    InvalidSignatureError,
+    JobCancelledError,
+    MediaProbeError,
+    NotFoundError,
 }
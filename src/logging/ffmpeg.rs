@@ -1,11 +1,57 @@
+use crate::media::jobs::{current_job, JobId};
 use ffmpeg4_sys::{AVClass, __va_list_tag};
+use log::Level;
 use std::{
+    collections::HashMap,
     ffi::CStr,
     mem::transmute,
     ops::Shr,
     os::raw::{c_char, c_int, c_void},
+    sync::{Mutex, Once},
+    thread,
+    time::{Duration, Instant},
 };
 
+/// Each log target may log this many messages per window, further ones are
+/// counted and reported once the window is over.
+const RATE_LIMIT_MESSAGES: usize = 50;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+/// How long after its last message a source's pending counts are reported
+/// even if it logs nothing else, like when its job has ended.
+const FLUSH_DELAY: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref LOG_SOURCES: Mutex<HashMap<SourceKey, LogSource>> = Mutex::new(HashMap::new());
+}
+
+static START_FLUSHER: Once = Once::new();
+
+/// Messages are collapsed and rate limited per target and job, so concurrent
+/// jobs using the same component do not share counts.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+struct SourceKey {
+    target: String,
+    job: Option<JobId>,
+}
+
+/// What was last logged to a target, for collapsing repeats and rate
+/// limiting.
+struct LogSource {
+    last: Option<(Level, String)>,
+    last_prefix: String,
+    last_time: Instant,
+    repeated: usize,
+    window_start: Instant,
+    window_count: usize,
+    suppressed: usize,
+}
+
+struct LogLine {
+    target: String,
+    level: Level,
+    text: String,
+}
+
 pub extern "C" fn log_callback(
     ptr: *mut c_void,
     level: c_int,
@@ -42,48 +88,204 @@ pub extern "C" fn log_callback(
     let (level, level_str) = match level {
         0 => {
             // panic level
-            (log::Level::Error, "PANIC")
+            (Level::Error, "PANIC")
         }
         1 => {
             // fatal level
-            (log::Level::Error, "FATAL")
+            (Level::Error, "FATAL")
         }
         2 => {
             // error level
-            (log::Level::Error, "ERROR")
+            (Level::Error, "ERROR")
         }
         3 => {
             // warning level
-            (log::Level::Warn, "WARN")
+            (Level::Warn, "WARN")
         }
         4 => {
             // info level
-            (log::Level::Info, "INFO")
+            (Level::Info, "INFO")
         }
         5 => {
             // verbose level
-            (log::Level::Debug, "VERB")
+            (Level::Debug, "VERB")
         }
         6 => {
             // debug level
-            (log::Level::Debug, "DEBUG")
+            (Level::Debug, "DEBUG")
         }
         7 => {
             // trace level
-            (log::Level::Trace, "TRACE")
+            (Level::Trace, "TRACE")
         }
         _ => unreachable!("Bad log level from ffmpeg"),
     };
 
-    if let Some(item_name) = item_name {
-        log!(
-            level,
-            "[ffmpeg:{:>5}:{}] {}",
-            level_str,
-            &item_name,
-            res.trim()
-        );
+    // Work run as a job is tagged with its id, so the lines of concurrent jobs
+    // can be told apart.
+    let job_id = current_job();
+    let job = job_tag(job_id);
+
+    // Every ffmpeg component gets its own log target, so its level can be set
+    // separately.
+    let (target, prefix) = match item_name {
+        Some(item_name) => (
+            format!("ffmpeg::{}", item_name),
+            format!("[ffmpeg:{:>5}:{}] {}", level_str, item_name, job),
+        ),
+        None => (
+            "ffmpeg".to_string(),
+            format!("[ffmpeg:{:>5}] {}", level_str, job),
+        ),
+    };
+    let message = res.trim();
+    if message.is_empty() || !log_enabled!(target: &target, level) {
+        return;
+    }
+
+    START_FLUSHER.call_once(|| {
+        thread::spawn(|| loop {
+            thread::sleep(FLUSH_DELAY);
+            write_lines(flush_idle_sources());
+        });
+    });
+
+    let key = SourceKey {
+        target,
+        job: job_id,
+    };
+    write_lines(filter_message(&key, level, &prefix, message));
+}
+
+fn write_lines(lines: Vec<LogLine>) {
+    for line in lines {
+        log!(target: &line.target, line.level, "{}", line.text);
+    }
+}
+
+fn job_tag(job: Option<JobId>) -> String {
+    job.map(|id| format!("[job:{}] ", id)).unwrap_or_default()
+}
+
+/// Collapses repeated messages and rate limits each log target, returning the
+/// lines that should actually be logged.
+fn filter_message(key: &SourceKey, level: Level, prefix: &str, message: &str) -> Vec<LogLine> {
+    let now = Instant::now();
+    let mut sources = LOG_SOURCES.lock().unwrap();
+    let mut lines = vec![];
+
+    let source = sources
+        .entry(key.clone())
+        .or_insert_with(|| LogSource::new(now));
+
+    if source
+        .last
+        .as_ref()
+        .is_some_and(|(last_level, last_message)| *last_level == level && last_message == message)
+    {
+        source.repeated += 1;
+        source.last_time = now;
+        return lines;
+    }
+    lines.extend(source.take_repeats(&key.target));
+
+    if now.duration_since(source.window_start) >= RATE_LIMIT_WINDOW {
+        lines.extend(source.take_suppressed(key));
+        source.window_start = now;
+        source.window_count = 0;
+    }
+
+    source.last_time = now;
+
+    if source.window_count >= RATE_LIMIT_MESSAGES {
+        // repeats of a suppressed message are suppressed along with it
+        source.last = None;
+        source.suppressed += 1;
     } else {
-        log!(level, "[ffmpeg:{:>5}] {}", level_str, res.trim());
+        source.last = Some((level, message.to_string()));
+        source.last_prefix = prefix.to_string();
+        source.window_count += 1;
+        lines.push(LogLine {
+            target: key.target.clone(),
+            level,
+            text: format!("{}{}", prefix, message),
+        });
+    }
+
+    lines
+}
+
+/// Reports the pending counts of sources that have gone quiet and forgets
+/// them, as their job may well be over.
+fn flush_idle_sources() -> Vec<LogLine> {
+    let now = Instant::now();
+    let mut sources = LOG_SOURCES.lock().unwrap();
+    let mut lines = vec![];
+
+    sources.retain(|key, source| {
+        if now.duration_since(source.last_time) < FLUSH_DELAY {
+            return true;
+        }
+
+        lines.extend(source.take_repeats(&key.target));
+        lines.extend(source.take_suppressed(key));
+        false
+    });
+
+    lines
+}
+
+impl LogSource {
+    fn new(now: Instant) -> LogSource {
+        LogSource {
+            last: None,
+            last_prefix: String::new(),
+            last_time: now,
+            repeated: 0,
+            window_start: now,
+            window_count: 0,
+            suppressed: 0,
+        }
+    }
+
+    /// Reports how often the last message was repeated, if it was.
+    fn take_repeats(&mut self, target: &str) -> Option<LogLine> {
+        let (level, _) = self.last.as_ref()?;
+        if self.repeated == 0 {
+            return None;
+        }
+
+        let line = LogLine {
+            target: target.to_string(),
+            level: *level,
+            text: format!(
+                "{}Last message repeated {} times",
+                self.last_prefix, self.repeated
+            ),
+        };
+        self.repeated = 0;
+
+        Some(line)
+    }
+
+    /// Reports how many messages were suppressed by rate limiting, if any.
+    fn take_suppressed(&mut self, key: &SourceKey) -> Option<LogLine> {
+        if self.suppressed == 0 {
+            return None;
+        }
+
+        let line = LogLine {
+            target: key.target.clone(),
+            level: Level::Warn,
+            text: format!(
+                "[ffmpeg] {}{} messages from {} were suppressed",
+                job_tag(key.job),
+                self.suppressed,
+                key.target
+            ),
+        };
+        self.suppressed = 0;
+
+        Some(line)
     }
 }
@@ -14,6 +14,8 @@ mod cdn;
 mod config;
 mod error;
 mod logging;
+mod media;
+mod metadata;
 mod util;
 
 use crate::{
@@ -21,7 +23,7 @@ use crate::{
     error::{Result, ResultExt},
 };
 use actix_web::{middleware::DefaultHeaders, web::Data, App, HttpServer};
-use std::process::exit;
+use std::{env, process::exit};
 
 mod frontend {
     include!(concat!(env!("OUT_DIR"), "/generated.rs"));
@@ -29,9 +31,20 @@ mod frontend {
 
 async fn run() -> Result<()> {
     let config = Config::load()?;
+    media::cache::init(&config)?;
 
     #[cfg(feature = "ffmpeg")]
     util::ffmpeg::init_ffmpeg()?;
+    media::jobs::set_max_concurrent(config.jobs.max_concurrent);
+    media::optimize::resume(&config);
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Some(command) = args.first() {
+        return run_command(&config, command, &args[1..]);
+    }
+
+    media::loudness::start_analysis(&config);
+    media::music::start_scan(&config);
 
     let server_config = config.clone();
     let server_config_data = Data::new(config.clone());
@@ -71,6 +84,25 @@ async fn run() -> Result<()> {
         .chain_err(|| "Error starting the actix server")
 }
 
+/// Runs a command given on the command line instead of the server.
+fn run_command(config: &Config, command: &str, args: &[String]) -> Result<()> {
+    match command {
+        // optimizes a directory relative to base_dir, or the whole library
+        "optimize" => {
+            let path = args.first().map_or("", String::as_str);
+            let queued = media::optimize::queue(config, path)?;
+            info!("Queued {} files for optimizing", queued);
+            media::optimize::wait(config);
+
+            Ok(())
+        }
+        _ => bail!(format!(
+            "Unknown command {:?}, the only command is \"optimize\"",
+            command
+        )),
+    }
+}
+
 #[actix_web::main]
 async fn main() {
     dotenv::dotenv().ok();
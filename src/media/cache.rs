@@ -0,0 +1,490 @@
+use crate::{
+    config::Config,
+    error::{ErrorKind::CacheError, Result, ResultExt},
+    util::hash::Fnv1a,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions, TryLockError},
+    hash::Hasher,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, Once,
+    },
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use walkdir::WalkDir;
+
+/// Lists every cached asset with its source, so it survives restarts.
+const MANIFEST_FILE_NAME: &str = "cache.json";
+/// Held locked by the process using the cache, as two processes would delete
+/// each other's assets and overwrite each other's manifest.
+const LOCK_FILE_NAME: &str = "cache.lock";
+const MANIFEST_FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+const BYTES_PER_MEGABYTE: u64 = 1024 * 1024;
+
+lazy_static! {
+    static ref CACHE: Mutex<CacheIndex> = Mutex::new(CacheIndex::default());
+    static ref LOCK_FILE: Mutex<Option<File>> = Mutex::new(None);
+}
+
+static START_FLUSHER: Once = Once::new();
+static NEXT_TEMP_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The kinds of derived assets, each kept in its own directory.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum AssetType {
+    Covers,
+    Images,
+    Loudness,
+    Optimized,
+    Probes,
+    Segments,
+    Sprites,
+    Subtitles,
+    Thumbnails,
+}
+
+/// A cached file or directory and the source file it was derived from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    asset: AssetType,
+    source: PathBuf,
+    /// The source's modification time when the entry was created.
+    modified: SystemTime,
+    size: u64,
+    accessed: SystemTime,
+}
+
+#[derive(Debug, Default)]
+struct CacheIndex {
+    dir: PathBuf,
+    max_size: u64,
+    entries: HashMap<PathBuf, CacheEntry>,
+    size: u64,
+    /// Whether the manifest is behind the entries.
+    dirty: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CacheStats {
+    pub max_size: u64,
+    pub size: u64,
+    pub assets: BTreeMap<AssetType, AssetStats>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct AssetStats {
+    pub entries: usize,
+    pub size: u64,
+}
+
+impl AssetType {
+    pub const ALL: [AssetType; 9] = [
+        AssetType::Covers,
+        AssetType::Images,
+        AssetType::Loudness,
+        AssetType::Optimized,
+        AssetType::Probes,
+        AssetType::Segments,
+        AssetType::Sprites,
+        AssetType::Subtitles,
+        AssetType::Thumbnails,
+    ];
+
+    /// Whether assets of this type count against the quota and may be
+    /// evicted. Optimized files take hours to make, so they are only deleted
+    /// once their source changes or goes away.
+    fn is_evictable(&self) -> bool {
+        *self != AssetType::Optimized
+    }
+
+    /// The directory this type of asset is kept in.
+    pub fn dir(&self, config: &Config) -> PathBuf {
+        config.cache_dir.join(self.dir_name())
+    }
+
+    fn dir_name(&self) -> &'static str {
+        match self {
+            AssetType::Covers => "covers",
+            AssetType::Images => "images",
+            AssetType::Loudness => "loudness",
+            AssetType::Optimized => "optimized",
+            AssetType::Probes => "probes",
+            AssetType::Segments => "hls",
+            AssetType::Sprites => "sprites",
+            AssetType::Subtitles => "subtitles",
+            AssetType::Thumbnails => "thumbnails",
+        }
+    }
+}
+
+/// Locks the cache directory for this process, failing if another one, like
+/// a running server, already has.
+fn lock(config: &Config) -> Result<()> {
+    let lock_path = config.cache_dir.join(LOCK_FILE_NAME);
+    let file = fs::create_dir_all(&config.cache_dir)
+        .and_then(|_| {
+            OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&lock_path)
+        })
+        .chain_err(|| CacheError(format!("Error opening {:?}", lock_path).into()))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => bail!(CacheError(
+            format!(
+                "{:?} is in use by another process, stop it first or use its API",
+                config.cache_dir
+            )
+            .into()
+        )),
+        Err(TryLockError::Error(e)) => {
+            return Err(e).chain_err(|| CacheError(format!("Error locking {:?}", lock_path).into()))
+        }
+    }
+    *LOCK_FILE.lock().unwrap() = Some(file);
+
+    Ok(())
+}
+
+/// Hashes a file's path and modification time into a cache key. Keys stay
+/// the same across restarts and upgrades, as they name files and urls.
+pub fn cache_key(path: &Path, modified: SystemTime) -> u64 {
+    let nanos = match modified.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_nanos() as i128,
+        Err(e) => -(e.duration().as_nanos() as i128),
+    };
+
+    let mut hasher = Fnv1a::default();
+    hasher.write(path.to_string_lossy().as_bytes());
+    hasher.write(&nanos.to_le_bytes());
+    hasher.finish()
+}
+
+/// Loads the cache manifest and cleans up whatever a previous run left
+/// behind: temporary files, HLS segments, assets that are not in the manifest
+/// and assets whose source has changed or is gone. Then evicts down to the
+/// quota. Only one process at a time can use the cache.
+pub fn init(config: &Config) -> Result<()> {
+    lock(config)?;
+
+    let manifest_path = config.cache_dir.join(MANIFEST_FILE_NAME);
+    let manifest: HashMap<PathBuf, CacheEntry> = fs::read(&manifest_path)
+        .ok()
+        .and_then(|bytes| match serde_json::from_slice(&bytes) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                warn!("Ignoring unreadable cache manifest: {}", e);
+                None
+            }
+        })
+        .unwrap_or_default();
+
+    let mut index = CACHE.lock().unwrap();
+    index.dir = config.cache_dir.clone();
+    index.max_size = config.cache.max_size * BYTES_PER_MEGABYTE;
+
+    // segments belong to HLS sessions, which do not survive a restart
+    let segment_dir = AssetType::Segments.dir(config);
+    if segment_dir.exists() {
+        fs::remove_dir_all(&segment_dir)
+            .chain_err(|| CacheError(format!("Error deleting {:?}", segment_dir).into()))?;
+    }
+
+    for (relative_path, entry) in manifest {
+        let path = config.cache_dir.join(&relative_path);
+        let source_modified = entry
+            .source
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok();
+
+        if !path.exists() || entry.asset == AssetType::Segments {
+            continue;
+        }
+        if source_modified != Some(entry.modified) {
+            debug!("Deleting stale cache entry {:?}", relative_path);
+            delete(&path);
+            continue;
+        }
+
+        index.size += entry.size;
+        index.entries.insert(relative_path, entry);
+    }
+
+    for asset in AssetType::ALL
+        .iter()
+        .filter(|asset| **asset != AssetType::Segments)
+    {
+        let dir = asset.dir(config);
+        let children = match fs::read_dir(&dir) {
+            Ok(children) => children,
+            Err(_) => continue,
+        };
+
+        for child in children.filter_map(|child| child.ok()) {
+            let relative_path = Path::new(asset.dir_name()).join(child.file_name());
+            if !index.entries.contains_key(&relative_path) {
+                debug!("Deleting orphaned cache entry {:?}", relative_path);
+                delete(&child.path());
+            }
+        }
+    }
+
+    index.evict(None);
+    index.dirty = true;
+    let size = index.size;
+    drop(index);
+
+    flush();
+    START_FLUSHER.call_once(|| {
+        thread::spawn(|| loop {
+            thread::sleep(MANIFEST_FLUSH_INTERVAL);
+            flush();
+        });
+    });
+
+    info!(
+        "Cache in {:?} uses {} of {} MB",
+        config.cache_dir,
+        size / BYTES_PER_MEGABYTE,
+        config.cache.max_size
+    );
+
+    Ok(())
+}
+
+/// Checks whether an asset is cached, marking it as recently used if it is.
+pub fn touch(path: &Path) -> bool {
+    let mut index = CACHE.lock().unwrap();
+    let relative_path = index.relative_path(path);
+    if let Some(entry) = relative_path.and_then(|path| index.entries.get_mut(&path)) {
+        entry.accessed = SystemTime::now();
+        index.dirty = true;
+    }
+
+    path.exists()
+}
+
+/// Writes an asset to a temporary file and moves it into place, so readers
+/// never see it half written, and adds it to the cache.
+pub fn write(
+    asset: AssetType,
+    path: &Path,
+    source: &Path,
+    modified: SystemTime,
+    data: &[u8],
+) -> Result<()> {
+    let temp_path = temp_path(path);
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&temp_path, data))
+        .and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.chain_err(|| CacheError(format!("Error writing {:?}", path).into()))?;
+
+    insert(asset, path, source, modified);
+
+    Ok(())
+}
+
+/// A temporary file next to an asset to write it into. Every writer gets its
+/// own, as several requests may be producing the same asset at once.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map_or("".into(), |name| name.to_string_lossy());
+    path.with_file_name(format!(
+        "{}.{}-{}.tmp",
+        name,
+        process::id(),
+        NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Adds an asset that has already been written, a file or a whole directory,
+/// to the cache. Entries of the same source from before it was modified are
+/// deleted, and the least recently used ones are evicted if this goes over
+/// the quota.
+pub fn insert(asset: AssetType, path: &Path, source: &Path, modified: SystemTime) {
+    let size = WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum();
+
+    let mut index = CACHE.lock().unwrap();
+    let relative_path = match index.relative_path(path) {
+        Some(relative_path) => relative_path,
+        None => {
+            warn!("Not caching {:?}, it is outside of the cache", path);
+            return;
+        }
+    };
+
+    let stale: Vec<PathBuf> = index
+        .entries
+        .iter()
+        .filter(|(_, entry)| entry.source == source && entry.modified != modified)
+        .map(|(path, _)| path.clone())
+        .collect();
+    for stale_path in stale {
+        debug!("Deleting outdated cache entry {:?}", stale_path);
+        index.remove(&stale_path);
+    }
+
+    let now = SystemTime::now();
+    if let Some(previous) = index.entries.insert(
+        relative_path.clone(),
+        CacheEntry {
+            asset,
+            source: source.to_path_buf(),
+            modified,
+            size,
+            accessed: now,
+        },
+    ) {
+        index.size -= previous.size;
+    }
+    index.size += size;
+    index.dirty = true;
+
+    index.evict(Some(&relative_path));
+}
+
+/// Deletes a cached file or directory, along with the entries of everything in
+/// it.
+#[cfg(feature = "ffmpeg")]
+pub fn remove(path: &Path) {
+    let mut index = CACHE.lock().unwrap();
+    if let Some(relative_path) = index.relative_path(path) {
+        let contained: Vec<PathBuf> = index
+            .entries
+            .keys()
+            .filter(|entry_path| entry_path.starts_with(&relative_path))
+            .cloned()
+            .collect();
+        for entry_path in contained {
+            index.remove(&entry_path);
+        }
+    }
+
+    delete(path);
+}
+
+pub fn stats() -> CacheStats {
+    let index = CACHE.lock().unwrap();
+    let mut stats = CacheStats {
+        max_size: index.max_size,
+        size: index.size,
+        assets: AssetType::ALL
+            .iter()
+            .map(|asset| (*asset, AssetStats::default()))
+            .collect(),
+    };
+
+    for entry in index.entries.values() {
+        let asset = stats.assets.get_mut(&entry.asset).unwrap();
+        asset.entries += 1;
+        asset.size += entry.size;
+    }
+
+    stats
+}
+
+/// Writes the manifest if anything changed since it was last written.
+fn flush() {
+    let mut index = CACHE.lock().unwrap();
+    if !index.dirty {
+        return;
+    }
+
+    let manifest_path = index.dir.join(MANIFEST_FILE_NAME);
+    let temp_path = manifest_path.with_extension("tmp");
+    let result = serde_json::to_vec(&index.entries)
+        .chain_err(|| CacheError("Error encoding cache manifest".into()))
+        .and_then(|bytes| {
+            fs::create_dir_all(&index.dir)
+                .and_then(|_| fs::write(&temp_path, bytes))
+                .and_then(|_| fs::rename(&temp_path, &manifest_path))
+                .chain_err(|| CacheError("Error writing cache manifest".into()))
+        });
+
+    match result {
+        Ok(()) => index.dirty = false,
+        Err(e) => e.log(),
+    }
+}
+
+fn delete(path: &Path) {
+    let result = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    if let Err(e) = result {
+        if path.exists() {
+            warn!("Error deleting {:?} from the cache: {}", path, e);
+        }
+    }
+}
+
+impl CacheIndex {
+    fn relative_path(&self, path: &Path) -> Option<PathBuf> {
+        path.strip_prefix(&self.dir).ok().map(Path::to_path_buf)
+    }
+
+    fn remove(&mut self, relative_path: &Path) {
+        if let Some(entry) = self.entries.remove(relative_path) {
+            self.size -= entry.size;
+            self.dirty = true;
+            delete(&self.dir.join(relative_path));
+        }
+    }
+
+    /// Deletes the least recently used entries until the evictable ones fit
+    /// the quota. The entry that was just added is kept even if it alone is
+    /// over the quota.
+    fn evict(&mut self, keep: Option<&Path>) {
+        if self.size <= self.max_size {
+            return;
+        }
+
+        let mut size: u64 = self
+            .entries
+            .values()
+            .filter(|entry| entry.asset.is_evictable())
+            .map(|entry| entry.size)
+            .sum();
+        let mut candidates: Vec<(SystemTime, PathBuf, u64)> = self
+            .entries
+            .iter()
+            .filter(|(path, entry)| entry.asset.is_evictable() && Some(path.as_path()) != keep)
+            .map(|(path, entry)| (entry.accessed, path.clone(), entry.size))
+            .collect();
+        candidates.sort();
+
+        for (_, path, entry_size) in candidates {
+            if size <= self.max_size {
+                break;
+            }
+
+            debug!("Evicting {:?} from the cache", path);
+            self.remove(&path);
+            size -= entry_size;
+        }
+    }
+}
@@ -0,0 +1,208 @@
+#[cfg(feature = "ffmpeg")]
+use crate::media::probe::probe_cached;
+use crate::{
+    config::Config,
+    media::subtitles::decode_text,
+    util::vtt::{write_vtt, Cue},
+};
+use regex::Regex;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Sidecar chapter files looked for when a file has no embedded chapters,
+/// either named after the file or shared by its whole directory.
+const SIDECAR_SUFFIXES: &[&str] = &[".chapters.txt", ".ogm.txt"];
+const SIDECAR_DIR_FILE_NAME: &str = "chapters.txt";
+
+lazy_static! {
+    /// OGM chapter files list a timestamp and a name line per chapter, like
+    /// `CHAPTER01=00:01:30.000` and `CHAPTER01NAME=Intro`.
+    static ref OGM_PATTERN: Regex =
+        Regex::new(r#"^CHAPTER(\d+)(NAME)?=(.*)$"#).unwrap();
+    /// Plain chapter lists have a timestamp and a title per line, like
+    /// `01:30 Intro` or `1:02:03.5 - Finale`.
+    static ref SIMPLE_PATTERN: Regex =
+        Regex::new(r#"^((?:\d+:)?\d{1,2}:\d{1,2}(?:[.,]\d+)?)\s*(?:-\s+)?(.*)$"#).unwrap();
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Chapter {
+    pub title: String,
+    /// Start time in seconds.
+    pub start: f64,
+    /// End time in seconds.
+    pub end: f64,
+}
+
+/// Gets a file's chapters from its container, falling back to a sidecar
+/// chapter file when it has none. Files without chapters get an empty list.
+pub fn find_chapters(config: &Config, path: &Path) -> Vec<Chapter> {
+    let (embedded, duration) = probe_chapters(config, path);
+    if !embedded.is_empty() {
+        return embedded;
+    }
+
+    find_sidecar(path)
+        .map(|sidecar| read_sidecar(&sidecar, duration))
+        .unwrap_or_default()
+}
+
+/// Renders chapters as a WebVTT chapters track.
+pub fn chapters_vtt(chapters: &[Chapter]) -> String {
+    let cues: Vec<Cue> = chapters
+        .iter()
+        .map(|chapter| Cue {
+            start: chapter.start,
+            end: chapter.end,
+            text: chapter.title.clone(),
+        })
+        .collect();
+
+    write_vtt(&cues)
+}
+
+/// The embedded chapters and the duration of a file, which sidecar chapters
+/// need to know where the last one ends.
+#[cfg(feature = "ffmpeg")]
+fn probe_chapters(config: &Config, path: &Path) -> (Vec<Chapter>, Option<f64>) {
+    match probe_cached(config, path) {
+        Ok(info) => (
+            info.chapters
+                .iter()
+                .enumerate()
+                .map(|(index, chapter)| Chapter {
+                    title: chapter
+                        .title
+                        .clone()
+                        .filter(|title| !title.trim().is_empty())
+                        .unwrap_or_else(|| default_title(index)),
+                    start: chapter.start,
+                    end: chapter.end,
+                })
+                .collect(),
+            info.duration,
+        ),
+        Err(e) => {
+            debug!("Not reading embedded chapters of {:?}: {}", path, e);
+            (vec![], None)
+        }
+    }
+}
+
+#[cfg(not(feature = "ffmpeg"))]
+fn probe_chapters(_config: &Config, _path: &Path) -> (Vec<Chapter>, Option<f64>) {
+    (vec![], None)
+}
+
+fn find_sidecar(path: &Path) -> Option<PathBuf> {
+    let dir = path.parent()?;
+    let stem = path.file_stem()?.to_string_lossy();
+
+    SIDECAR_SUFFIXES
+        .iter()
+        .map(|suffix| dir.join(format!("{}{}", stem, suffix)))
+        .chain(Some(dir.join(SIDECAR_DIR_FILE_NAME)))
+        .find(|sidecar| sidecar.is_file())
+}
+
+/// Reads an OGM or plain chapter list. Each chapter ends where the next one
+/// starts, and the last one at the end of the file if its duration is known.
+fn read_sidecar(path: &Path, duration: Option<f64>) -> Vec<Chapter> {
+    let text = match fs::read(path) {
+        Ok(bytes) => decode_text(&bytes),
+        Err(e) => {
+            warn!("Error reading chapters from {:?}: {}", path, e);
+            return vec![];
+        }
+    };
+
+    let mut starts: Vec<(f64, Option<String>)> =
+        if text.lines().any(|line| OGM_PATTERN.is_match(line.trim())) {
+            parse_ogm(&text)
+        } else {
+            parse_simple(&text)
+        };
+    starts.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+    let ends: Vec<f64> = starts
+        .iter()
+        .skip(1)
+        .map(|(start, _)| *start)
+        .chain(Some(duration.unwrap_or_else(|| {
+            starts.last().map_or(0.0, |(start, _)| *start)
+        })))
+        .collect();
+
+    starts
+        .into_iter()
+        .zip(ends)
+        .enumerate()
+        .map(|(index, ((start, title), end))| Chapter {
+            title: title.unwrap_or_else(|| default_title(index)),
+            start,
+            end: end.max(start),
+        })
+        .collect()
+}
+
+fn parse_ogm(text: &str) -> Vec<(f64, Option<String>)> {
+    // chapters by number, as the name lines may come in any order
+    let mut chapters: Vec<(String, Option<f64>, Option<String>)> = vec![];
+
+    for line in text.lines() {
+        let captures = match OGM_PATTERN.captures(line.trim()) {
+            Some(captures) => captures,
+            None => continue,
+        };
+        let number = captures[1].to_string();
+        let value = captures[3].trim();
+
+        let index = match chapters.iter().position(|(n, _, _)| *n == number) {
+            Some(index) => index,
+            None => {
+                chapters.push((number, None, None));
+                chapters.len() - 1
+            }
+        };
+        if captures.get(2).is_some() {
+            chapters[index].2 = Some(value.to_string()).filter(|name| !name.is_empty());
+        } else {
+            chapters[index].1 = parse_timestamp(value);
+        }
+    }
+
+    chapters
+        .into_iter()
+        .filter_map(|(_, start, title)| Some((start?, title)))
+        .collect()
+}
+
+fn parse_simple(text: &str) -> Vec<(f64, Option<String>)> {
+    text.lines()
+        .filter_map(|line| SIMPLE_PATTERN.captures(line.trim()))
+        .filter_map(|captures| {
+            let start = parse_timestamp(&captures[1])?;
+            let title = captures[2].trim().to_string();
+            Some((start, Some(title).filter(|title| !title.is_empty())))
+        })
+        .collect()
+}
+
+/// Parses `[hh:]mm:ss[.fff]` into seconds.
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    timestamp
+        .replace(',', ".")
+        .split(':')
+        .try_fold(0.0, |total, part| {
+            part.trim()
+                .parse::<f64>()
+                .ok()
+                .map(|part| total * 60.0 + part)
+        })
+}
+
+fn default_title(index: usize) -> String {
+    format!("Chapter {}", index + 1)
+}
@@ -0,0 +1,236 @@
+use crate::{
+    config::ThumbnailFormat,
+    error::{
+        ErrorKind::{MediaProbeError, MediaProcessingError},
+        Result, ResultExt,
+    },
+};
+use ffmpeg4::{
+    codec, decoder, encoder, format, format::Pixel, frame, media, software::scaling, Packet,
+    Rational,
+};
+use ffmpeg4_sys::{AV_CODEC_FLAG_QSCALE, AV_TIME_BASE, FF_QP2LAMBDA};
+use std::path::Path;
+
+/// The JPEG quality scale used for encoded images (2 is best, 31 is worst).
+const JPEG_QSCALE: i32 = 3;
+/// The size frames are shrunk to before measuring their brightness.
+const LUMA_SAMPLE_SIZE: u32 = 16;
+
+/// Decodes individual frames out of a file's best video stream.
+pub struct VideoFrameReader {
+    input: format::context::Input,
+    decoder: decoder::Video,
+    stream_index: usize,
+    time_base: Rational,
+    duration: Option<f64>,
+}
+
+impl VideoFrameReader {
+    pub fn open(path: &Path) -> Result<VideoFrameReader> {
+        let input = format::input(&path)
+            .chain_err(|| MediaProbeError(format!("Error opening {:?}", path).into()))?;
+
+        let (stream_index, time_base, decoder) = {
+            let stream = input
+                .streams()
+                .best(media::Type::Video)
+                .ok_or_else(|| MediaProbeError(format!("No video stream in {:?}", path).into()))?;
+            let decoder = codec::context::Context::from_parameters(stream.parameters())
+                .and_then(|context| context.decoder().video())
+                .chain_err(|| MediaProbeError(format!("No video decoder for {:?}", path).into()))?;
+
+            (stream.index(), stream.time_base(), decoder)
+        };
+
+        let duration = Some(input.duration())
+            .filter(|duration| *duration > 0)
+            .map(|duration| duration as f64 / AV_TIME_BASE as f64);
+
+        Ok(VideoFrameReader {
+            input,
+            decoder,
+            stream_index,
+            time_base,
+            duration,
+        })
+    }
+
+    /// The duration of the file in seconds, if known.
+    pub fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+
+    /// The size frames should be displayed at, taking the sample aspect ratio
+    /// into account.
+    pub fn display_size(&self) -> (u32, u32) {
+        let (width, height) = (self.decoder.width(), self.decoder.height());
+        let aspect_ratio = self.decoder.aspect_ratio();
+
+        if aspect_ratio.numerator() > 0 && aspect_ratio.denominator() > 0 {
+            (
+                (width as f64 * f64::from(aspect_ratio)).round() as u32,
+                height,
+            )
+        } else {
+            (width, height)
+        }
+    }
+
+    /// Decodes a frame near `seconds`. When `accurate` is false, this is the
+    /// keyframe before `seconds`, which is much faster to get to. Otherwise
+    /// this is the first frame at or after `seconds`.
+    pub fn frame_at(&mut self, seconds: f64, accurate: bool) -> Result<frame::Video> {
+        let target = (seconds * AV_TIME_BASE as f64) as i64;
+        if target > 0 {
+            self.input.seek(target, ..target).chain_err(|| {
+                MediaProcessingError(format!("Error seeking to {}s", seconds).into())
+            })?;
+        }
+        self.decoder.flush();
+
+        let target_pts = (seconds / f64::from(self.time_base)) as i64;
+        let mut frame = frame::Video::empty();
+
+        for (stream, packet) in self.input.packets() {
+            if stream.index() != self.stream_index {
+                continue;
+            }
+
+            if self.decoder.send_packet(&packet).is_err() {
+                // damaged packets are skipped, the decoder will have logged the details
+                continue;
+            }
+
+            while self.decoder.receive_frame(&mut frame).is_ok() {
+                if !accurate || frame.timestamp().is_none_or(|pts| pts >= target_pts) {
+                    return Ok(frame);
+                }
+            }
+        }
+
+        self.decoder
+            .send_eof()
+            .chain_err(|| MediaProcessingError("Error flushing decoder".into()))?;
+        if self.decoder.receive_frame(&mut frame).is_ok() {
+            return Ok(frame);
+        }
+
+        bail!(MediaProcessingError(
+            format!("No frame could be decoded near {}s", seconds).into()
+        ))
+    }
+}
+
+/// Scales a frame and converts it into the given pixel format.
+pub fn scale(frame: &frame::Video, width: u32, height: u32, format: Pixel) -> Result<frame::Video> {
+    let mut scaler = scaling::Context::get(
+        frame.format(),
+        frame.width(),
+        frame.height(),
+        format,
+        width,
+        height,
+        scaling::Flags::BICUBIC,
+    )
+    .chain_err(|| MediaProcessingError("Error creating scaler".into()))?;
+
+    let mut scaled = frame::Video::empty();
+    scaler
+        .run(frame, &mut scaled)
+        .chain_err(|| MediaProcessingError("Error scaling frame".into()))?;
+
+    Ok(scaled)
+}
+
+/// Measures the average brightness of a frame from 0 (black) to 255 (white).
+pub fn average_luma(frame: &frame::Video) -> Result<f64> {
+    let gray = scale(frame, LUMA_SAMPLE_SIZE, LUMA_SAMPLE_SIZE, Pixel::GRAY8)?;
+    let data = gray.data(0);
+    let stride = gray.stride(0);
+
+    let total: u64 = (0..LUMA_SAMPLE_SIZE as usize)
+        .flat_map(|row| &data[row * stride..row * stride + LUMA_SAMPLE_SIZE as usize])
+        .map(|luma| *luma as u64)
+        .sum();
+
+    Ok(total as f64 / (LUMA_SAMPLE_SIZE * LUMA_SAMPLE_SIZE) as f64)
+}
+
+/// Scales a frame and encodes it as a single image.
+pub fn encode_image(
+    frame: &frame::Video,
+    width: u32,
+    height: u32,
+    format: ThumbnailFormat,
+) -> Result<Vec<u8>> {
+    let (codec_id, pixel) = match format {
+        ThumbnailFormat::Jpeg => (codec::Id::MJPEG, Pixel::YUVJ420P),
+        ThumbnailFormat::Webp => (codec::Id::WEBP, Pixel::YUV420P),
+    };
+    let codec = encoder::find(codec_id)
+        .ok_or_else(|| MediaProcessingError(format!("No {:?} encoder", format).into()))?;
+
+    let mut scaled = scale(frame, width, height, pixel)?;
+    scaled.set_pts(Some(0));
+
+    let mut context = codec::context::Context::new()
+        .encoder()
+        .video()
+        .chain_err(|| MediaProcessingError("Error creating image encoder".into()))?;
+    context.set_width(width);
+    context.set_height(height);
+    context.set_format(pixel);
+    context.set_time_base((1, 1));
+    if format == ThumbnailFormat::Jpeg {
+        // The mjpeg encoder's default quality is pretty poor, so a fixed quality
+        // scale is used instead.
+        unsafe {
+            let raw = context.as_mut_ptr();
+            (*raw).flags |= AV_CODEC_FLAG_QSCALE as i32;
+            (*raw).global_quality = JPEG_QSCALE * FF_QP2LAMBDA as i32;
+        }
+    }
+
+    let mut encoder = context
+        .open_as(codec)
+        .chain_err(|| MediaProcessingError("Error opening image encoder".into()))?;
+    encoder
+        .send_frame(&scaled)
+        .and_then(|_| encoder.send_eof())
+        .chain_err(|| MediaProcessingError("Error encoding image".into()))?;
+
+    let mut packet = Packet::empty();
+    encoder
+        .receive_packet(&mut packet)
+        .chain_err(|| MediaProcessingError("Error receiving encoded image".into()))?;
+
+    packet
+        .data()
+        .map(|data| data.to_vec())
+        .ok_or_else(|| MediaProcessingError("Encoded image is empty".into()).into())
+}
+
+/// Fits a size into a bounding box, keeping the aspect ratio. Nothing is
+/// scaled up, and both dimensions are even, which the yuv420 formats require.
+pub fn fit_within(size: (u32, u32), max_width: u32, max_height: u32) -> (u32, u32) {
+    let (width, height) = size;
+    let scale = (max_width as f64 / width.max(1) as f64)
+        .min(max_height as f64 / height.max(1) as f64)
+        .min(1.0);
+    let width = (width as f64 * scale).round() as u32;
+    let height = (height as f64 * scale).round() as u32;
+
+    ((width & !1).max(2), (height & !1).max(2))
+}
+
+/// Fits a display size into the given width, keeping the aspect ratio and
+/// making sure both dimensions are even, which the yuv420 formats require.
+pub fn fit_width(display_size: (u32, u32), width: u32) -> (u32, u32) {
+    let (display_width, display_height) = display_size;
+    let width = width.min(display_width).max(2);
+    let height =
+        (display_height as f64 * width as f64 / display_width.max(1) as f64).round() as u32;
+
+    (width & !1, (height & !1).max(2))
+}
@@ -0,0 +1,613 @@
+use crate::{
+    config::{AudioCodec, Config, ConfigHls, HlsRendition},
+    error::{
+        ErrorKind::{MediaProcessingError, NotFoundError},
+        Result, ResultExt,
+    },
+    media::{
+        cache::{self, cache_key, AssetType},
+        jobs::{self, Job, JobKind, JobPriority, JobSlot},
+        loudness,
+        probe::{probe_cached, to_seconds, MediaInfo, StreamKind},
+        transcode::{AudioSettings, AudioTranscoder, VideoSettings, VideoTranscoder},
+    },
+};
+use ffmpeg4::{codec, format, media, Packet, Rational};
+use ffmpeg4_sys::AV_TIME_BASE;
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, Once,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How many segments the encoder may get ahead of the last one requested
+/// before it pauses.
+const SEGMENT_LOOKAHEAD: usize = 5;
+/// Requests for segments further ahead of the encoder than this restart it
+/// at the requested segment instead of waiting.
+const RESTART_DISTANCE: usize = 2;
+const THROTTLE_INTERVAL: Duration = Duration::from_millis(500);
+const REAPER_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref HLS_SESSIONS: Mutex<HashMap<String, HlsSession>> = Mutex::new(HashMap::new());
+}
+
+static START_REAPER: Once = Once::new();
+
+/// A rendition of a video that is being watched.
+struct HlsSession {
+    dir: PathBuf,
+    last_request: Instant,
+    encoder: Option<Arc<SegmentEncoder>>,
+}
+
+/// Shared state of a thread encoding segments of a single rendition.
+#[derive(Debug)]
+struct SegmentEncoder {
+    start: usize,
+    /// The audio stream to encode, if not the default one.
+    audio_stream: Option<usize>,
+    /// How many segments the rendition has in total.
+    segments: usize,
+    /// The source's modification time, which the segments are cached with.
+    modified: SystemTime,
+    /// The segment currently being encoded.
+    position: AtomicUsize,
+    /// The last segment a client asked for.
+    requested: AtomicUsize,
+    /// The encoder's job, cancelled to stop it.
+    job: Arc<Job>,
+    finished: AtomicBool,
+    error: Mutex<Option<String>>,
+}
+
+/// A rendition the source video can be offered in, with its output size.
+struct Variant<'a> {
+    rendition: &'a HlsRendition,
+    width: u32,
+    height: u32,
+}
+
+/// Renders the master playlist listing every rendition of a video. A
+/// non-empty query is added to every link.
+pub fn master_playlist(config: &Config, path: &Path, query: &str) -> Result<String> {
+    let info = probe_cached(config, path)?;
+    let has_audio = info.streams_of(StreamKind::Audio).next().is_some();
+    let codecs = if has_audio {
+        "avc1.640028,mp4a.40.2"
+    } else {
+        "avc1.640028"
+    };
+
+    let mut playlist = "#EXTM3U\n#EXT-X-VERSION:3\n".to_string();
+    for variant in variants(&config.hls, &info)? {
+        let rendition = variant.rendition;
+        let mut bandwidth = rendition.video_bitrate * 3 / 2;
+        if has_audio {
+            bandwidth += rendition.audio_bitrate;
+        }
+
+        write!(
+            playlist,
+            "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{},CODECS=\"{}\"\n{}/index.m3u8{}\n",
+            bandwidth * 1000,
+            variant.width,
+            variant.height,
+            codecs,
+            rendition.name,
+            link_query(query)
+        )
+        .unwrap();
+    }
+
+    Ok(playlist)
+}
+
+/// Renders the playlist of a single rendition. Every segment is listed up
+/// front, even though they are only encoded once requested. A non-empty query
+/// is added to every link.
+pub fn variant_playlist(
+    config: &Config,
+    path: &Path,
+    rendition: &str,
+    query: &str,
+) -> Result<String> {
+    let info = probe_cached(config, path)?;
+    find_variant(&config.hls, &info, rendition)?;
+
+    let duration = duration(&info)?;
+    let segment_length = segment_length(&config.hls);
+
+    // Segments only start on keyframes, so they may run a little long.
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n",
+        config.hls.segment_length.max(1) + 1
+    );
+    for index in 0..segment_count(duration, segment_length) {
+        let start = index as f64 * segment_length;
+        let length = segment_length.min(duration - start);
+        write!(
+            playlist,
+            "#EXTINF:{:.3},\n{}.ts{}\n",
+            length,
+            index,
+            link_query(query)
+        )
+        .unwrap();
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+
+    Ok(playlist)
+}
+
+/// Gets the path of a segment if it has been encoded yet. Otherwise this makes
+/// sure an encoder is working towards it, starting a new one at the requested
+/// segment if the running one is too far away, and returns `None`. Segments
+/// carry the given audio stream, or the default one.
+pub fn request_segment(
+    config: &Config,
+    path: &Path,
+    rendition: &str,
+    audio_stream: Option<usize>,
+    index: usize,
+) -> Result<Option<PathBuf>> {
+    let info = probe_cached(config, path)?;
+    if let Some(audio_stream) = audio_stream {
+        if !info
+            .streams_of(StreamKind::Audio)
+            .any(|stream| stream.index == audio_stream)
+        {
+            bail!(NotFoundError)
+        }
+    }
+    let Variant {
+        rendition,
+        width,
+        height,
+    } = find_variant(&config.hls, &info, rendition)?;
+    let duration = duration(&info)?;
+    let segments = segment_count(duration, segment_length(&config.hls));
+    if index >= segments {
+        bail!(NotFoundError)
+    }
+
+    let modified = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .chain_err(|| {
+            MediaProcessingError(format!("Error reading metadata of {:?}", path).into())
+        })?;
+    let key = match audio_stream {
+        Some(audio_stream) => format!(
+            "{:016x}/{}-audio{}",
+            cache_key(path, modified),
+            rendition.name,
+            audio_stream
+        ),
+        None => format!("{:016x}/{}", cache_key(path, modified), rendition.name),
+    };
+
+    START_REAPER.call_once(|| {
+        let idle_timeout = Duration::from_secs(config.hls.idle_timeout);
+        thread::spawn(move || reap_sessions(idle_timeout));
+    });
+
+    let mut sessions = HLS_SESSIONS.lock().unwrap();
+    let session = sessions.entry(key.clone()).or_insert_with(|| HlsSession {
+        dir: AssetType::Segments.dir(config).join(&key),
+        last_request: Instant::now(),
+        encoder: None,
+    });
+    session.last_request = Instant::now();
+
+    let segment_path = session.dir.join(format!("{}.ts", index));
+    if cache::touch(&segment_path) {
+        if let Some(encoder) = &session.encoder {
+            encoder.requested.store(index, Ordering::Relaxed);
+        }
+        return Ok(Some(segment_path));
+    }
+
+    if let Some(encoder) = session.encoder.take() {
+        if let Some(error) = encoder.error.lock().unwrap().clone() {
+            bail!(MediaProcessingError(error.into()))
+        }
+
+        let position = encoder.position.load(Ordering::Relaxed);
+        if !encoder.finished.load(Ordering::Relaxed)
+            && index >= position
+            && index <= position + RESTART_DISTANCE
+        {
+            encoder.requested.store(index, Ordering::Relaxed);
+            session.encoder = Some(encoder);
+            return Ok(None);
+        }
+
+        encoder.job.cancel();
+    }
+
+    debug!(
+        "Starting HLS encoder for {:?} ({}) at segment {}",
+        path, rendition.name, index
+    );
+
+    let encoder = Arc::new(SegmentEncoder {
+        start: index,
+        audio_stream,
+        segments,
+        modified,
+        position: AtomicUsize::new(index),
+        requested: AtomicUsize::new(index),
+        job: jobs::submit(
+            JobKind::Hls,
+            format!("{:?} ({})", path, rendition.name),
+            JobPriority::Interactive,
+        ),
+        finished: AtomicBool::new(false),
+        error: Mutex::new(None),
+    });
+    session.encoder = Some(encoder.clone());
+
+    let settings = config.hls.clone();
+    let audio_filter = loudness::gain_filter(loudness::transcode_gain(config, path));
+    let rendition = rendition.clone();
+    let path = path.to_path_buf();
+    let dir = session.dir.clone();
+    thread::spawn(move || {
+        let video = VideoSettings {
+            width,
+            height,
+            bit_rate: rendition.video_bitrate as usize * 1000,
+            keyframe_interval: Some(segment_length(&settings)),
+            global_header: false,
+        };
+
+        let result = encode(
+            &settings,
+            &path,
+            &dir,
+            &video,
+            &rendition,
+            &audio_filter,
+            &encoder,
+        );
+        match result {
+            Ok(()) => {}
+            // errors after being cancelled are most likely caused by the
+            // segments being deleted out from under the encoder
+            Err(_) if encoder.job.is_cancelled() => {}
+            Err(e) => {
+                e.log();
+                *encoder.error.lock().unwrap() = Some(e.to_string());
+            }
+        }
+    });
+
+    Ok(None)
+}
+
+/// Stops the encoders of sessions nobody has requested anything from in a
+/// while and deletes their segments.
+fn reap_sessions(idle_timeout: Duration) {
+    loop {
+        thread::sleep(REAPER_INTERVAL);
+
+        let mut sessions = HLS_SESSIONS.lock().unwrap();
+        let idle: Vec<String> = sessions
+            .iter()
+            .filter(|(_, session)| session.last_request.elapsed() >= idle_timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in idle {
+            let session = sessions.remove(&key).unwrap();
+            if let Some(encoder) = session.encoder {
+                encoder.job.cancel();
+            }
+
+            debug!("Cleaning up idle HLS segments in {:?}", session.dir);
+            // done while holding the lock so a new session can not start
+            // writing into the directory while it is being deleted
+            cache::remove(&session.dir);
+        }
+    }
+}
+
+/// Encodes segments from the encoder's start segment until the end of the
+/// file, pausing whenever it gets too far ahead of the client.
+fn encode(
+    settings: &ConfigHls,
+    path: &Path,
+    dir: &Path,
+    video_settings: &VideoSettings,
+    rendition: &HlsRendition,
+    audio_filter: &str,
+    state: &SegmentEncoder,
+) -> Result<()> {
+    let slot = state.job.wait_for_slot()?;
+    let mut input = format::input(&path)
+        .chain_err(|| MediaProcessingError(format!("Error opening {:?}", path).into()))?;
+
+    let mut video = {
+        let stream = input
+            .streams()
+            .best(media::Type::Video)
+            .ok_or_else(|| MediaProcessingError(format!("No video stream in {:?}", path).into()))?;
+        VideoTranscoder::new(&stream, video_settings)?
+    };
+    let audio_stream = match state.audio_stream {
+        Some(index) => input
+            .stream(index)
+            .filter(|stream| stream.parameters().medium() == media::Type::Audio),
+        None => input.streams().best(media::Type::Audio),
+    };
+    let mut audio = match audio_stream {
+        Some(stream) => Some(AudioTranscoder::new(
+            &stream,
+            &AudioSettings {
+                codec: AudioCodec::Aac,
+                channels: Some(2),
+                bit_rate: Some(rendition.audio_bitrate as usize * 1000),
+                global_header: false,
+            },
+            audio_filter,
+        )?),
+        None => None,
+    };
+
+    let segment_length = segment_length(settings);
+    let start_time = state.start as f64 * segment_length;
+    if state.start > 0 {
+        let target = (start_time * AV_TIME_BASE as f64) as i64;
+        input.seek(target, ..target).chain_err(|| {
+            MediaProcessingError(format!("Error seeking to {}s", start_time).into())
+        })?;
+    }
+
+    fs::create_dir_all(dir)
+        .chain_err(|| MediaProcessingError(format!("Error creating {:?}", dir).into()))?;
+
+    let mut writer = SegmentWriter {
+        source: path,
+        dir,
+        segment_length,
+        start_time,
+        index: state.start,
+        video: (codec::Parameters::from(video.encoder()), video.time_base()),
+        audio: audio
+            .as_ref()
+            .map(|audio| (codec::Parameters::from(audio.encoder()), audio.time_base())),
+        output: None,
+        state,
+        slot: Some(slot),
+    };
+
+    for (stream, packet) in input.packets() {
+        if state.job.is_cancelled() {
+            return Ok(());
+        }
+
+        if stream.index() == video.stream_index() {
+            video.send_packet(&packet, &mut |packet| writer.write(packet, true))?;
+        } else if let Some(audio) = audio
+            .as_mut()
+            .filter(|audio| stream.index() == audio.stream_index())
+        {
+            audio.send_packet(&packet, &mut |packet| writer.write(packet, false))?;
+        }
+    }
+
+    video.finish(&mut |packet| writer.write(packet, true))?;
+    if let Some(audio) = audio.as_mut() {
+        audio.finish(&mut |packet| writer.write(packet, false))?;
+    }
+    writer.finish_segment()?;
+
+    state.finished.store(true, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Splits encoded packets into MPEG-TS segment files, starting a new segment
+/// on the first video keyframe after each segment boundary.
+struct SegmentWriter<'a> {
+    source: &'a Path,
+    dir: &'a Path,
+    segment_length: f64,
+    start_time: f64,
+    index: usize,
+    video: (codec::Parameters, Rational),
+    audio: Option<(codec::Parameters, Rational)>,
+    output: Option<format::context::Output>,
+    state: &'a SegmentEncoder,
+    /// Given up while the encoder waits for the client.
+    slot: Option<JobSlot>,
+}
+
+impl SegmentWriter<'_> {
+    fn write(&mut self, packet: &mut Packet, is_video: bool) -> Result<()> {
+        let (stream_index, time_base) = match (is_video, &self.audio) {
+            (true, _) => (0, self.video.1),
+            (false, Some((_, time_base))) => (1, *time_base),
+            (false, None) => return Ok(()),
+        };
+
+        let seconds = packet.pts().map(|pts| to_seconds(pts, time_base));
+        // seeking lands on the keyframe before the start, so everything up to
+        // the start of the first segment is dropped
+        if seconds.is_some_and(|seconds| seconds < self.start_time - 0.001) {
+            return Ok(());
+        }
+
+        if is_video
+            && packet.is_key()
+            && self.output.is_some()
+            && seconds.is_some_and(|seconds| {
+                seconds >= (self.index + 1) as f64 * self.segment_length - 0.001
+            })
+        {
+            self.finish_segment()?;
+            self.index += 1;
+            self.throttle()?;
+        }
+
+        if self.output.is_none() {
+            self.output = Some(self.start_segment()?);
+        }
+        let output = self.output.as_mut().unwrap();
+
+        let output_time_base = output.stream(stream_index).unwrap().time_base();
+        packet.set_stream(stream_index);
+        packet.rescale_ts(time_base, output_time_base);
+        packet.set_position(-1);
+        packet
+            .write_interleaved(output)
+            .chain_err(|| MediaProcessingError("Error writing HLS segment".into()))
+    }
+
+    fn start_segment(&mut self) -> Result<format::context::Output> {
+        self.state.position.store(self.index, Ordering::Relaxed);
+        self.state
+            .job
+            .set_progress(self.index as f64 / self.state.segments as f64);
+
+        let temp_path = self.segment_path().with_extension("tmp");
+        let mut output = format::output_as(&temp_path, "mpegts")
+            .chain_err(|| MediaProcessingError(format!("Error creating {:?}", temp_path).into()))?;
+
+        for (parameters, _) in Some(&self.video).into_iter().chain(self.audio.as_ref()) {
+            output
+                .add_stream(parameters.id())
+                .map(|mut stream| stream.set_parameters(parameters.clone()))
+                .chain_err(|| MediaProcessingError("Error adding HLS segment stream".into()))?;
+        }
+        output
+            .write_header()
+            .chain_err(|| MediaProcessingError("Error writing HLS segment header".into()))?;
+
+        Ok(output)
+    }
+
+    /// Finishes the current segment file and moves it into place.
+    fn finish_segment(&mut self) -> Result<()> {
+        if let Some(mut output) = self.output.take() {
+            output
+                .write_trailer()
+                .chain_err(|| MediaProcessingError("Error writing HLS segment trailer".into()))?;
+            drop(output);
+
+            let segment_path = self.segment_path();
+            fs::rename(segment_path.with_extension("tmp"), &segment_path).chain_err(|| {
+                MediaProcessingError(format!("Error moving {:?}", segment_path).into())
+            })?;
+            cache::insert(
+                AssetType::Segments,
+                &segment_path,
+                self.source,
+                self.state.modified,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Waits while the encoder is too far ahead of what the client asked for.
+    /// The job slot is given up meanwhile, as a paused client may not catch up
+    /// for hours, and taken back once it does.
+    fn throttle(&mut self) -> Result<()> {
+        if !self.is_ahead() {
+            return Ok(());
+        }
+
+        if let Some(slot) = self.slot.take() {
+            slot.pause();
+        }
+        while self.is_ahead() && !self.state.job.is_cancelled() {
+            thread::sleep(THROTTLE_INTERVAL);
+        }
+        self.slot = Some(self.state.job.wait_for_slot()?);
+
+        Ok(())
+    }
+
+    fn is_ahead(&self) -> bool {
+        self.index > self.state.requested.load(Ordering::Relaxed) + SEGMENT_LOOKAHEAD
+    }
+
+    fn segment_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.ts", self.index))
+    }
+}
+
+/// The renditions that are not taller than the source. If the source is
+/// smaller than every rendition, the smallest one is used at the source's
+/// size.
+fn variants<'a>(settings: &'a ConfigHls, info: &MediaInfo) -> Result<Vec<Variant<'a>>> {
+    let source = info
+        .streams_of(StreamKind::Video)
+        .next()
+        .ok_or_else(|| MediaProcessingError("No video stream".into()))?;
+    let source_width = source.width.unwrap_or(0).max(2);
+    let source_height = source.height.unwrap_or(0).max(2);
+
+    let variant = |rendition: &'a HlsRendition| {
+        let height = rendition.height.min(source_height) & !1;
+        let width =
+            (source_width as f64 * height as f64 / source_height as f64).round() as u32 & !1;
+        Variant {
+            rendition,
+            width: width.max(2),
+            height: height.max(2),
+        }
+    };
+
+    let mut variants: Vec<Variant> = settings
+        .renditions
+        .iter()
+        .filter(|rendition| rendition.height <= source_height)
+        .map(variant)
+        .collect();
+    if variants.is_empty() {
+        if let Some(smallest) = settings.renditions.iter().min_by_key(|r| r.height) {
+            variants.push(variant(smallest));
+        }
+    }
+
+    Ok(variants)
+}
+
+fn find_variant<'a>(settings: &'a ConfigHls, info: &MediaInfo, name: &str) -> Result<Variant<'a>> {
+    variants(settings, info)?
+        .into_iter()
+        .find(|variant| variant.rendition.name == name)
+        .ok_or_else(|| NotFoundError.into())
+}
+
+fn duration(info: &MediaInfo) -> Result<f64> {
+    info.duration
+        .ok_or_else(|| MediaProcessingError("Unknown duration".into()).into())
+}
+
+fn link_query(query: &str) -> String {
+    if query.is_empty() {
+        String::new()
+    } else {
+        format!("?{}", query)
+    }
+}
+
+fn segment_length(settings: &ConfigHls) -> f64 {
+    settings.segment_length.max(1) as f64
+}
+
+fn segment_count(duration: f64, segment_length: f64) -> usize {
+    (duration / segment_length).ceil() as usize
+}
@@ -0,0 +1,114 @@
+use crate::{
+    config::{Config, ThumbnailFormat},
+    error::{ErrorKind::MediaProcessingError, Result, ResultExt},
+    media::{
+        cache::{self, cache_key, AssetType},
+        frame::{encode_image, fit_within, VideoFrameReader},
+        jobs::Job,
+        transcode::build_filter,
+    },
+    metadata::exif,
+};
+use ffmpeg4::frame;
+use ffmpeg4_sys::AVPixelFormat;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Gets the path of a resized image in the cache, generating it first if
+/// needed. The image fits within the given size, and the job only waits for a
+/// slot when the image has to be generated.
+pub fn image_cached(
+    config: &Config,
+    path: &Path,
+    max_width: u32,
+    max_height: u32,
+    format: ThumbnailFormat,
+    job: &Arc<Job>,
+) -> Result<PathBuf> {
+    let modified = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .chain_err(|| {
+            MediaProcessingError(format!("Error reading metadata of {:?}", path).into())
+        })?;
+
+    let cache_path = AssetType::Images.dir(config).join(format!(
+        "{:016x}-{}x{}.{}",
+        cache_key(path, modified),
+        max_width,
+        max_height,
+        format.extension()
+    ));
+
+    if cache::touch(&cache_path) {
+        return Ok(cache_path);
+    }
+
+    let image = {
+        let _slot = job.wait_for_slot()?;
+        generate(path, max_width, max_height, format)?
+    };
+    cache::write(AssetType::Images, &cache_path, path, modified, &image)?;
+
+    Ok(cache_path)
+}
+
+/// Decodes an image, turns it the way its EXIF orientation says and encodes it
+/// again at a smaller size.
+pub fn generate(
+    path: &Path,
+    max_width: u32,
+    max_height: u32,
+    format: ThumbnailFormat,
+) -> Result<Vec<u8>> {
+    let mut reader = VideoFrameReader::open(path)?;
+    let frame = reader.frame_at(0.0, false)?;
+    let frame = orient(frame, exif::orientation(path))?;
+    let (width, height) = fit_within((frame.width(), frame.height()), max_width, max_height);
+
+    encode_image(&frame, width, height, format)
+}
+
+/// Rotates and flips a frame from the way it is stored into the way it is
+/// meant to be displayed.
+fn orient(mut frame: frame::Video, orientation: u32) -> Result<frame::Video> {
+    let spec = match orientation {
+        2 => "hflip",
+        3 => "hflip,vflip",
+        4 => "vflip",
+        5 => "transpose=cclock_flip",
+        6 => "transpose=clock",
+        7 => "transpose=clock_flip",
+        8 => "transpose=cclock",
+        _ => return Ok(frame),
+    };
+    frame.set_pts(Some(0));
+
+    let args = format!(
+        "video_size={}x{}:pix_fmt={}:time_base=1/1:pixel_aspect=1/1",
+        frame.width(),
+        frame.height(),
+        AVPixelFormat::from(frame.format()) as i32
+    );
+    let mut filter = build_filter("buffer", "buffersink", &args, spec, |_| {})?;
+
+    filter
+        .get("in")
+        .unwrap()
+        .source()
+        .add(&frame)
+        .and_then(|_| filter.get("in").unwrap().source().flush())
+        .chain_err(|| MediaProcessingError("Error orienting image".into()))?;
+
+    let mut oriented = frame::Video::empty();
+    filter
+        .get("out")
+        .unwrap()
+        .sink()
+        .frame(&mut oriented)
+        .chain_err(|| MediaProcessingError("Error receiving oriented image".into()))?;
+
+    Ok(oriented)
+}
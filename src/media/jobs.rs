@@ -0,0 +1,246 @@
+#[cfg(feature = "ffmpeg")]
+use crate::error::{ErrorKind::JobCancelledError, Result};
+#[cfg(feature = "ffmpeg")]
+use std::cmp::Reverse;
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::Instant,
+};
+
+pub type JobId = u64;
+
+lazy_static! {
+    static ref JOBS: Mutex<JobQueue> = Mutex::new(JobQueue::default());
+    static ref JOBS_CHANGED: Condvar = Condvar::new();
+}
+
+#[cfg(feature = "ffmpeg")]
+static NEXT_JOB_ID: AtomicU64 = AtomicU64::new(1);
+static MAX_CONCURRENT: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    static CURRENT_JOB: Cell<Option<JobId>> = const { Cell::new(None) };
+}
+
+#[derive(Debug, Default)]
+struct JobQueue {
+    jobs: BTreeMap<JobId, Arc<Job>>,
+    #[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+    running: usize,
+}
+
+#[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub enum JobKind {
+    Hls,
+    Image,
+    Loudness,
+    Optimize,
+    Remux,
+    Sprites,
+    Subtitles,
+    Thumbnail,
+}
+
+/// Queued jobs with a higher priority are started first.
+#[cfg_attr(not(feature = "ffmpeg"), allow(dead_code))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Serialize)]
+pub enum JobPriority {
+    /// Work nobody is waiting for.
+    Background,
+    Normal,
+    /// Work a client is actively waiting on, like playback.
+    Interactive,
+}
+
+/// A piece of ffmpeg work. Jobs wait in a queue until one of the limited
+/// number of slots is free.
+#[derive(Debug)]
+pub struct Job {
+    pub id: JobId,
+    pub kind: JobKind,
+    pub description: String,
+    pub priority: JobPriority,
+    created: Instant,
+    /// The progress from 0 to 1, stored as the bits of an f64.
+    progress: AtomicU64,
+    running: AtomicBool,
+    /// Paused jobs gave up their slot and are not waiting for one either.
+    paused: AtomicBool,
+    cancelled: AtomicBool,
+}
+
+/// Holds one of the job slots. The job is finished once this is dropped.
+#[cfg(feature = "ffmpeg")]
+pub struct JobSlot {
+    job: Arc<Job>,
+}
+
+/// Cancels a job when dropped. Request handlers hold one of these so that a
+/// client disconnecting, which drops the handler, cancels its job.
+#[cfg(feature = "ffmpeg")]
+pub struct CancelOnDrop(pub Arc<Job>);
+
+/// Sets how many jobs may run at once.
+pub fn set_max_concurrent(max_concurrent: usize) {
+    MAX_CONCURRENT.store(max_concurrent.max(1), Ordering::Relaxed);
+    JOBS_CHANGED.notify_all();
+}
+
+/// Adds a job to the queue. It is started by calling `wait_for_slot` on the
+/// thread doing the work.
+#[cfg(feature = "ffmpeg")]
+pub fn submit(kind: JobKind, description: String, priority: JobPriority) -> Arc<Job> {
+    let job = Arc::new(Job {
+        id: NEXT_JOB_ID.fetch_add(1, Ordering::Relaxed),
+        kind,
+        description,
+        priority,
+        created: Instant::now(),
+        progress: AtomicU64::new(0f64.to_bits()),
+        running: AtomicBool::new(false),
+        paused: AtomicBool::new(false),
+        cancelled: AtomicBool::new(false),
+    });
+
+    JOBS.lock().unwrap().jobs.insert(job.id, job.clone());
+
+    job
+}
+
+/// Lists every queued and running job.
+pub fn list() -> Vec<Arc<Job>> {
+    JOBS.lock().unwrap().jobs.values().cloned().collect()
+}
+
+pub fn find(id: JobId) -> Option<Arc<Job>> {
+    JOBS.lock().unwrap().jobs.get(&id).cloned()
+}
+
+/// The job the current thread is working on, if any.
+#[cfg(feature = "ffmpeg")]
+pub fn current_job() -> Option<JobId> {
+    CURRENT_JOB.with(|current| current.get())
+}
+
+impl Job {
+    /// Blocks until this job is the highest priority queued job and a slot is
+    /// free. Fails if the job is cancelled while waiting.
+    #[cfg(feature = "ffmpeg")]
+    pub fn wait_for_slot(self: &Arc<Self>) -> Result<JobSlot> {
+        let mut queue = JOBS.lock().unwrap();
+        self.paused.store(false, Ordering::Relaxed);
+
+        loop {
+            if self.is_cancelled() {
+                queue.jobs.remove(&self.id);
+                bail!(JobCancelledError)
+            }
+
+            let next = queue
+                .jobs
+                .values()
+                .filter(|job| !job.is_running() && !job.is_paused() && !job.is_cancelled())
+                .max_by_key(|job| (job.priority, Reverse(job.id)))
+                .map(|job| job.id);
+            if queue.running < MAX_CONCURRENT.load(Ordering::Relaxed) && next == Some(self.id) {
+                self.running.store(true, Ordering::Relaxed);
+                queue.running += 1;
+                CURRENT_JOB.with(|current| current.set(Some(self.id)));
+
+                return Ok(JobSlot { job: self.clone() });
+            }
+
+            queue = JOBS_CHANGED.wait(queue).unwrap();
+        }
+    }
+
+    /// Asks the job to stop. Queued jobs are removed right away, running ones
+    /// stop the next time they check.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+
+        let mut queue = JOBS.lock().unwrap();
+        if !self.is_running() {
+            queue.jobs.remove(&self.id);
+        }
+        JOBS_CHANGED.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Fails if the job has been cancelled, for checking in between steps of
+    /// work.
+    #[cfg(feature = "ffmpeg")]
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            bail!(JobCancelledError)
+        }
+
+        Ok(())
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn progress(&self) -> f64 {
+        f64::from_bits(self.progress.load(Ordering::Relaxed))
+    }
+
+    #[cfg(feature = "ffmpeg")]
+    pub fn set_progress(&self, progress: f64) {
+        self.progress
+            .store(progress.clamp(0.0, 1.0).to_bits(), Ordering::Relaxed);
+    }
+
+    /// Seconds since the job was submitted.
+    pub fn age(&self) -> f64 {
+        self.created.elapsed().as_secs_f64()
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+impl JobSlot {
+    /// Gives up the slot while the job waits on something other than ffmpeg,
+    /// like a client, without finishing the job. It continues once it gets a
+    /// slot from `wait_for_slot` again.
+    pub fn pause(self) {
+        self.job.paused.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+impl Drop for JobSlot {
+    fn drop(&mut self) {
+        CURRENT_JOB.with(|current| current.set(None));
+
+        let mut queue = JOBS.lock().unwrap();
+        if !self.job.is_paused() {
+            queue.jobs.remove(&self.job.id);
+        }
+        queue.running -= 1;
+        self.job.running.store(false, Ordering::Relaxed);
+        JOBS_CHANGED.notify_all();
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        // jobs that already finished are no longer registered, so cancelling
+        // them does nothing
+        self.0.cancel();
+    }
+}
@@ -0,0 +1,406 @@
+use crate::{
+    config::{Config, ConfigLoudness},
+    media::cache::{self, cache_key, AssetType},
+    util::path::file_extension,
+};
+#[cfg(feature = "ffmpeg")]
+use crate::{
+    error::{
+        ErrorKind::{JobCancelledError, MediaProcessingError},
+        Result, ResultExt,
+    },
+    media::{
+        jobs::{self, Job, JobKind, JobPriority},
+        transcode::{audio_source_args, build_filter},
+    },
+};
+#[cfg(feature = "ffmpeg")]
+use ffmpeg4::{codec, decoder, format, frame, media};
+#[cfg(feature = "ffmpeg")]
+use ffmpeg4_sys::AV_TIME_BASE;
+#[cfg(feature = "ffmpeg")]
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Condvar, Mutex, Once},
+    thread,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+#[cfg(feature = "ffmpeg")]
+use walkdir::WalkDir;
+
+/// Measures integrated loudness, loudness range and per channel true peaks,
+/// and attaches them to the filtered frames as metadata.
+#[cfg(feature = "ffmpeg")]
+const LOUDNESS_FILTER: &str = "ebur128=peak=true:metadata=1";
+#[cfg(feature = "ffmpeg")]
+const INTEGRATED_KEY: &str = "lavfi.r128.I";
+#[cfg(feature = "ffmpeg")]
+const RANGE_KEY: &str = "lavfi.r128.LRA";
+#[cfg(feature = "ffmpeg")]
+const TRUE_PEAK_KEY_PREFIX: &str = "lavfi.r128.true_peaks_ch";
+/// Reported as the true peak of silence, which has none.
+#[cfg(feature = "ffmpeg")]
+const SILENT_TRUE_PEAK: f64 = -144.0;
+/// The absolute gate of EBU R128. Anything this quiet measures as exactly
+/// this loud.
+const SILENCE_LOUDNESS: f64 = -70.0;
+
+#[cfg(feature = "ffmpeg")]
+lazy_static! {
+    static ref ANALYSIS_QUEUE: Mutex<AnalysisQueue> = Mutex::new(AnalysisQueue::default());
+    static ref ANALYSIS_QUEUED: Condvar = Condvar::new();
+}
+
+#[cfg(feature = "ffmpeg")]
+static START_ANALYZER: Once = Once::new();
+
+/// EBU R128 measurements of a file's audio.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS.
+    pub integrated: f64,
+    /// Loudness range in LU.
+    pub range: f64,
+    /// True peak in dBTP.
+    pub true_peak: f64,
+}
+
+/// Files waiting to be analyzed, in the order they were requested.
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Default)]
+struct AnalysisQueue {
+    paths: VecDeque<PathBuf>,
+    queued: HashSet<PathBuf>,
+}
+
+impl Loudness {
+    /// The gain in dB that brings the file to the target loudness, limited so
+    /// that its true peak stays below the maximum and it is not boosted by
+    /// more than the maximum gain. Files at or below the silence floor get
+    /// none, as their loudness says nothing.
+    pub fn suggested_gain(&self, settings: &ConfigLoudness) -> Option<f64> {
+        if self.integrated <= SILENCE_LOUDNESS {
+            return None;
+        }
+
+        Some(
+            (settings.target - self.integrated)
+                .min(settings.max_true_peak - self.true_peak)
+                .min(settings.max_gain),
+        )
+    }
+}
+
+/// Starts analyzing every audio and video file in the library in the
+/// background, if enabled. Files that have been analyzed before are skipped.
+pub fn start_analysis(config: &Config) {
+    #[cfg(feature = "ffmpeg")]
+    {
+        if !config.loudness.analyze {
+            return;
+        }
+
+        let config = config.clone();
+        thread::spawn(move || {
+            for entry in WalkDir::new(&config.base_dir)
+                .into_iter()
+                // excluded directories are not descended into
+                .filter_entry(|entry| {
+                    entry.depth() == 0
+                        || entry
+                            .path()
+                            .strip_prefix(&config.base_dir)
+                            .is_ok_and(|path| config.is_legal_path(&path.to_string_lossy()))
+                })
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().is_file())
+            {
+                request(&config, entry.path());
+            }
+        });
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = config;
+    }
+}
+
+/// Gets a file's loudness if it has been analyzed. Files without audio, or
+/// that could not be analyzed, have been too, and give `Some(None)`.
+pub fn cached(config: &Config, path: &Path) -> Option<Option<Loudness>> {
+    let cache_path = cache_path(config, path)?;
+    if !cache::touch(&cache_path) {
+        return None;
+    }
+
+    fs::read(&cache_path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+}
+
+/// Queues an audio or video file for analysis if it has not been analyzed
+/// yet.
+pub fn request(config: &Config, path: &Path) {
+    #[cfg(feature = "ffmpeg")]
+    {
+        if !config.loudness.analyze || !has_audio_extension(path) || cached(config, path).is_some()
+        {
+            return;
+        }
+
+        let mut queue = ANALYSIS_QUEUE.lock().unwrap();
+        if queue.queued.insert(path.to_path_buf()) {
+            queue.paths.push_back(path.to_path_buf());
+            ANALYSIS_QUEUED.notify_one();
+        }
+        drop(queue);
+
+        START_ANALYZER.call_once(|| {
+            let config = config.clone();
+            thread::spawn(move || analyze_queued(&config));
+        });
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = (config, path);
+    }
+}
+
+/// The gain to apply when transcoding a file's audio, if normalization is
+/// enabled and the file has been analyzed.
+#[cfg(feature = "ffmpeg")]
+pub fn transcode_gain(config: &Config, path: &Path) -> Option<f64> {
+    if !config.loudness.normalize {
+        return None;
+    }
+
+    cached(config, path)
+        .flatten()
+        .and_then(|loudness| loudness.suggested_gain(&config.loudness))
+}
+
+/// The audio filter applying a gain, `anull` if there is none.
+#[cfg(feature = "ffmpeg")]
+pub fn gain_filter(gain: Option<f64>) -> String {
+    match gain {
+        Some(gain) => format!("volume={:.2}dB", gain),
+        None => "anull".to_string(),
+    }
+}
+
+pub fn has_audio_extension(path: &Path) -> bool {
+    let mime =
+        actix_files::file_extension_to_mime(file_extension(&path.to_string_lossy()).unwrap_or(""));
+    mime.type_() == "audio" || mime.type_() == "video"
+}
+
+fn cache_path(config: &Config, path: &Path) -> Option<PathBuf> {
+    let modified = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+
+    Some(
+        AssetType::Loudness
+            .dir(config)
+            .join(format!("{:016x}.json", cache_key(path, modified))),
+    )
+}
+
+/// Analyzes queued files one at a time, forever.
+#[cfg(feature = "ffmpeg")]
+fn analyze_queued(config: &Config) {
+    loop {
+        let path = {
+            let mut queue = ANALYSIS_QUEUE.lock().unwrap();
+            loop {
+                match queue.paths.pop_front() {
+                    Some(path) => break path,
+                    None => queue = ANALYSIS_QUEUED.wait(queue).unwrap(),
+                }
+            }
+        };
+
+        if let Err(e) = analyze_cached(config, &path) {
+            e.log();
+        }
+
+        ANALYSIS_QUEUE.lock().unwrap().queued.remove(&path);
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+fn analyze_cached(config: &Config, path: &Path) -> Result<()> {
+    if cached(config, path).is_some() {
+        return Ok(());
+    }
+
+    let modified = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .chain_err(|| {
+            MediaProcessingError(format!("Error reading metadata of {:?}", path).into())
+        })?;
+    let cache_path = match cache_path(config, path) {
+        Some(cache_path) => cache_path,
+        None => return Ok(()),
+    };
+
+    let job = jobs::submit(
+        JobKind::Loudness,
+        format!("{:?}", path),
+        JobPriority::Background,
+    );
+    let loudness = {
+        let _slot = job.wait_for_slot()?;
+        debug!("Analyzing loudness of {:?}", path);
+        match analyze(path, &job) {
+            Ok(loudness) => loudness,
+            Err(e) if matches!(e.kind(), JobCancelledError) => return Err(e),
+            // broken files would otherwise be queued again on every visit
+            Err(e) => {
+                e.log();
+                None
+            }
+        }
+    };
+
+    let json = serde_json::to_vec(&loudness)
+        .chain_err(|| MediaProcessingError("Error encoding loudness".into()))?;
+    cache::write(AssetType::Loudness, &cache_path, path, modified, &json)
+}
+
+/// Runs a file's default audio stream through the EBU R128 filter. Files
+/// without audio give `None`.
+#[cfg(feature = "ffmpeg")]
+fn analyze(path: &Path, job: &Job) -> Result<Option<Loudness>> {
+    let mut input = format::input(&path)
+        .chain_err(|| MediaProcessingError(format!("Error opening {:?}", path).into()))?;
+
+    let (index, time_base, mut decoder) = match input.streams().best(media::Type::Audio) {
+        Some(stream) => {
+            let decoder = codec::context::Context::from_parameters(stream.parameters())
+                .and_then(|context| context.decoder().audio())
+                .chain_err(|| MediaProcessingError("Error opening audio decoder".into()))?;
+            (stream.index(), stream.time_base(), decoder)
+        }
+        None => return Ok(None),
+    };
+    let mut filter = build_filter(
+        "abuffer",
+        "abuffersink",
+        &audio_source_args(&decoder, time_base),
+        LOUDNESS_FILTER,
+        |_| {},
+    )?;
+    let duration = input.duration() as f64 / AV_TIME_BASE as f64;
+
+    let mut measurement = Measurement::default();
+    for (stream, packet) in input.packets() {
+        if stream.index() != index {
+            continue;
+        }
+        job.check()?;
+
+        if let Some(pts) = packet.pts() {
+            if duration > 0.0 {
+                job.set_progress(pts as f64 * f64::from(time_base) / duration);
+            }
+        }
+
+        if decoder.send_packet(&packet).is_ok() {
+            measurement.receive_frames(&mut decoder, &mut filter)?;
+        }
+    }
+
+    decoder
+        .send_eof()
+        .chain_err(|| MediaProcessingError("Error flushing audio decoder".into()))?;
+    measurement.receive_frames(&mut decoder, &mut filter)?;
+    filter
+        .get("in")
+        .unwrap()
+        .source()
+        .flush()
+        .chain_err(|| MediaProcessingError("Error flushing loudness filter".into()))?;
+    measurement.receive_filtered(&mut filter);
+
+    Ok(measurement.loudness())
+}
+
+/// The latest values the filter reported. Integrated loudness and range cover
+/// everything up to the frame they are attached to, so the last ones count.
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Default)]
+struct Measurement {
+    integrated: Option<f64>,
+    range: Option<f64>,
+    /// The highest true peak of any channel, as a linear amplitude.
+    true_peak: f64,
+}
+
+#[cfg(feature = "ffmpeg")]
+impl Measurement {
+    fn receive_frames(
+        &mut self,
+        decoder: &mut decoder::Audio,
+        filter: &mut ffmpeg4::filter::Graph,
+    ) -> Result<()> {
+        let mut frame = frame::Audio::empty();
+        while decoder.receive_frame(&mut frame).is_ok() {
+            frame.set_pts(frame.timestamp());
+            filter
+                .get("in")
+                .unwrap()
+                .source()
+                .add(&frame)
+                .chain_err(|| MediaProcessingError("Error filtering audio".into()))?;
+            self.receive_filtered(filter);
+        }
+
+        Ok(())
+    }
+
+    fn receive_filtered(&mut self, filter: &mut ffmpeg4::filter::Graph) {
+        let mut filtered = frame::Audio::empty();
+        while filter
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut filtered)
+            .is_ok()
+        {
+            for (key, value) in filtered.metadata().iter() {
+                let value = match value.parse::<f64>() {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                };
+
+                if key == INTEGRATED_KEY {
+                    self.integrated = Some(value);
+                } else if key == RANGE_KEY {
+                    self.range = Some(value);
+                } else if key.starts_with(TRUE_PEAK_KEY_PREFIX) {
+                    self.true_peak = self.true_peak.max(value);
+                }
+            }
+        }
+    }
+
+    fn loudness(&self) -> Option<Loudness> {
+        Some(Loudness {
+            integrated: self.integrated?,
+            range: self.range.unwrap_or(0.0),
+            true_peak: if self.true_peak > 0.0 {
+                (20.0 * self.true_peak.log10()).max(SILENT_TRUE_PEAK)
+            } else {
+                SILENT_TRUE_PEAK
+            },
+        })
+    }
+}
@@ -0,0 +1,27 @@
+pub mod cache;
+pub mod chapters;
+#[cfg(feature = "ffmpeg")]
+pub mod frame;
+#[cfg(feature = "ffmpeg")]
+pub mod hls;
+#[cfg(feature = "ffmpeg")]
+pub mod image;
+pub mod jobs;
+pub mod loudness;
+pub mod music;
+pub mod optimize;
+#[cfg(feature = "ffmpeg")]
+pub mod output;
+#[cfg(feature = "ffmpeg")]
+pub mod playback;
+#[cfg(feature = "ffmpeg")]
+pub mod probe;
+#[cfg(feature = "ffmpeg")]
+pub mod remux;
+#[cfg(feature = "ffmpeg")]
+pub mod sprites;
+pub mod subtitles;
+#[cfg(feature = "ffmpeg")]
+pub mod thumbnail;
+#[cfg(feature = "ffmpeg")]
+pub mod transcode;
@@ -0,0 +1,429 @@
+#[cfg(not(feature = "ffmpeg"))]
+use crate::error::ErrorKind::FeatureUnavailableError;
+use crate::{config::Config, error::Result};
+#[cfg(feature = "ffmpeg")]
+use crate::{
+    error::{ErrorKind::MediaProcessingError, ResultExt},
+    media::{
+        cache::{self, cache_key, AssetType},
+        probe::{probe_cached, MediaInfo, StreamKind},
+    },
+    util::{hash::Fnv1a, path::file_extension},
+};
+#[cfg(feature = "ffmpeg")]
+use ffmpeg4::format;
+#[cfg(feature = "ffmpeg")]
+use path_slash::PathExt;
+#[cfg(feature = "ffmpeg")]
+use std::{
+    collections::BTreeMap,
+    hash::Hasher,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+#[cfg(feature = "ffmpeg")]
+use walkdir::WalkDir;
+
+/// How long a scan of the library is used before the next request starts a
+/// rescan. Rescans are quick, as probes are cached.
+#[cfg(feature = "ffmpeg")]
+const LIBRARY_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+#[cfg(feature = "ffmpeg")]
+const VARIOUS_ARTISTS: &str = "Various Artists";
+
+#[cfg(feature = "ffmpeg")]
+lazy_static! {
+    static ref LIBRARY: Mutex<LibraryState> = Mutex::new(LibraryState::default());
+}
+
+/// The last scan of the library, and whether the next one is underway.
+#[cfg(feature = "ffmpeg")]
+#[derive(Debug, Default)]
+struct LibraryState {
+    library: Arc<MusicLibrary>,
+    scanned: Option<Instant>,
+    scanning: bool,
+}
+
+/// An audio file's tags.
+#[derive(Debug, Clone, Serialize)]
+pub struct Track {
+    /// The path relative to `base_dir`, with forward slashes.
+    pub path: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub track: Option<u32>,
+    pub disc: Option<u32>,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    /// Duration in seconds.
+    pub duration: Option<f64>,
+    /// Whether the file has cover art embedded.
+    pub has_cover: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Album {
+    /// Stays the same as long as the album's tags and directory do.
+    pub id: String,
+    pub title: String,
+    pub artist: String,
+    pub year: Option<i32>,
+    pub genre: Option<String>,
+    /// Ordered by disc and track number.
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Artist {
+    pub name: String,
+    pub albums: usize,
+    pub tracks: usize,
+}
+
+/// Every tagged audio file in the library, grouped into albums. Files without
+/// an album tag are left out.
+#[derive(Debug, Default)]
+pub struct MusicLibrary {
+    /// Ordered by artist, year and title.
+    pub albums: Vec<Album>,
+}
+
+impl Album {
+    /// The first track with embedded cover art.
+    pub fn cover_track(&self) -> Option<&Track> {
+        self.tracks.iter().find(|track| track.has_cover)
+    }
+
+    /// The total duration in seconds, if every track's is known.
+    pub fn duration(&self) -> Option<f64> {
+        self.tracks.iter().map(|track| track.duration).sum()
+    }
+}
+
+impl MusicLibrary {
+    /// Lists the album artists, ordered by name.
+    pub fn artists(&self) -> Vec<Artist> {
+        let mut artists: Vec<Artist> = vec![];
+        for album in &self.albums {
+            match artists
+                .iter_mut()
+                .find(|artist| artist.name.eq_ignore_ascii_case(&album.artist))
+            {
+                Some(artist) => {
+                    artist.albums += 1;
+                    artist.tracks += album.tracks.len();
+                }
+                None => artists.push(Artist {
+                    name: album.artist.clone(),
+                    albums: 1,
+                    tracks: album.tracks.len(),
+                }),
+            }
+        }
+        artists.sort_by_cached_key(|artist| artist.name.to_lowercase());
+
+        artists
+    }
+
+    pub fn album(&self, id: &str) -> Option<&Album> {
+        self.albums.iter().find(|album| album.id == id)
+    }
+}
+
+/// Gets the music library as it was last scanned, starting a rescan in the
+/// background if that was a while ago. Until the first scan is done, the
+/// library is empty.
+pub fn library(config: &Config) -> Result<Arc<MusicLibrary>> {
+    #[cfg(feature = "ffmpeg")]
+    {
+        start_scan(config);
+
+        Ok(LIBRARY.lock().unwrap().library.clone())
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = config;
+        bail!(FeatureUnavailableError("ffmpeg"))
+    }
+}
+
+/// Whether an audio file has cover art embedded.
+pub fn has_cover(config: &Config, path: &Path) -> bool {
+    #[cfg(feature = "ffmpeg")]
+    {
+        is_audio(path) && probe_cached(config, path).is_ok_and(|info| cover_stream(&info).is_some())
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = (config, path);
+        false
+    }
+}
+
+/// Gets the path of a file's embedded cover art in the cache, extracting it
+/// first if needed. Files without cover art give `None`.
+pub fn cover_cached(config: &Config, path: &Path) -> Result<Option<PathBuf>> {
+    #[cfg(feature = "ffmpeg")]
+    {
+        let info = probe_cached(config, path)?;
+        let (index, extension) = match cover_stream(&info) {
+            Some(cover) => cover,
+            None => return Ok(None),
+        };
+
+        let modified = path
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .chain_err(|| {
+                MediaProcessingError(format!("Error reading metadata of {:?}", path).into())
+            })?;
+        let cache_path = AssetType::Covers.dir(config).join(format!(
+            "{:016x}.{}",
+            cache_key(path, modified),
+            extension
+        ));
+        if cache::touch(&cache_path) {
+            return Ok(Some(cache_path));
+        }
+
+        let mut input = format::input(&path)
+            .chain_err(|| MediaProcessingError(format!("Error opening {:?}", path).into()))?;
+        // demuxers hand out an attached picture as its stream's first packet
+        let image = input
+            .packets()
+            .find(|(stream, _)| stream.index() == index)
+            .and_then(|(_, packet)| packet.data().map(|data| data.to_vec()))
+            .ok_or_else(|| {
+                MediaProcessingError(format!("No cover art packet in {:?}", path).into())
+            })?;
+        cache::write(AssetType::Covers, &cache_path, path, modified, &image)?;
+
+        Ok(Some(cache_path))
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = (config, path);
+        bail!(FeatureUnavailableError("ffmpeg"))
+    }
+}
+
+/// Scans the library in the background, unless it is already being scanned
+/// or was scanned recently.
+pub fn start_scan(config: &Config) {
+    #[cfg(feature = "ffmpeg")]
+    {
+        let mut state = LIBRARY.lock().unwrap();
+        if state.scanning
+            || state
+                .scanned
+                .is_some_and(|scanned| scanned.elapsed() < LIBRARY_REFRESH_INTERVAL)
+        {
+            return;
+        }
+        state.scanning = true;
+
+        let config = config.clone();
+        thread::spawn(move || {
+            let library = Arc::new(scan(&config));
+
+            let mut state = LIBRARY.lock().unwrap();
+            state.library = library;
+            state.scanned = Some(Instant::now());
+            state.scanning = false;
+        });
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = config;
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+pub fn is_audio(path: &Path) -> bool {
+    actix_files::file_extension_to_mime(file_extension(&path.to_string_lossy()).unwrap_or(""))
+        .type_()
+        == "audio"
+}
+
+/// Reads the tags of every legal audio file under `base_dir` and groups them
+/// into albums. Tracks with an album artist are grouped by album artist and
+/// album, others by directory and album, which keeps compilations together.
+#[cfg(feature = "ffmpeg")]
+fn scan(config: &Config) -> MusicLibrary {
+    let started = Instant::now();
+    let mut groups: BTreeMap<(String, String), Vec<Track>> = BTreeMap::new();
+
+    for entry in WalkDir::new(&config.base_dir)
+        .into_iter()
+        // excluded directories are not descended into
+        .filter_entry(|entry| {
+            entry.depth() == 0
+                || entry
+                    .path()
+                    .strip_prefix(&config.base_dir)
+                    .is_ok_and(|path| config.is_legal_path(&path.to_string_lossy()))
+        })
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file() && is_audio(entry.path()))
+    {
+        let relative_path = match entry.path().strip_prefix(&config.base_dir) {
+            Ok(relative_path) => relative_path,
+            Err(_) => continue,
+        };
+
+        let info = match probe_cached(config, entry.path()) {
+            Ok(info) => info,
+            Err(e) => {
+                debug!("Not reading tags of {:?}: {}", entry.path(), e);
+                continue;
+            }
+        };
+        let track = read_track(&info, relative_path.to_slash_lossy());
+        let album = match &track.album {
+            Some(album) => album.to_lowercase(),
+            None => continue,
+        };
+        let group = match &track.album_artist {
+            Some(album_artist) => album_artist.to_lowercase(),
+            None => relative_path
+                .parent()
+                .map(|dir| dir.to_slash_lossy())
+                .unwrap_or_default(),
+        };
+
+        groups.entry((group, album)).or_default().push(track);
+    }
+
+    let mut albums: Vec<Album> = groups
+        .into_iter()
+        .map(|(key, mut tracks)| {
+            tracks.sort_by(|a, b| {
+                (a.disc.unwrap_or(1), a.track.unwrap_or(u32::MAX), &a.path).cmp(&(
+                    b.disc.unwrap_or(1),
+                    b.track.unwrap_or(u32::MAX),
+                    &b.path,
+                ))
+            });
+            album(key, tracks)
+        })
+        .collect();
+    albums.sort_by_cached_key(|album| {
+        (
+            album.artist.to_lowercase(),
+            album.year,
+            album.title.to_lowercase(),
+        )
+    });
+
+    debug!(
+        "Scanned {} albums in {:.1}s",
+        albums.len(),
+        started.elapsed().as_secs_f64()
+    );
+
+    MusicLibrary { albums }
+}
+
+/// Builds an album out of its sorted tracks. Album-wide values come from the
+/// first track that has them.
+#[cfg(feature = "ffmpeg")]
+fn album(key: (String, String), tracks: Vec<Track>) -> Album {
+    // ids end up in bookmarked urls, so they have to outlive upgrades too;
+    // 0xff never occurs in UTF-8 and separates the two parts
+    let (group, title) = &key;
+    let mut hasher = Fnv1a::default();
+    hasher.write(group.as_bytes());
+    hasher.write(&[0xff]);
+    hasher.write(title.as_bytes());
+
+    let first = |value: fn(&Track) -> Option<&String>| tracks.iter().find_map(value).cloned();
+    let mut artists: Vec<&String> = tracks
+        .iter()
+        .filter_map(|track| track.artist.as_ref())
+        .collect();
+    artists.sort();
+    artists.dedup();
+
+    let artist = first(|track| track.album_artist.as_ref()).unwrap_or_else(|| match artists[..] {
+        [artist] => artist.clone(),
+        _ => VARIOUS_ARTISTS.to_string(),
+    });
+
+    Album {
+        id: format!("{:016x}", hasher.finish()),
+        title: first(|track| track.album.as_ref()).unwrap_or_default(),
+        artist,
+        year: tracks.iter().find_map(|track| track.year),
+        genre: first(|track| track.genre.as_ref()),
+        tracks,
+    }
+}
+
+/// Reads the tags ffmpeg found in a file. ID3, Vorbis comment and MP4 tags
+/// mostly end up under the same names, the rest are looked up by their
+/// Vorbis comment names.
+#[cfg(feature = "ffmpeg")]
+fn read_track(info: &MediaInfo, path: String) -> Track {
+    // Ogg files keep their tags on the audio stream rather than the container
+    let audio_tags = info
+        .streams_of(StreamKind::Audio)
+        .next()
+        .map(|stream| &stream.tags);
+    let tag = |keys: &[&str]| {
+        keys.iter().find_map(|key| {
+            Some(&info.tags)
+                .into_iter()
+                .chain(audio_tags)
+                .flat_map(|tags| tags.iter())
+                .find(|(name, _)| name.eq_ignore_ascii_case(key))
+                .map(|(_, value)| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        })
+    };
+    // track and disc numbers may come with a total, like "3/12"
+    let number = |keys: &[&str]| {
+        tag(keys).and_then(|value| value.split('/').next()?.trim().parse::<u32>().ok())
+    };
+
+    Track {
+        title: tag(&["title"]),
+        artist: tag(&["artist"]),
+        album_artist: tag(&["album_artist", "albumartist", "album artist"]),
+        album: tag(&["album"]),
+        track: number(&["track", "tracknumber"]),
+        disc: number(&["disc", "discnumber"]),
+        year: tag(&["date", "year", "originaldate"])
+            .and_then(|date| date.get(..4).and_then(|year| year.parse().ok())),
+        genre: tag(&["genre"]),
+        duration: info.duration,
+        has_cover: cover_stream(info).is_some(),
+        path,
+    }
+}
+
+/// Finds the stream holding a file's cover art, with the file extension of
+/// its image format.
+#[cfg(feature = "ffmpeg")]
+fn cover_stream(info: &MediaInfo) -> Option<(usize, &'static str)> {
+    info.streams_of(StreamKind::Video)
+        .filter(|stream| stream.attached_pic)
+        .find_map(|stream| match stream.codec.as_str() {
+            "mjpeg" => Some((stream.index, "jpg")),
+            "png" => Some((stream.index, "png")),
+            "bmp" => Some((stream.index, "bmp")),
+            "webp" => Some((stream.index, "webp")),
+            _ => None,
+        })
+}
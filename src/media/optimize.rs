@@ -0,0 +1,510 @@
+#[cfg(not(feature = "ffmpeg"))]
+use crate::error::ErrorKind::FeatureUnavailableError;
+#[cfg(feature = "ffmpeg")]
+use crate::{
+    config::AudioCodec,
+    error::{
+        ErrorKind::{CacheError, MediaProcessingError, NotFoundError},
+        ResultExt,
+    },
+    media::{
+        jobs::{self, JobKind, JobPriority},
+        playback::{self, PlaybackMethod},
+        probe::{probe_cached, to_seconds, MediaInfo, StreamKind},
+        remux::write_packet,
+        transcode::{AudioSettings, AudioTranscoder, VideoSettings, VideoTranscoder},
+    },
+    util::path::{file_extension, parse_path},
+};
+use crate::{
+    config::Config,
+    error::Result,
+    media::{
+        cache::{self, cache_key, AssetType},
+        jobs::Job,
+    },
+};
+#[cfg(feature = "ffmpeg")]
+use ffmpeg4::{codec, format, media, Dictionary};
+#[cfg(feature = "ffmpeg")]
+use ffmpeg4_sys::AV_TIME_BASE;
+#[cfg(feature = "ffmpeg")]
+use std::{
+    collections::HashSet,
+    fs,
+    sync::{Condvar, Once},
+    thread,
+    time::{Duration, SystemTime},
+};
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+#[cfg(feature = "ffmpeg")]
+use walkdir::WalkDir;
+
+/// Lists the files still to be optimized, so an interrupted run continues
+/// after a restart.
+#[cfg(feature = "ffmpeg")]
+const QUEUE_FILE_NAME: &str = "optimize.json";
+/// Moves the index to the front of the file, so playback can start before the
+/// whole file is loaded.
+#[cfg(feature = "ffmpeg")]
+const FAST_START_FLAGS: &str = "+faststart";
+#[cfg(feature = "ffmpeg")]
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref OPTIMIZER: Mutex<Optimizer> = Mutex::new(Optimizer::default());
+}
+
+#[cfg(feature = "ffmpeg")]
+lazy_static! {
+    static ref OPTIMIZER_CHANGED: Condvar = Condvar::new();
+}
+
+#[cfg(feature = "ffmpeg")]
+static START_OPTIMIZER: Once = Once::new();
+
+#[derive(Debug, Default)]
+struct Optimizer {
+    /// Files waiting to be optimized, the one being worked on first.
+    pending: VecDeque<PathBuf>,
+    #[cfg(feature = "ffmpeg")]
+    queued: HashSet<PathBuf>,
+    current: Option<Arc<Job>>,
+    optimized: usize,
+    skipped: usize,
+    failed: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OptimizeStatus {
+    /// Files waiting to be optimized, including the current one.
+    pub pending: usize,
+    /// The file being optimized, relative to the base directory.
+    pub current: Option<String>,
+    /// Progress of the current file from 0 to 1.
+    pub progress: Option<f64>,
+    pub optimized: usize,
+    /// Files that the target profile can already play, or that had been
+    /// optimized before.
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// Continues optimizing whatever was left when the server last stopped.
+pub fn resume(config: &Config) {
+    #[cfg(feature = "ffmpeg")]
+    {
+        let pending: Vec<PathBuf> = match fs::read(config.cache_dir.join(QUEUE_FILE_NAME)) {
+            Ok(bytes) => match serde_json::from_slice(&bytes) {
+                Ok(pending) => pending,
+                Err(e) => {
+                    warn!("Ignoring unreadable optimize queue: {}", e);
+                    return;
+                }
+            },
+            Err(_) => return,
+        };
+
+        if !pending.is_empty() {
+            info!("Resuming optimization of {} files", pending.len());
+            enqueue(config, pending);
+        }
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = config;
+    }
+}
+
+/// Queues every video file in a directory under `base_dir`, or a single
+/// file, for optimizing. Returns how many files were added to the queue.
+pub fn queue(config: &Config, path: &str) -> Result<usize> {
+    #[cfg(feature = "ffmpeg")]
+    {
+        let relative_path = parse_path(path, false)?;
+        if !config.is_legal_path(&relative_path.to_string_lossy()) {
+            bail!(NotFoundError)
+        }
+        let root = config.base_dir.join(&relative_path);
+        if !root.exists() {
+            bail!(NotFoundError)
+        }
+
+        let files: Vec<PathBuf> = WalkDir::new(&root)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file() && is_video(entry.path()))
+            .filter(|entry| {
+                entry
+                    .path()
+                    .strip_prefix(&config.base_dir)
+                    .is_ok_and(|path| config.is_legal_path(&path.to_string_lossy()))
+            })
+            .map(|entry| entry.into_path())
+            .collect();
+
+        Ok(enqueue(config, files))
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = (config, path);
+        bail!(FeatureUnavailableError("ffmpeg"))
+    }
+}
+
+pub fn status(config: &Config) -> OptimizeStatus {
+    let optimizer = OPTIMIZER.lock().unwrap();
+
+    OptimizeStatus {
+        pending: optimizer.pending.len(),
+        current: optimizer.current.as_ref().and_then(|_| {
+            optimizer.pending.front().map(|path| {
+                path.strip_prefix(&config.base_dir)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .to_string()
+            })
+        }),
+        progress: optimizer.current.as_ref().map(|job| job.progress()),
+        optimized: optimizer.optimized,
+        skipped: optimizer.skipped,
+        failed: optimizer.failed,
+    }
+}
+
+/// Blocks until the queue is empty, logging the progress every now and then.
+pub fn wait(config: &Config) {
+    #[cfg(feature = "ffmpeg")]
+    {
+        let mut optimizer = OPTIMIZER.lock().unwrap();
+        while !optimizer.pending.is_empty() {
+            optimizer = OPTIMIZER_CHANGED
+                .wait_timeout(optimizer, PROGRESS_LOG_INTERVAL)
+                .unwrap()
+                .0;
+
+            drop(optimizer);
+            let status = status(config);
+            if let (Some(current), Some(progress)) = (status.current, status.progress) {
+                info!(
+                    "Optimizing {} ({:.0}%), {} files left",
+                    current,
+                    progress * 100.0,
+                    status.pending
+                );
+            }
+            optimizer = OPTIMIZER.lock().unwrap();
+        }
+
+        info!(
+            "Optimized {} files, skipped {}, {} failed",
+            optimizer.optimized, optimizer.skipped, optimizer.failed
+        );
+    }
+
+    #[cfg(not(feature = "ffmpeg"))]
+    {
+        let _ = config;
+    }
+}
+
+/// Gets the optimized version of a file, if there is one.
+pub fn optimized(config: &Config, path: &Path) -> Option<PathBuf> {
+    let optimized_path = optimized_path(config, path)?;
+    if cache::touch(&optimized_path) {
+        Some(optimized_path)
+    } else {
+        None
+    }
+}
+
+fn optimized_path(config: &Config, path: &Path) -> Option<PathBuf> {
+    let modified = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+
+    Some(
+        AssetType::Optimized
+            .dir(config)
+            .join(format!("{:016x}.mp4", cache_key(path, modified))),
+    )
+}
+
+#[cfg(feature = "ffmpeg")]
+fn is_video(path: &Path) -> bool {
+    actix_files::file_extension_to_mime(file_extension(&path.to_string_lossy()).unwrap_or(""))
+        .type_()
+        == "video"
+}
+
+/// Adds files to the queue, starting the optimizer if it is not running yet.
+#[cfg(feature = "ffmpeg")]
+fn enqueue(config: &Config, files: Vec<PathBuf>) -> usize {
+    let mut optimizer = OPTIMIZER.lock().unwrap();
+    let mut added = 0;
+    for file in files {
+        if optimizer.queued.insert(file.clone()) {
+            optimizer.pending.push_back(file);
+            added += 1;
+        }
+    }
+    save_queue(config, &optimizer.pending);
+    OPTIMIZER_CHANGED.notify_all();
+    drop(optimizer);
+
+    START_OPTIMIZER.call_once(|| {
+        let config = config.clone();
+        thread::spawn(move || optimize_queued(&config));
+    });
+
+    added
+}
+
+/// Optimizes queued files one at a time, forever. A file stays at the front
+/// of the persisted queue until it is done, so it is started over if the
+/// server stops in the middle of it.
+#[cfg(feature = "ffmpeg")]
+fn optimize_queued(config: &Config) {
+    loop {
+        let path = {
+            let mut optimizer = OPTIMIZER.lock().unwrap();
+            loop {
+                match optimizer.pending.front() {
+                    Some(path) => break path.clone(),
+                    None => optimizer = OPTIMIZER_CHANGED.wait(optimizer).unwrap(),
+                }
+            }
+        };
+
+        let result = optimize_cached(config, &path);
+
+        let mut optimizer = OPTIMIZER.lock().unwrap();
+        match result {
+            Ok(true) => optimizer.optimized += 1,
+            Ok(false) => optimizer.skipped += 1,
+            Err(e) => {
+                e.log();
+                optimizer.failed += 1;
+            }
+        }
+        optimizer.current = None;
+        optimizer.pending.pop_front();
+        optimizer.queued.remove(&path);
+        save_queue(config, &optimizer.pending);
+        OPTIMIZER_CHANGED.notify_all();
+    }
+}
+
+/// Optimizes a file unless that has been done before or the target profile
+/// can play it as it is. Returns whether it was optimized.
+#[cfg(feature = "ffmpeg")]
+fn optimize_cached(config: &Config, path: &Path) -> Result<bool> {
+    if !path.is_file() || optimized(config, path).is_some() {
+        return Ok(false);
+    }
+
+    let info = probe_cached(config, path)?;
+    let extension = file_extension(&path.to_string_lossy())
+        .unwrap_or("")
+        .to_lowercase();
+    let profile = config.named_profile(&config.optimize.profile);
+    if playback::plan(&info, &extension, &profile).method == PlaybackMethod::DirectPlay {
+        return Ok(false);
+    }
+
+    let modified = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .chain_err(|| {
+            MediaProcessingError(format!("Error reading metadata of {:?}", path).into())
+        })?;
+    let optimized_path = match optimized_path(config, path) {
+        Some(optimized_path) => optimized_path,
+        None => return Ok(false),
+    };
+
+    let job = jobs::submit(
+        JobKind::Optimize,
+        format!("{:?}", path),
+        JobPriority::Background,
+    );
+    OPTIMIZER.lock().unwrap().current = Some(job.clone());
+
+    let _slot = job.wait_for_slot()?;
+    info!("Optimizing {:?}", path);
+    transcode(config, path, &info, &optimized_path, modified, &job)?;
+
+    Ok(true)
+}
+
+/// Transcodes a file's default video and audio streams into an MP4 in the
+/// cache. The file is written under a temporary name and only moved into
+/// place once complete.
+#[cfg(feature = "ffmpeg")]
+fn transcode(
+    config: &Config,
+    path: &Path,
+    info: &MediaInfo,
+    optimized_path: &Path,
+    modified: SystemTime,
+    job: &Job,
+) -> Result<()> {
+    let settings = &config.optimize;
+    let mut input = format::input(&path)
+        .chain_err(|| MediaProcessingError(format!("Error opening {:?}", path).into()))?;
+    let duration = input.duration() as f64 / AV_TIME_BASE as f64;
+
+    let (width, height) = output_size(info, settings.max_height)?;
+    let mut video = {
+        let stream = input
+            .streams()
+            .best(media::Type::Video)
+            .ok_or_else(|| MediaProcessingError(format!("No video stream in {:?}", path).into()))?;
+        VideoTranscoder::new(
+            &stream,
+            &VideoSettings {
+                width,
+                height,
+                bit_rate: settings.video_bitrate as usize * 1000,
+                keyframe_interval: None,
+                global_header: true,
+            },
+        )?
+    };
+    let mut audio = match input.streams().best(media::Type::Audio) {
+        Some(stream) => Some(AudioTranscoder::new(
+            &stream,
+            &AudioSettings {
+                codec: AudioCodec::Aac,
+                channels: Some(2),
+                bit_rate: Some(settings.audio_bitrate as usize * 1000),
+                global_header: true,
+            },
+            "anull",
+        )?),
+        None => None,
+    };
+
+    let temp_path = optimized_path.with_extension("tmp");
+    if let Some(parent) = temp_path.parent() {
+        fs::create_dir_all(parent)
+            .chain_err(|| MediaProcessingError(format!("Error creating {:?}", parent).into()))?;
+    }
+    let mut output = format::output_as(&temp_path, "mp4")
+        .chain_err(|| MediaProcessingError(format!("Error creating {:?}", temp_path).into()))?;
+
+    let parameters = Some(codec::Parameters::from(video.encoder()))
+        .into_iter()
+        .chain(
+            audio
+                .as_ref()
+                .map(|audio| codec::Parameters::from(audio.encoder())),
+        );
+    for parameters in parameters {
+        output
+            .add_stream(parameters.id())
+            .map(|mut stream| stream.set_parameters(parameters.clone()))
+            .chain_err(|| MediaProcessingError("Error adding output stream".into()))?;
+    }
+
+    let mut header_options = Dictionary::new();
+    header_options.set("movflags", FAST_START_FLAGS);
+    output.write_header_with(header_options).chain_err(|| {
+        MediaProcessingError(format!("Error writing MP4 header for {:?}", temp_path).into())
+    })?;
+
+    let result = (|| -> Result<()> {
+        let video_time_base = video.time_base();
+        for (stream, packet) in input.packets() {
+            job.check()?;
+
+            if stream.index() == video.stream_index() {
+                if let Some(pts) = packet.pts() {
+                    if duration > 0.0 {
+                        job.set_progress(to_seconds(pts, stream.time_base()) / duration);
+                    }
+                }
+                video.send_packet(&packet, &mut |packet| {
+                    write_packet(&mut output, packet, 0, video_time_base)
+                })?;
+            } else if let Some(audio) = audio
+                .as_mut()
+                .filter(|audio| stream.index() == audio.stream_index())
+            {
+                let time_base = audio.time_base();
+                audio.send_packet(&packet, &mut |packet| {
+                    write_packet(&mut output, packet, 1, time_base)
+                })?;
+            }
+        }
+
+        video.finish(&mut |packet| write_packet(&mut output, packet, 0, video_time_base))?;
+        if let Some(audio) = audio.as_mut() {
+            let time_base = audio.time_base();
+            audio.finish(&mut |packet| write_packet(&mut output, packet, 1, time_base))?;
+        }
+
+        output
+            .write_trailer()
+            .chain_err(|| MediaProcessingError("Error writing MP4 trailer".into()))
+    })();
+    drop(output);
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    fs::rename(&temp_path, optimized_path)
+        .chain_err(|| MediaProcessingError(format!("Error moving {:?}", optimized_path).into()))?;
+    cache::insert(AssetType::Optimized, optimized_path, path, modified);
+
+    Ok(())
+}
+
+/// Scales the source video down to the maximum height, keeping its aspect
+/// ratio. Sources that are small enough keep their size.
+#[cfg(feature = "ffmpeg")]
+fn output_size(info: &MediaInfo, max_height: u32) -> Result<(u32, u32)> {
+    let source = info
+        .streams_of(StreamKind::Video)
+        .next()
+        .ok_or_else(|| MediaProcessingError("No video stream".into()))?;
+    let source_width = source.width.unwrap_or(0).max(2);
+    let source_height = source.height.unwrap_or(0).max(2);
+
+    let height = source_height.min(max_height.max(2)) & !1;
+    let width = (source_width as f64 * height as f64 / source_height as f64).round() as u32 & !1;
+
+    Ok((width.max(2), height.max(2)))
+}
+
+#[cfg(feature = "ffmpeg")]
+fn save_queue(config: &Config, pending: &VecDeque<PathBuf>) {
+    let queue_path = config.cache_dir.join(QUEUE_FILE_NAME);
+    if pending.is_empty() {
+        let _ = fs::remove_file(&queue_path);
+        return;
+    }
+
+    let temp_path = queue_path.with_extension("tmp");
+    let result = serde_json::to_vec(pending)
+        .chain_err(|| CacheError("Error encoding optimize queue".into()))
+        .and_then(|bytes| {
+            fs::create_dir_all(&config.cache_dir)
+                .and_then(|_| fs::write(&temp_path, bytes))
+                .and_then(|_| fs::rename(&temp_path, &queue_path))
+                .chain_err(|| CacheError("Error writing optimize queue".into()))
+        });
+    if let Err(e) = result {
+        e.log();
+    }
+}
@@ -0,0 +1,131 @@
+use crate::error::{ErrorKind::MediaProcessingError, Result};
+use ffmpeg4::format;
+use ffmpeg4_sys::{
+    av_free, av_malloc, avformat_alloc_output_context2, avio_alloc_context, avio_context_free,
+    avio_flush, AVFMT_FLAG_CUSTOM_IO,
+};
+use std::{
+    ffi::CString,
+    ops::{Deref, DerefMut},
+    os::raw::{c_int, c_void},
+    ptr, slice,
+};
+
+const IO_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Receives muxed bytes. Returning false means nobody is listening anymore,
+/// which makes the muxer fail its current write.
+pub type ByteSink = Box<dyn FnMut(&[u8]) -> bool + Send>;
+
+struct SinkState {
+    sink: ByteSink,
+    closed: bool,
+}
+
+/// An output format context that hands everything it writes to a callback
+/// instead of a file, so muxed media can be streamed straight into a response.
+pub struct CallbackOutput {
+    output: format::context::Output,
+    state: *mut SinkState,
+}
+
+// The raw state pointer is only ever used by the muxer of the output it
+// belongs to, so it moves between threads together with the output.
+unsafe impl Send for CallbackOutput {}
+
+impl CallbackOutput {
+    pub fn new(format_name: &str, sink: ByteSink) -> Result<CallbackOutput> {
+        let format_name = CString::new(format_name).unwrap();
+        let state = Box::into_raw(Box::new(SinkState {
+            sink,
+            closed: false,
+        }));
+
+        unsafe {
+            let mut context = ptr::null_mut();
+            if avformat_alloc_output_context2(
+                &mut context,
+                ptr::null_mut(),
+                format_name.as_ptr(),
+                ptr::null(),
+            ) < 0
+                || context.is_null()
+            {
+                drop(Box::from_raw(state));
+                bail!(MediaProcessingError(
+                    format!("Error creating {:?} output", format_name).into()
+                ))
+            }
+
+            let buffer = av_malloc(IO_BUFFER_SIZE) as *mut u8;
+            let io = avio_alloc_context(
+                buffer,
+                IO_BUFFER_SIZE as c_int,
+                1,
+                state as *mut c_void,
+                None,
+                Some(write_packet),
+                None,
+            );
+            (*context).pb = io;
+            (*context).flags |= AVFMT_FLAG_CUSTOM_IO as c_int;
+
+            Ok(CallbackOutput {
+                output: format::context::Output::wrap(context),
+                state,
+            })
+        }
+    }
+
+    /// Whether the sink has stopped accepting bytes.
+    pub fn is_closed(&self) -> bool {
+        unsafe { (*self.state).closed }
+    }
+}
+
+impl Deref for CallbackOutput {
+    type Target = format::context::Output;
+
+    fn deref(&self) -> &Self::Target {
+        &self.output
+    }
+}
+
+impl DerefMut for CallbackOutput {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.output
+    }
+}
+
+impl Drop for CallbackOutput {
+    fn drop(&mut self) {
+        // The wrapped output closes its IO context when dropped, which must
+        // not happen to a custom one, so it is freed and detached here first.
+        unsafe {
+            let context = self.output.as_mut_ptr();
+            let mut io = (*context).pb;
+            if !io.is_null() {
+                avio_flush(io);
+                av_free((*io).buffer as *mut c_void);
+                avio_context_free(&mut io);
+                (*context).pb = ptr::null_mut();
+            }
+
+            drop(Box::from_raw(self.state));
+        }
+    }
+}
+
+unsafe extern "C" fn write_packet(opaque: *mut c_void, buffer: *mut u8, size: c_int) -> c_int {
+    let state = &mut *(opaque as *mut SinkState);
+    if state.closed || size < 0 {
+        return ffmpeg4::Error::Eof.into();
+    }
+
+    if (state.sink)(slice::from_raw_parts(buffer, size as usize)) {
+        size
+    } else {
+        state.closed = true;
+        ffmpeg4::Error::Eof.into()
+    }
+}
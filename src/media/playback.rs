@@ -0,0 +1,143 @@
+use crate::{
+    config::{AudioCodec, DeviceProfile},
+    media::probe::{MediaInfo, StreamInfo, StreamKind},
+};
+
+/// The container remuxed streams are served in.
+const REMUX_CONTAINER: &str = "mp4";
+/// The codec HLS transcodes video to.
+const TRANSCODE_VIDEO_CODEC: &str = "h264";
+/// Channels above which transcoded audio is mixed down to stereo.
+const MAX_CHANNELS: u32 = 2;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub enum PlaybackMethod {
+    /// The file plays as it is.
+    DirectPlay,
+    /// The streams play, but the container does not, or only the audio needs
+    /// transcoding.
+    Remux,
+    /// The video needs re-encoding.
+    Transcode,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlaybackPlan {
+    pub method: PlaybackMethod,
+    /// Index of the audio stream the plan was made for.
+    pub audio_stream: Option<usize>,
+    /// For remuxing, the codec to transcode the audio to if the original can
+    /// not be played.
+    pub audio_codec: Option<AudioCodec>,
+    pub downmix: bool,
+    /// Why direct play was not possible.
+    pub reasons: Vec<String>,
+}
+
+/// Decides how a file is best played by a client with the given profile.
+pub fn plan(info: &MediaInfo, extension: &str, profile: &DeviceProfile) -> PlaybackPlan {
+    let supports =
+        |list: &[String], value: &str| list.iter().any(|item| item.eq_ignore_ascii_case(value));
+    let mut reasons = vec![];
+
+    let container_ok = supports(&profile.containers, extension);
+    if !container_ok {
+        reasons.push(format!("Container {} is not supported", extension));
+    }
+
+    let video = info.streams_of(StreamKind::Video).next();
+    let video_ok = video.is_none_or(|video| {
+        let mut ok = true;
+        if !supports(&profile.video_codecs, &video.codec) {
+            reasons.push(format!("Video codec {} is not supported", video.codec));
+            ok = false;
+        }
+        if let (Some(max_height), Some(height)) = (profile.max_height, video.height) {
+            if height > max_height {
+                reasons.push(format!(
+                    "Video height {} is above the maximum of {}",
+                    height, max_height
+                ));
+                ok = false;
+            }
+        }
+        ok
+    });
+
+    let bit_rate_ok = match (profile.max_bitrate, info.bit_rate) {
+        (Some(max_bitrate), Some(bit_rate)) if bit_rate > max_bitrate as i64 * 1000 => {
+            reasons.push(format!(
+                "Bitrate {} kbit/s is above the maximum of {} kbit/s",
+                bit_rate / 1000,
+                max_bitrate
+            ));
+            false
+        }
+        _ => true,
+    };
+
+    let audio = default_audio(info);
+    let audio_ok = audio.is_none_or(|audio| {
+        let ok = supports(&profile.audio_codecs, &audio.codec);
+        if !ok {
+            reasons.push(format!("Audio codec {} is not supported", audio.codec));
+        }
+        ok
+    });
+
+    if container_ok && video_ok && bit_rate_ok && audio_ok {
+        return PlaybackPlan {
+            method: PlaybackMethod::DirectPlay,
+            audio_stream: audio.map(|audio| audio.index),
+            audio_codec: None,
+            downmix: false,
+            reasons,
+        };
+    }
+
+    let transcoded_audio = [AudioCodec::Aac, AudioCodec::Opus]
+        .iter()
+        .copied()
+        .find(|codec| supports(&profile.audio_codecs, codec.name()));
+    let downmix = audio.is_some_and(|audio| {
+        audio
+            .channels
+            .is_some_and(|channels| channels > MAX_CHANNELS)
+    });
+
+    let can_remux = video_ok
+        && bit_rate_ok
+        && supports(&profile.containers, REMUX_CONTAINER)
+        && (audio_ok || transcoded_audio.is_some());
+    if can_remux {
+        return PlaybackPlan {
+            method: PlaybackMethod::Remux,
+            audio_stream: audio.map(|audio| audio.index),
+            audio_codec: if audio_ok { None } else { transcoded_audio },
+            downmix: !audio_ok && downmix,
+            reasons,
+        };
+    }
+
+    if !supports(&profile.video_codecs, TRANSCODE_VIDEO_CODEC) {
+        reasons.push(format!(
+            "Transcoding produces {}, which is not supported either",
+            TRANSCODE_VIDEO_CODEC
+        ));
+    }
+
+    PlaybackPlan {
+        method: PlaybackMethod::Transcode,
+        audio_stream: audio.map(|audio| audio.index),
+        audio_codec: None,
+        downmix: false,
+        reasons,
+    }
+}
+
+/// The audio stream players pick when not told otherwise.
+fn default_audio(info: &MediaInfo) -> Option<&StreamInfo> {
+    info.streams_of(StreamKind::Audio)
+        .find(|stream| stream.default)
+        .or_else(|| info.streams_of(StreamKind::Audio).next())
+}
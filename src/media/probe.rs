@@ -0,0 +1,308 @@
+use crate::{
+    config::Config,
+    error::{ErrorKind::MediaProbeError, Result, ResultExt},
+    media::cache::{self, cache_key, AssetType},
+};
+use ffmpeg4::{
+    format,
+    format::stream::{Disposition, Stream},
+    media, Rational,
+};
+use ffmpeg4_sys::{
+    av_color_transfer_name, av_get_pix_fmt_name, av_get_sample_fmt_name, avcodec_get_name,
+    avcodec_profile_name, AVCodecParameters, AVColorTransferCharacteristic, AVPixelFormat,
+    AVSampleFormat, AV_NOPTS_VALUE, AV_TIME_BASE,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::CStr,
+    fs,
+    mem::transmute,
+    os::raw::c_char,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
+
+/// How many probe results are kept in memory. Beyond that, the least
+/// recently used ones are dropped, as library scans probe every file.
+const PROBE_CACHE_SIZE: usize = 1024;
+
+lazy_static! {
+    static ref PROBE_CACHE: Mutex<HashMap<PathBuf, CachedProbe>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug)]
+struct CachedProbe {
+    modified: SystemTime,
+    info: Arc<MediaInfo>,
+    used: Instant,
+}
+
+/// Everything ffmpeg can tell about a media file without decoding it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaInfo {
+    pub container: String,
+    pub container_long_name: String,
+    /// Duration in seconds.
+    pub duration: Option<f64>,
+    /// Overall bitrate in bits per second.
+    pub bit_rate: Option<i64>,
+    pub streams: Vec<StreamInfo>,
+    pub chapters: Vec<ChapterInfo>,
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StreamInfo {
+    pub index: usize,
+    pub kind: StreamKind,
+    pub codec: String,
+    pub profile: Option<String>,
+    pub bit_rate: Option<i64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub frame_rate: Option<f64>,
+    pub pixel_format: Option<String>,
+    pub color_transfer: Option<String>,
+    /// Whether the color transfer is one of the HDR ones (PQ or HLG).
+    pub hdr: bool,
+    pub channels: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub sample_format: Option<String>,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub default: bool,
+    pub forced: bool,
+    /// Whether the stream is a still image attached to the file, like an
+    /// audio file's cover art.
+    #[serde(default)]
+    pub attached_pic: bool,
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub enum StreamKind {
+    Video,
+    Audio,
+    Subtitle,
+    Data,
+    Attachment,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChapterInfo {
+    pub id: i64,
+    pub title: Option<String>,
+    /// Start time in seconds.
+    pub start: f64,
+    /// End time in seconds.
+    pub end: f64,
+}
+
+impl MediaInfo {
+    pub fn streams_of(&self, kind: StreamKind) -> impl Iterator<Item = &StreamInfo> {
+        self.streams
+            .iter()
+            .filter(move |stream| stream.kind == kind)
+    }
+}
+
+/// Probes a media file, re-using the previous result if the file has not
+/// been modified since. Results are kept in memory and in the cache
+/// directory, so they survive restarts.
+pub fn probe_cached(config: &Config, path: &Path) -> Result<Arc<MediaInfo>> {
+    let modified = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .chain_err(|| MediaProbeError(format!("Error reading metadata of {:?}", path).into()))?;
+
+    if let Some(cached) = PROBE_CACHE.lock().unwrap().get_mut(path) {
+        if cached.modified == modified {
+            cached.used = Instant::now();
+            return Ok(cached.info.clone());
+        }
+    }
+
+    let cache_path = AssetType::Probes
+        .dir(config)
+        .join(format!("{:016x}.json", cache_key(path, modified)));
+    let stored = Some(&cache_path)
+        .filter(|cache_path| cache::touch(cache_path))
+        .and_then(|cache_path| fs::read(cache_path).ok())
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+
+    let info = Arc::new(match stored {
+        Some(info) => info,
+        None => {
+            let info = probe(path)?;
+            let json = serde_json::to_vec(&info)
+                .chain_err(|| MediaProbeError("Error encoding probe result".into()))?;
+            cache::write(AssetType::Probes, &cache_path, path, modified, &json)?;
+            info
+        }
+    });
+    let mut probe_cache = PROBE_CACHE.lock().unwrap();
+    if probe_cache.len() >= PROBE_CACHE_SIZE && !probe_cache.contains_key(path) {
+        let oldest = probe_cache
+            .iter()
+            .min_by_key(|(_, cached)| cached.used)
+            .map(|(path, _)| path.clone());
+        if let Some(oldest) = oldest {
+            probe_cache.remove(&oldest);
+        }
+    }
+    probe_cache.insert(
+        path.to_path_buf(),
+        CachedProbe {
+            modified,
+            info: info.clone(),
+            used: Instant::now(),
+        },
+    );
+
+    Ok(info)
+}
+
+/// Opens a media file's format context and collects its stream information.
+pub fn probe(path: &Path) -> Result<MediaInfo> {
+    let input = format::input(&path)
+        .chain_err(|| MediaProbeError(format!("Error opening {:?}", path).into()))?;
+
+    let duration = input.duration();
+    let bit_rate = input.bit_rate();
+
+    Ok(MediaInfo {
+        container: input.format().name().to_string(),
+        container_long_name: input.format().description().to_string(),
+        duration: if duration == AV_NOPTS_VALUE || duration <= 0 {
+            None
+        } else {
+            Some(duration as f64 / AV_TIME_BASE as f64)
+        },
+        bit_rate: Some(bit_rate).filter(|bit_rate| *bit_rate > 0),
+        streams: input.streams().map(|stream| stream_info(&stream)).collect(),
+        chapters: input
+            .chapters()
+            .map(|chapter| ChapterInfo {
+                id: chapter.id(),
+                title: chapter.metadata().get("title").map(str::to_string),
+                start: to_seconds(chapter.start(), chapter.time_base()),
+                end: to_seconds(chapter.end(), chapter.time_base()),
+            })
+            .collect(),
+        tags: input
+            .metadata()
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    })
+}
+
+fn stream_info(stream: &Stream) -> StreamInfo {
+    let parameters = stream.parameters();
+    // The safe parameters wrapper only exposes the codec id and media type, so
+    // everything else is read directly from the AVCodecParameters.
+    let raw: &AVCodecParameters = unsafe { &*parameters.as_ptr() };
+
+    let kind = match parameters.medium() {
+        media::Type::Video => StreamKind::Video,
+        media::Type::Audio => StreamKind::Audio,
+        media::Type::Subtitle => StreamKind::Subtitle,
+        media::Type::Data => StreamKind::Data,
+        media::Type::Attachment => StreamKind::Attachment,
+        media::Type::Unknown => StreamKind::Unknown,
+    };
+    let is_video = kind == StreamKind::Video;
+    let is_audio = kind == StreamKind::Audio;
+
+    let frame_rate = stream.avg_frame_rate();
+    let metadata = stream.metadata();
+    let disposition = stream.disposition();
+
+    let color_transfer = raw.color_trc;
+
+    StreamInfo {
+        index: stream.index(),
+        kind,
+        codec: unsafe { c_string(avcodec_get_name(raw.codec_id)) }
+            .unwrap_or_else(|| "unknown".to_string()),
+        profile: unsafe { c_string(avcodec_profile_name(raw.codec_id, raw.profile)) },
+        bit_rate: Some(raw.bit_rate).filter(|bit_rate| *bit_rate > 0),
+        width: Some(raw.width as u32).filter(|_| is_video && raw.width > 0),
+        height: Some(raw.height as u32).filter(|_| is_video && raw.height > 0),
+        frame_rate: if is_video && frame_rate.denominator() != 0 && frame_rate.numerator() != 0 {
+            Some(f64::from(frame_rate))
+        } else {
+            None
+        },
+        // formats the linked libavutil knows but the bindings do not are no
+        // valid enum values
+        pixel_format: if is_video
+            && raw.format >= 0
+            && raw.format < AVPixelFormat::AV_PIX_FMT_NB as i32
+        {
+            unsafe {
+                c_string(av_get_pix_fmt_name(transmute::<i32, AVPixelFormat>(
+                    raw.format,
+                )))
+            }
+        } else {
+            None
+        },
+        color_transfer: if is_video
+            && color_transfer != AVColorTransferCharacteristic::AVCOL_TRC_UNSPECIFIED
+        {
+            unsafe { c_string(av_color_transfer_name(color_transfer)) }
+        } else {
+            None
+        },
+        hdr: is_video
+            && matches!(
+                color_transfer,
+                AVColorTransferCharacteristic::AVCOL_TRC_SMPTE2084
+                    | AVColorTransferCharacteristic::AVCOL_TRC_ARIB_STD_B67
+            ),
+        channels: Some(raw.channels as u32).filter(|_| is_audio && raw.channels > 0),
+        sample_rate: Some(raw.sample_rate as u32).filter(|_| is_audio && raw.sample_rate > 0),
+        sample_format: if is_audio
+            && raw.format >= 0
+            && raw.format < AVSampleFormat::AV_SAMPLE_FMT_NB as i32
+        {
+            unsafe {
+                c_string(av_get_sample_fmt_name(transmute::<i32, AVSampleFormat>(
+                    raw.format,
+                )))
+            }
+        } else {
+            None
+        },
+        language: metadata
+            .get("language")
+            .filter(|language| *language != "und")
+            .map(str::to_string),
+        title: metadata.get("title").map(str::to_string),
+        default: disposition.contains(Disposition::DEFAULT),
+        forced: disposition.contains(Disposition::FORCED),
+        attached_pic: disposition.contains(Disposition::ATTACHED_PIC),
+        tags: metadata
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+/// Converts a timestamp in the given time base into seconds.
+pub fn to_seconds(timestamp: i64, time_base: Rational) -> f64 {
+    timestamp as f64 * f64::from(time_base)
+}
+
+/// Copies a static C string from ffmpeg, treating null as absent.
+unsafe fn c_string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_string_lossy().to_string())
+    }
+}
@@ -0,0 +1,294 @@
+use crate::{
+    config::AudioCodec,
+    error::{
+        ErrorKind::{MediaProcessingError, NotFoundError},
+        Result, ResultExt,
+    },
+    media::{
+        jobs::Job,
+        loudness::gain_filter,
+        output::{ByteSink, CallbackOutput},
+        transcode::{AudioSettings, AudioTranscoder},
+    },
+};
+use ffmpeg4::{
+    format::{self, stream::Disposition},
+    media, Dictionary, Packet, Rational,
+};
+use ffmpeg4_sys::AV_TIME_BASE;
+use std::path::Path;
+
+/// Fragmented MP4 can be played while it is still being written, which a
+/// regular MP4 with its index at the end can not.
+const FRAGMENTED_MP4_FLAGS: &str = "frag_keyframe+empty_moov+default_base_moof";
+
+#[derive(Debug, Clone, Default)]
+pub struct RemuxOptions {
+    /// Offset into the file in seconds to start at.
+    pub start: f64,
+    /// Index of the audio stream to use. Takes precedence over `language`.
+    pub audio_stream: Option<usize>,
+    /// Language of the audio stream to use, as tagged in the file.
+    pub language: Option<String>,
+    /// Transcodes the audio stream instead of copying it.
+    pub audio: Option<AudioSettings>,
+    /// Gain in dB applied to transcoded audio.
+    pub gain: Option<f64>,
+}
+
+/// Where packets of an input stream go in the output.
+struct StreamMapping {
+    output_index: usize,
+    input_time_base: Rational,
+}
+
+/// Copies a file's video and audio streams into a fragmented MP4 stream.
+/// Video is never re-encoded, but audio can be transcoded for browsers that
+/// can not play the original codec.
+pub struct Remuxer {
+    input: format::context::Input,
+    output: CallbackOutput,
+    mappings: Vec<Option<StreamMapping>>,
+    audio: Option<AudioTranscoder>,
+    /// The input stream timestamps are lined up against, so the output starts
+    /// at 0 even when seeking.
+    primary_stream: usize,
+}
+
+impl Remuxer {
+    /// Opens a file and writes the output header. Playback starts at the
+    /// keyframe at or before the start offset.
+    pub fn open(path: &Path, options: &RemuxOptions, sink: ByteSink) -> Result<Remuxer> {
+        let mut input = format::input(&path)
+            .chain_err(|| MediaProcessingError(format!("Error opening {:?}", path).into()))?;
+
+        let video_stream = input
+            .streams()
+            .best(media::Type::Video)
+            .map(|stream| stream.index());
+        let audio_stream = select_audio_stream(&input, options)?;
+        let selected: Vec<usize> = video_stream.into_iter().chain(audio_stream).collect();
+        let primary_stream = *selected
+            .first()
+            .ok_or_else(|| MediaProcessingError(format!("No streams in {:?}", path).into()))?;
+
+        let mut output = CallbackOutput::new("mp4", sink)?;
+        let mut mappings: Vec<Option<StreamMapping>> = input.streams().map(|_| None).collect();
+        let mut audio = None;
+
+        for (output_index, input_index) in selected.into_iter().enumerate() {
+            let stream = input.stream(input_index).unwrap();
+
+            let transcoder = match &options.audio {
+                Some(settings) if Some(input_index) == audio_stream => Some(AudioTranscoder::new(
+                    &stream,
+                    &AudioSettings {
+                        global_header: true,
+                        ..settings.clone()
+                    },
+                    &gain_filter(options.gain),
+                )?),
+                _ => None,
+            };
+
+            let parameters = match &transcoder {
+                Some(transcoder) => ffmpeg4::codec::Parameters::from(transcoder.encoder()),
+                None => stream.parameters(),
+            };
+            let mut output_stream = output
+                .add_stream(parameters.id())
+                .chain_err(|| MediaProcessingError("Error adding output stream".into()))?;
+            output_stream.set_parameters(parameters);
+            // the input container's codec tag rarely means the same in MP4
+            unsafe {
+                (*output_stream.parameters().as_mut_ptr()).codec_tag = 0;
+            }
+
+            mappings[input_index] = Some(StreamMapping {
+                output_index,
+                input_time_base: stream.time_base(),
+            });
+            if transcoder.is_some() {
+                audio = transcoder;
+            }
+        }
+
+        if options.start > 0.0 {
+            let target = (options.start * AV_TIME_BASE as f64) as i64;
+            input.seek(target, ..target).chain_err(|| {
+                MediaProcessingError(format!("Error seeking to {}s", options.start).into())
+            })?;
+        }
+
+        let mut header_options = Dictionary::new();
+        header_options.set("movflags", FRAGMENTED_MP4_FLAGS);
+        if options
+            .audio
+            .as_ref()
+            .is_some_and(|settings| settings.codec == AudioCodec::Opus)
+        {
+            // Opus in MP4 is still considered experimental by older ffmpegs
+            header_options.set("strict", "experimental");
+        }
+        output.write_header_with(header_options).chain_err(|| {
+            MediaProcessingError(format!("Error writing MP4 header for {:?}", path).into())
+        })?;
+
+        Ok(Remuxer {
+            input,
+            output,
+            mappings,
+            audio,
+            primary_stream,
+        })
+    }
+
+    /// Whether audio is being transcoded, which makes remuxing worth running
+    /// as a job.
+    pub fn is_transcoding(&self) -> bool {
+        self.audio.is_some()
+    }
+
+    /// Copies packets until the end of the file, until the sink stops
+    /// accepting data or until the job, if any, is cancelled.
+    pub fn run(mut self, job: Option<&Job>) -> Result<()> {
+        match self.copy_packets(job) {
+            // the client going away is not an error
+            Err(_) if self.output.is_closed() => Ok(()),
+            result => result,
+        }
+    }
+
+    fn copy_packets(&mut self, job: Option<&Job>) -> Result<()> {
+        let Remuxer {
+            input,
+            output,
+            mappings,
+            audio,
+            primary_stream,
+        } = self;
+
+        // seconds subtracted from every timestamp, set by the first packet of
+        // the primary stream
+        let mut offset: Option<f64> = None;
+        let duration = input.duration() as f64 / AV_TIME_BASE as f64;
+
+        for (stream, mut packet) in input.packets() {
+            if job.is_some_and(Job::is_cancelled) {
+                return Ok(());
+            }
+
+            let mapping = match &mappings[stream.index()] {
+                Some(mapping) => mapping,
+                None => continue,
+            };
+            let time_base = mapping.input_time_base;
+
+            let offset_seconds = match offset {
+                Some(offset_seconds) => offset_seconds,
+                None if stream.index() == *primary_stream => {
+                    let first = packet.dts().or_else(|| packet.pts()).unwrap_or(0);
+                    *offset.insert(first as f64 * f64::from(time_base))
+                }
+                // nothing is written before the primary stream's first packet
+                None => continue,
+            };
+
+            if let (Some(job), Some(pts)) = (job, packet.pts()) {
+                if stream.index() == *primary_stream && duration > 0.0 {
+                    job.set_progress(pts as f64 * f64::from(time_base) / duration);
+                }
+            }
+
+            let shift = (offset_seconds / f64::from(time_base)).round() as i64;
+            if packet.pts().is_some_and(|pts| pts < shift) && stream.index() != *primary_stream {
+                continue;
+            }
+            packet.set_pts(packet.pts().map(|pts| pts - shift));
+            packet.set_dts(packet.dts().map(|dts| dts - shift));
+
+            let output_index = mapping.output_index;
+            match audio
+                .as_mut()
+                .filter(|audio| audio.stream_index() == stream.index())
+            {
+                Some(audio) => {
+                    let time_base = audio.time_base();
+                    audio.send_packet(&packet, &mut |packet| {
+                        write_packet(output, packet, output_index, time_base)
+                    })?;
+                }
+                None => write_packet(output, &mut packet, output_index, time_base)?,
+            }
+        }
+
+        if let Some(audio) = audio {
+            let output_index = mappings[audio.stream_index()]
+                .as_ref()
+                .unwrap()
+                .output_index;
+            let time_base = audio.time_base();
+            audio.finish(&mut |packet| write_packet(output, packet, output_index, time_base))?;
+        }
+
+        output
+            .write_trailer()
+            .chain_err(|| MediaProcessingError("Error writing MP4 trailer".into()))
+    }
+}
+
+/// Writes a packet with timestamps in `time_base` to an output stream.
+pub fn write_packet(
+    output: &mut format::context::Output,
+    packet: &mut Packet,
+    output_index: usize,
+    time_base: Rational,
+) -> Result<()> {
+    let output_time_base = output.stream(output_index).unwrap().time_base();
+    packet.set_stream(output_index);
+    packet.rescale_ts(time_base, output_time_base);
+    packet.set_position(-1);
+    packet
+        .write_interleaved(output)
+        .chain_err(|| MediaProcessingError("Error writing MP4 packet".into()))
+}
+
+/// Picks the audio stream by index or language, falling back to the one
+/// ffmpeg considers best.
+fn select_audio_stream(
+    input: &format::context::Input,
+    options: &RemuxOptions,
+) -> Result<Option<usize>> {
+    if let Some(index) = options.audio_stream {
+        return match input.stream(index) {
+            Some(stream) if stream.parameters().medium() == media::Type::Audio => Ok(Some(index)),
+            _ => bail!(NotFoundError),
+        };
+    }
+
+    if let Some(language) = &options.language {
+        let mut matching: Vec<_> = input
+            .streams()
+            .filter(|stream| stream.parameters().medium() == media::Type::Audio)
+            .filter(|stream| {
+                stream
+                    .metadata()
+                    .get("language")
+                    .is_some_and(|tag| tag.eq_ignore_ascii_case(language))
+            })
+            .map(|stream| (stream.index(), stream.disposition()))
+            .collect();
+        // the default track wins when there are several in the same language
+        matching.sort_by_key(|(_, disposition)| !disposition.contains(Disposition::DEFAULT));
+
+        return match matching.first() {
+            Some((index, _)) => Ok(Some(*index)),
+            None => bail!(NotFoundError),
+        };
+    }
+
+    Ok(input
+        .streams()
+        .best(media::Type::Audio)
+        .map(|stream| stream.index()))
+}
@@ -0,0 +1,251 @@
+use crate::{
+    config::{Config, ConfigSprites, ThumbnailFormat},
+    error::{ErrorKind::MediaProcessingError, Result, ResultExt},
+    media::{
+        cache::{self, cache_key, AssetType},
+        frame::{encode_image, fit_width, scale, VideoFrameReader},
+        jobs::{self, Job, JobKind, JobPriority},
+    },
+    util::vtt::vtt_timestamp,
+};
+use ffmpeg4::{format::Pixel, frame};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+pub const SPRITE_VTT_FILE_NAME: &str = "thumbnails.vtt";
+const RGB24_BYTES_PER_PIXEL: usize = 3;
+/// How long a failed generation is reported before it is tried again.
+const FAILED_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+lazy_static! {
+    static ref SPRITE_JOBS: Mutex<HashMap<String, Arc<SpriteProgress>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Tracks how far along a background sprite generation is.
+#[derive(Debug, Default)]
+pub struct SpriteProgress {
+    total: AtomicUsize,
+    done: AtomicUsize,
+    /// Why the generation failed, and when.
+    error: Mutex<Option<(String, Instant)>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum SpriteStatus {
+    /// The sprites are ready and can be found in the given cache directory.
+    Done {
+        key: String,
+    },
+    /// The sprites are being generated. Progress goes from 0 to 1.
+    Generating {
+        progress: f64,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+/// Gets the status of a video's seek-preview sprites, starting their
+/// generation in the background if they do not exist yet.
+pub fn sprites_status(config: &Config, path: &Path) -> Result<SpriteStatus> {
+    let modified = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .chain_err(|| {
+            MediaProcessingError(format!("Error reading metadata of {:?}", path).into())
+        })?;
+
+    let settings = &config.sprites;
+    let key = format!(
+        "{:016x}-{}-{}x{}x{}",
+        cache_key(path, modified),
+        settings.interval,
+        settings.tile_width,
+        settings.columns,
+        settings.rows
+    );
+    let sprite_dir = sprite_dir(config, &key);
+
+    if cache::touch(&sprite_dir) && sprite_dir.join(SPRITE_VTT_FILE_NAME).is_file() {
+        return Ok(SpriteStatus::Done { key });
+    }
+
+    let mut jobs = SPRITE_JOBS.lock().unwrap();
+    if let Some(progress) = jobs.get(&key) {
+        match progress.error.lock().unwrap().clone() {
+            Some((error, failed)) if failed.elapsed() < FAILED_RETRY_DELAY => {
+                return Ok(SpriteStatus::Failed { error });
+            }
+            // the file may have been fixed since, so it is tried again
+            Some(_) => {}
+            None => {
+                return Ok(SpriteStatus::Generating {
+                    progress: progress.fraction(),
+                })
+            }
+        }
+    }
+
+    let progress = Arc::new(SpriteProgress::default());
+    jobs.insert(key.clone(), progress.clone());
+
+    let job = jobs::submit(
+        JobKind::Sprites,
+        format!("{:?}", path),
+        JobPriority::Background,
+    );
+    let settings = settings.clone();
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        let result = job.wait_for_slot().and_then(|_slot| {
+            info!("Generating seek-preview sprites for {:?}", path);
+            generate(&settings, &path, &sprite_dir, &progress, &job)
+        });
+
+        match result {
+            Ok(()) => {
+                cache::insert(AssetType::Sprites, &sprite_dir, &path, modified);
+                SPRITE_JOBS.lock().unwrap().remove(&key);
+            }
+            // cancelled generations start over on the next request
+            Err(_) if job.is_cancelled() => {
+                SPRITE_JOBS.lock().unwrap().remove(&key);
+            }
+            Err(e) => {
+                e.log();
+                *progress.error.lock().unwrap() = Some((e.to_string(), Instant::now()));
+            }
+        }
+    });
+
+    Ok(SpriteStatus::Generating { progress: 0.0 })
+}
+
+/// The directory a set of sprites is stored in.
+pub fn sprite_dir(config: &Config, key: &str) -> PathBuf {
+    AssetType::Sprites.dir(config).join(key)
+}
+
+impl SpriteProgress {
+    fn fraction(&self) -> f64 {
+        let total = self.total.load(Ordering::Relaxed);
+        if total == 0 {
+            0.0
+        } else {
+            self.done.load(Ordering::Relaxed) as f64 / total as f64
+        }
+    }
+}
+
+/// Generates the sprite sheets and the WebVTT file describing them. Everything
+/// is written to a temporary directory that is moved into place once done.
+fn generate(
+    settings: &ConfigSprites,
+    path: &Path,
+    sprite_dir: &Path,
+    progress: &SpriteProgress,
+    job: &Job,
+) -> Result<()> {
+    let mut reader = VideoFrameReader::open(path)?;
+    let duration = reader
+        .duration()
+        .ok_or_else(|| MediaProcessingError(format!("Unknown duration of {:?}", path).into()))?;
+
+    let interval = settings.interval.max(1) as f64;
+    let tile_count = (duration / interval).ceil() as usize;
+    let tiles_per_sheet = (settings.columns * settings.rows).max(1) as usize;
+    let (tile_width, tile_height) = fit_width(reader.display_size(), settings.tile_width);
+    progress.total.store(tile_count, Ordering::Relaxed);
+
+    let temp_dir = sprite_dir.with_extension("tmp");
+    if temp_dir.exists() {
+        fs::remove_dir_all(&temp_dir)
+            .chain_err(|| MediaProcessingError(format!("Error cleaning {:?}", temp_dir).into()))?;
+    }
+    fs::create_dir_all(&temp_dir)
+        .chain_err(|| MediaProcessingError(format!("Error creating {:?}", temp_dir).into()))?;
+
+    let mut vtt = "WEBVTT\n".to_string();
+
+    for (sheet_index, first_tile) in (0..tile_count).step_by(tiles_per_sheet).enumerate() {
+        let sheet_tiles = tiles_per_sheet.min(tile_count - first_tile);
+        let columns = (settings.columns as usize).min(sheet_tiles);
+        let rows = sheet_tiles.div_ceil(columns);
+        let sheet_name = format!("sprite-{}.jpg", sheet_index);
+
+        let mut sheet = frame::Video::new(
+            Pixel::RGB24,
+            tile_width * columns as u32,
+            tile_height * rows as u32,
+        );
+        sheet.data_mut(0).iter_mut().for_each(|byte| *byte = 0);
+
+        for sheet_tile in 0..sheet_tiles {
+            job.check()?;
+
+            let tile = first_tile + sheet_tile;
+            let start = tile as f64 * interval;
+            let end = (start + interval).min(duration);
+            let x = (sheet_tile % columns) as u32 * tile_width;
+            let y = (sheet_tile / columns) as u32 * tile_height;
+
+            let frame = reader.frame_at(start + (end - start) / 2.0, false)?;
+            let scaled = scale(&frame, tile_width, tile_height, Pixel::RGB24)?;
+            draw_tile(&mut sheet, &scaled, x, y);
+
+            write!(
+                vtt,
+                "\n{} --> {}\n{}#xywh={},{},{},{}\n",
+                vtt_timestamp(start),
+                vtt_timestamp(end),
+                sheet_name,
+                x,
+                y,
+                tile_width,
+                tile_height
+            )
+            .unwrap();
+
+            progress.done.fetch_add(1, Ordering::Relaxed);
+            job.set_progress(progress.fraction());
+        }
+
+        let image = encode_image(&sheet, sheet.width(), sheet.height(), ThumbnailFormat::Jpeg)?;
+        fs::write(temp_dir.join(&sheet_name), image)
+            .chain_err(|| MediaProcessingError(format!("Error writing {}", sheet_name).into()))?;
+    }
+
+    fs::write(temp_dir.join(SPRITE_VTT_FILE_NAME), vtt)
+        .chain_err(|| MediaProcessingError("Error writing sprite WebVTT".into()))?;
+    fs::rename(&temp_dir, sprite_dir)
+        .chain_err(|| MediaProcessingError(format!("Error moving {:?}", temp_dir).into()))?;
+
+    Ok(())
+}
+
+/// Copies an RGB24 tile into an RGB24 sprite sheet.
+fn draw_tile(sheet: &mut frame::Video, tile: &frame::Video, x: u32, y: u32) {
+    let tile_stride = tile.stride(0);
+    let sheet_stride = sheet.stride(0);
+    let row_bytes = tile.width() as usize * RGB24_BYTES_PER_PIXEL;
+    let tile_data = tile.data(0);
+    let sheet_data = sheet.data_mut(0);
+
+    for row in 0..tile.height() as usize {
+        let source = row * tile_stride;
+        let destination = (y as usize + row) * sheet_stride + x as usize * RGB24_BYTES_PER_PIXEL;
+        sheet_data[destination..destination + row_bytes]
+            .copy_from_slice(&tile_data[source..source + row_bytes]);
+    }
+}
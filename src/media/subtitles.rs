@@ -0,0 +1,418 @@
+#[cfg(not(feature = "ffmpeg"))]
+use crate::error::ErrorKind::FeatureUnavailableError;
+#[cfg(feature = "ffmpeg")]
+use crate::media::{
+    jobs::{self, JobKind, JobPriority},
+    probe::{probe_cached, StreamKind},
+};
+use crate::{
+    config::Config,
+    error::{
+        ErrorKind::{MediaProcessingError, NotFoundError},
+        Result, ResultExt,
+    },
+    media::cache::{self, cache_key, AssetType},
+    util::vtt::{write_vtt, Cue},
+};
+use chardetng::EncodingDetector;
+use encoding_rs::Encoding;
+#[cfg(feature = "ffmpeg")]
+use ffmpeg4::{
+    codec,
+    codec::subtitle::{Rect, Subtitle},
+    format, media,
+};
+use regex::Regex;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+const SIDECAR_EXTENSIONS: &[&str] = &["srt", "ass", "ssa", "vtt"];
+/// Embedded subtitle codecs that are text, rather than images, and can be
+/// converted to WebVTT.
+#[cfg(feature = "ffmpeg")]
+const TEXT_SUBTITLE_CODECS: &[&str] =
+    &["subrip", "srt", "ass", "ssa", "mov_text", "webvtt", "text"];
+const EMBEDDED_ID_PREFIX: &str = "stream-";
+const SIDECAR_ID_PREFIX: &str = "file-";
+
+lazy_static! {
+    static ref SRT_TIMING_PATTERN: Regex = Regex::new(
+        r#"(\d+):(\d{1,2}):(\d{1,2})[,.](\d{1,3})\s*-->\s*(\d+):(\d{1,2}):(\d{1,2})[,.](\d{1,3})"#
+    )
+    .unwrap();
+    /// SRT allows HTML-ish tags, but WebVTT only knows a few of them.
+    static ref SRT_UNSUPPORTED_TAG_PATTERN: Regex =
+        Regex::new(r#"</?(font|span|br)[^>]*>"#).unwrap();
+    static ref ASS_OVERRIDE_PATTERN: Regex = Regex::new(r#"\{[^}]*\}"#).unwrap();
+    static ref LANGUAGE_PATTERN: Regex = Regex::new(r#"^[a-zA-Z]{2,3}(-[a-zA-Z]{2})?$"#).unwrap();
+}
+
+/// A subtitle track that can be converted to WebVTT.
+#[derive(Debug, Clone, Serialize)]
+pub struct SubtitleTrack {
+    /// Identifies the track in requests for its WebVTT conversion.
+    pub id: String,
+    pub language: Option<String>,
+    pub title: Option<String>,
+    pub format: String,
+    pub default: bool,
+    pub forced: bool,
+}
+
+/// Lists a video's subtitle tracks: sidecar files next to it and, with the
+/// ffmpeg feature, text subtitle streams inside it.
+pub fn find_tracks(config: &Config, path: &Path) -> Vec<SubtitleTrack> {
+    find_sidecars(config, path)
+        .into_iter()
+        .map(|(_, track)| track)
+        .chain(find_embedded(config, path))
+        .collect()
+}
+
+#[cfg(feature = "ffmpeg")]
+fn find_embedded(config: &Config, path: &Path) -> Vec<SubtitleTrack> {
+    match probe_cached(config, path) {
+        Ok(info) => info
+            .streams_of(StreamKind::Subtitle)
+            .filter(|stream| TEXT_SUBTITLE_CODECS.contains(&stream.codec.as_str()))
+            .map(|stream| SubtitleTrack {
+                id: format!("{}{}", EMBEDDED_ID_PREFIX, stream.index),
+                language: stream.language.clone(),
+                title: stream.title.clone(),
+                format: stream.codec.clone(),
+                default: stream.default,
+                forced: stream.forced,
+            })
+            .collect(),
+        Err(e) => {
+            debug!("Not listing embedded subtitles of {:?}: {}", path, e);
+            vec![]
+        }
+    }
+}
+
+#[cfg(not(feature = "ffmpeg"))]
+fn find_embedded(_config: &Config, _path: &Path) -> Vec<SubtitleTrack> {
+    vec![]
+}
+
+/// Finds subtitle files named after a video, like `Movie.srt` or
+/// `Movie.en.forced.srt`. Files that are not legal to serve are left out.
+fn find_sidecars(config: &Config, path: &Path) -> Vec<(PathBuf, SubtitleTrack)> {
+    let (dir, stem) = match (path.parent(), path.file_stem()) {
+        (Some(dir), Some(stem)) => (dir, stem.to_string_lossy().to_string()),
+        _ => return vec![],
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    let mut sidecars: Vec<(PathBuf, SubtitleTrack)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|sidecar| sidecar.is_file())
+        .filter(|sidecar| {
+            sidecar
+                .strip_prefix(&config.base_dir)
+                .is_ok_and(|relative| config.is_legal_path(&relative.to_string_lossy()))
+        })
+        .filter_map(|sidecar| {
+            let name = sidecar.file_name()?.to_string_lossy().to_string();
+            let extension = sidecar.extension()?.to_string_lossy().to_lowercase();
+            if !SIDECAR_EXTENSIONS.contains(&extension.as_str()) {
+                return None;
+            }
+
+            let middle = name
+                .strip_prefix(&stem)?
+                .strip_suffix(&format!(".{}", sidecar.extension()?.to_string_lossy()))?;
+            if !middle.is_empty() && !middle.starts_with('.') {
+                return None;
+            }
+            let parts: Vec<&str> = middle.split('.').filter(|part| !part.is_empty()).collect();
+
+            let track = SubtitleTrack {
+                id: format!("{}{}", SIDECAR_ID_PREFIX, name),
+                language: parts
+                    .iter()
+                    .find(|part| LANGUAGE_PATTERN.is_match(part))
+                    .map(|part| part.to_lowercase()),
+                title: Some(parts.join(" ")).filter(|title| !title.is_empty()),
+                format: extension,
+                default: parts
+                    .iter()
+                    .any(|part| part.eq_ignore_ascii_case("default")),
+                forced: parts.iter().any(|part| part.eq_ignore_ascii_case("forced")),
+            };
+
+            Some((sidecar, track))
+        })
+        .collect();
+    sidecars.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    sidecars
+}
+
+/// Gets the path of a subtitle track's WebVTT conversion in the cache,
+/// converting it first if needed.
+pub fn vtt_cached(config: &Config, path: &Path, track_id: &str) -> Result<PathBuf> {
+    // sidecars are keyed by their own path and modification time so editing
+    // one is picked up
+    let (source, stream_index) = if let Some(name) = track_id.strip_prefix(SIDECAR_ID_PREFIX) {
+        let (sidecar, _) = find_sidecars(config, path)
+            .into_iter()
+            .find(|(sidecar, _)| sidecar.file_name().is_some_and(|file| file == name))
+            .ok_or(NotFoundError)?;
+        (sidecar, None)
+    } else if let Some(index) = track_id.strip_prefix(EMBEDDED_ID_PREFIX) {
+        let index: usize = index.parse().map_err(|_| NotFoundError)?;
+        (path.to_path_buf(), Some(index))
+    } else {
+        bail!(NotFoundError)
+    };
+
+    let modified = source
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .chain_err(|| {
+            MediaProcessingError(format!("Error reading metadata of {:?}", source).into())
+        })?;
+    let cache_name = match stream_index {
+        Some(index) => format!("{:016x}-{}.vtt", cache_key(&source, modified), index),
+        None => format!("{:016x}.vtt", cache_key(&source, modified)),
+    };
+    let cache_path = AssetType::Subtitles.dir(config).join(cache_name);
+
+    if cache::touch(&cache_path) {
+        return Ok(cache_path);
+    }
+
+    let vtt = match stream_index {
+        Some(index) => convert_embedded(&source, index)?,
+        None => convert_sidecar(&source)?,
+    };
+
+    cache::write(
+        AssetType::Subtitles,
+        &cache_path,
+        &source,
+        modified,
+        vtt.as_bytes(),
+    )?;
+
+    Ok(cache_path)
+}
+
+fn convert_sidecar(path: &Path) -> Result<String> {
+    let bytes = fs::read(path)
+        .chain_err(|| MediaProcessingError(format!("Error reading {:?}", path).into()))?;
+    let text = decode_text(&bytes);
+
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("vtt") if text.trim_start().starts_with("WEBVTT") => Ok(text),
+        Some("ass") | Some("ssa") => Ok(write_vtt(&parse_ass(&text))),
+        _ => Ok(write_vtt(&parse_srt(&text))),
+    }
+}
+
+#[cfg(feature = "ffmpeg")]
+fn convert_embedded(path: &Path, index: usize) -> Result<String> {
+    let job = jobs::submit(
+        JobKind::Subtitles,
+        format!("{:?} (stream {})", path, index),
+        JobPriority::Normal,
+    );
+    let _slot = job.wait_for_slot()?;
+
+    let mut input = format::input(&path)
+        .chain_err(|| MediaProcessingError(format!("Error opening {:?}", path).into()))?;
+    let (time_base, mut decoder) = {
+        let stream = input
+            .stream(index)
+            .filter(|stream| stream.parameters().medium() == media::Type::Subtitle)
+            .ok_or(NotFoundError)?;
+        let decoder = codec::context::Context::from_parameters(stream.parameters())
+            .and_then(|context| context.decoder().subtitle())
+            .chain_err(|| MediaProcessingError("Error opening subtitle decoder".into()))?;
+        (stream.time_base(), decoder)
+    };
+
+    let mut cues = vec![];
+    for (stream, packet) in input.packets() {
+        job.check()?;
+        if stream.index() != index {
+            continue;
+        }
+
+        let mut subtitle = Subtitle::new();
+        if !decoder.decode(&packet, &mut subtitle).unwrap_or(false) {
+            continue;
+        }
+
+        let packet_start = packet.pts().unwrap_or(0) as f64 * f64::from(time_base);
+        let start = packet_start + subtitle.start() as f64 / 1000.0;
+        let end = if subtitle.end() > subtitle.start() {
+            packet_start + subtitle.end() as f64 / 1000.0
+        } else {
+            packet_start + packet.duration() as f64 * f64::from(time_base)
+        };
+
+        let text: Vec<String> = subtitle
+            .rects()
+            .filter_map(|rect| match rect {
+                Rect::Text(text) => Some(text.get().to_string()),
+                Rect::Ass(ass) => Some(ass_event_text(ass.get())),
+                _ => None,
+            })
+            .collect();
+
+        cues.push(Cue {
+            start,
+            end,
+            text: text.join("\n"),
+        });
+    }
+
+    Ok(write_vtt(&cues))
+}
+
+#[cfg(not(feature = "ffmpeg"))]
+fn convert_embedded(_path: &Path, _index: usize) -> Result<String> {
+    bail!(FeatureUnavailableError("ffmpeg"))
+}
+
+/// Decodes subtitle text, or other sidecar text files, using the byte order
+/// mark if there is one and guessing the encoding otherwise.
+pub fn decode_text(bytes: &[u8]) -> String {
+    let encoding = match Encoding::for_bom(bytes) {
+        Some((encoding, _)) => encoding,
+        None => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(bytes, true);
+            detector.guess(None, true)
+        }
+    };
+
+    let (text, _, _) = encoding.decode(bytes);
+    text.into_owned()
+}
+
+fn parse_srt(text: &str) -> Vec<Cue> {
+    let text = text.replace("\r\n", "\n");
+    let mut cues = vec![];
+
+    for block in text.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let timing = match lines
+            .next()
+            .and_then(|line| SRT_TIMING_PATTERN.captures(line))
+        {
+            Some(timing) => timing,
+            None => continue,
+        };
+        let time = |first: usize| {
+            let part = |i: usize| timing[first + i].parse::<f64>().unwrap_or(0.0);
+            let millis = &timing[first + 3];
+            part(0) * 3600.0
+                + part(1) * 60.0
+                + part(2)
+                + millis.parse::<f64>().unwrap_or(0.0) / 10f64.powi(millis.len() as i32)
+        };
+
+        let text = lines.collect::<Vec<_>>().join("\n");
+        let text = ASS_OVERRIDE_PATTERN.replace_all(&text, "");
+        cues.push(Cue {
+            start: time(1),
+            end: time(5),
+            text: SRT_UNSUPPORTED_TAG_PATTERN
+                .replace_all(&text, "")
+                .to_string(),
+        });
+    }
+
+    cues
+}
+
+fn parse_ass(text: &str) -> Vec<Cue> {
+    let mut in_events = false;
+    let mut format: Vec<String> = vec![];
+    let mut cues = vec![];
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_events = line.eq_ignore_ascii_case("[events]");
+            continue;
+        }
+        if !in_events {
+            continue;
+        }
+
+        if let Some(fields) = line.strip_prefix("Format:") {
+            format = fields
+                .split(',')
+                .map(|field| field.trim().to_lowercase())
+                .collect();
+        } else if let Some(fields) = line.strip_prefix("Dialogue:") {
+            if format.is_empty() {
+                continue;
+            }
+
+            // the text is the last field and may contain commas itself
+            let values: Vec<&str> = fields.trim_start().splitn(format.len(), ',').collect();
+            let field = |name: &str| {
+                format
+                    .iter()
+                    .position(|field| field == name)
+                    .and_then(|i| values.get(i))
+                    .copied()
+            };
+
+            if let (Some(start), Some(end), Some(text)) =
+                (field("start"), field("end"), field("text"))
+            {
+                cues.push(Cue {
+                    start: ass_time(start),
+                    end: ass_time(end),
+                    text: ass_text(text),
+                });
+            }
+        }
+    }
+
+    cues.sort_by(|a, b| a.start.total_cmp(&b.start));
+    cues
+}
+
+/// Parses an ASS timestamp (`h:mm:ss.cc`) into seconds.
+fn ass_time(time: &str) -> f64 {
+    time.trim().split(':').fold(0.0, |total, part| {
+        total * 60.0 + part.parse::<f64>().unwrap_or(0.0)
+    })
+}
+
+/// Strips ASS override blocks and turns its escapes into plain text.
+fn ass_text(text: &str) -> String {
+    ASS_OVERRIDE_PATTERN
+        .replace_all(text, "")
+        .replace("\\N", "\n")
+        .replace("\\n", "\n")
+        .replace("\\h", " ")
+}
+
+/// Gets the text out of an ASS event as ffmpeg's decoders produce them:
+/// `ReadOrder,Layer,Style,Name,MarginL,MarginR,MarginV,Effect,Text`.
+#[cfg(feature = "ffmpeg")]
+fn ass_event_text(event: &str) -> String {
+    let fields = if event.starts_with("Dialogue:") {
+        10
+    } else {
+        9
+    };
+    ass_text(event.splitn(fields, ',').last().unwrap_or(""))
+}
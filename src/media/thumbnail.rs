@@ -0,0 +1,105 @@
+use crate::{
+    config::{Config, ThumbnailFormat},
+    error::{ErrorKind::MediaProcessingError, Result, ResultExt},
+    media::{
+        cache::{self, cache_key, AssetType},
+        frame::{average_luma, encode_image, fit_width, VideoFrameReader},
+        jobs::Job,
+    },
+};
+use ffmpeg4::frame;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Frames darker than this average brightness count as black.
+const BLACK_FRAME_LUMA: f64 = 24.0;
+/// How much further into the video to look when a frame is black.
+const BLACK_FRAME_SKIP_PERCENT: f64 = 5.0;
+const MAX_FRAME_ATTEMPTS: usize = 5;
+
+/// Gets the path of a file's thumbnail in the cache, generating it first if
+/// needed. The job only waits for a slot when the thumbnail has to be
+/// generated.
+pub fn thumbnail_cached(
+    config: &Config,
+    path: &Path,
+    width: u32,
+    format: ThumbnailFormat,
+    job: &Arc<Job>,
+) -> Result<PathBuf> {
+    let modified = path
+        .metadata()
+        .and_then(|metadata| metadata.modified())
+        .chain_err(|| {
+            MediaProcessingError(format!("Error reading metadata of {:?}", path).into())
+        })?;
+
+    let cache_path = AssetType::Thumbnails.dir(config).join(format!(
+        "{:016x}-{}.{}",
+        cache_key(path, modified),
+        width,
+        format.extension()
+    ));
+
+    if cache::touch(&cache_path) {
+        return Ok(cache_path);
+    }
+
+    let image = {
+        let _slot = job.wait_for_slot()?;
+        generate(config, path, width, format)?
+    };
+    cache::write(AssetType::Thumbnails, &cache_path, path, modified, &image)?;
+
+    Ok(cache_path)
+}
+
+/// Decodes a representative frame from a video and encodes it as an image.
+pub fn generate(
+    config: &Config,
+    path: &Path,
+    width: u32,
+    format: ThumbnailFormat,
+) -> Result<Vec<u8>> {
+    let settings = &config.thumbnails;
+    let mut reader = VideoFrameReader::open(path)?;
+    let duration = reader.duration().unwrap_or(0.0);
+
+    let attempts = if settings.avoid_black_frames {
+        MAX_FRAME_ATTEMPTS
+    } else {
+        1
+    };
+
+    // If every frame tried is black, the brightest one is used.
+    let mut best: Option<(f64, frame::Video)> = None;
+    for attempt in 0..attempts {
+        let percent = settings.seek_percent + attempt as f64 * BLACK_FRAME_SKIP_PERCENT;
+        if attempt > 0 && percent >= 100.0 {
+            break;
+        }
+
+        let frame = reader.frame_at(duration * percent.clamp(0.0, 100.0) / 100.0, false)?;
+        let luma = if settings.avoid_black_frames {
+            average_luma(&frame)?
+        } else {
+            BLACK_FRAME_LUMA
+        };
+
+        if best.as_ref().is_none_or(|(best_luma, _)| luma > *best_luma) {
+            best = Some((luma, frame));
+        }
+
+        if luma >= BLACK_FRAME_LUMA {
+            break;
+        }
+    }
+
+    let (_, frame) =
+        best.ok_or_else(|| MediaProcessingError(format!("No frame in {:?}", path).into()))?;
+    let (width, height) = fit_width(reader.display_size(), width);
+
+    encode_image(&frame, width, height, format)
+}
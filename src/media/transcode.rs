@@ -0,0 +1,465 @@
+use crate::{
+    config::AudioCodec,
+    error::{ErrorKind::MediaProcessingError, Result, ResultExt},
+};
+use ffmpeg4::{
+    codec, decoder, encoder,
+    format::{stream::Stream, Pixel, Sample},
+    frame, picture, ChannelLayout, Dictionary, Packet, Rational,
+};
+use ffmpeg4_sys::AVPixelFormat;
+
+const X264_PRESET: &str = "veryfast";
+const AUDIO_SAMPLE_RATE: i32 = 48000;
+/// Default audio bitrates in bits per second per channel.
+const AAC_CHANNEL_BIT_RATE: usize = 96_000;
+const OPUS_CHANNEL_BIT_RATE: usize = 64_000;
+
+/// Receives encoded packets, with timestamps in the transcoder's time base.
+pub type PacketSink<'a> = dyn FnMut(&mut Packet) -> Result<()> + 'a;
+
+#[derive(Debug, Clone)]
+pub struct VideoSettings {
+    pub width: u32,
+    pub height: u32,
+    /// Target bitrate in bits per second.
+    pub bit_rate: usize,
+    /// Keyframes are forced at every multiple of this many seconds, which is
+    /// what lets HLS segments start cleanly.
+    pub keyframe_interval: Option<f64>,
+    pub global_header: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct AudioSettings {
+    pub codec: AudioCodec,
+    /// Channels to mix down to, or `None` to keep the source's channel count.
+    pub channels: Option<u32>,
+    /// Target bitrate in bits per second, or `None` for a default based on the
+    /// channel count.
+    pub bit_rate: Option<usize>,
+    pub global_header: bool,
+}
+
+/// Decodes a video stream, scales it and re-encodes it as H.264.
+pub struct VideoTranscoder {
+    stream_index: usize,
+    decoder: decoder::Video,
+    filter: ffmpeg4::filter::Graph,
+    encoder: encoder::video::Encoder,
+    time_base: Rational,
+    keyframe_interval: Option<f64>,
+    next_keyframe: f64,
+}
+
+impl VideoTranscoder {
+    pub fn new(stream: &Stream, settings: &VideoSettings) -> Result<VideoTranscoder> {
+        let decoder = codec::context::Context::from_parameters(stream.parameters())
+            .and_then(|context| context.decoder().video())
+            .chain_err(|| MediaProcessingError("Error opening video decoder".into()))?;
+        let time_base = stream.time_base();
+
+        let codec = encoder::find_by_name("libx264")
+            .or_else(|| encoder::find(codec::Id::H264))
+            .ok_or_else(|| MediaProcessingError("No H.264 encoder".into()))?;
+
+        let mut context = codec::context::Context::new()
+            .encoder()
+            .video()
+            .chain_err(|| MediaProcessingError("Error creating video encoder".into()))?;
+        context.set_width(settings.width);
+        context.set_height(settings.height);
+        context.set_format(Pixel::YUV420P);
+        context.set_time_base(time_base);
+        context.set_frame_rate(Some(stream.avg_frame_rate()));
+        context.set_bit_rate(settings.bit_rate);
+        context.set_max_bit_rate(settings.bit_rate * 3 / 2);
+        if settings.global_header {
+            context.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let mut options = Dictionary::new();
+        options.set("preset", X264_PRESET);
+        options.set("forced-idr", "1");
+        let encoder = context
+            .open_as_with(codec, options)
+            .chain_err(|| MediaProcessingError("Error opening video encoder".into()))?;
+
+        let filter = video_filter(&decoder, time_base, settings.width, settings.height)?;
+
+        Ok(VideoTranscoder {
+            stream_index: stream.index(),
+            decoder,
+            filter,
+            encoder,
+            time_base,
+            keyframe_interval: settings.keyframe_interval,
+            next_keyframe: 0.0,
+        })
+    }
+
+    pub fn stream_index(&self) -> usize {
+        self.stream_index
+    }
+
+    pub fn time_base(&self) -> Rational {
+        self.time_base
+    }
+
+    pub fn encoder(&self) -> &encoder::video::Encoder {
+        &self.encoder
+    }
+
+    /// Decodes a packet from the input and sends any resulting encoded
+    /// packets to `sink`.
+    pub fn send_packet(&mut self, packet: &Packet, sink: &mut PacketSink) -> Result<()> {
+        if self.decoder.send_packet(packet).is_err() {
+            // damaged packets are skipped, the decoder will have logged the details
+            return Ok(());
+        }
+
+        self.receive_frames(sink)
+    }
+
+    /// Flushes everything still buffered in the decoder, filter and encoder.
+    pub fn finish(&mut self, sink: &mut PacketSink) -> Result<()> {
+        self.decoder
+            .send_eof()
+            .chain_err(|| MediaProcessingError("Error flushing video decoder".into()))?;
+        self.receive_frames(sink)?;
+
+        self.filter
+            .get("in")
+            .unwrap()
+            .source()
+            .flush()
+            .chain_err(|| MediaProcessingError("Error flushing video filter".into()))?;
+        self.receive_filtered(sink)?;
+
+        self.encoder
+            .send_eof()
+            .chain_err(|| MediaProcessingError("Error flushing video encoder".into()))?;
+        self.receive_packets(sink)
+    }
+
+    fn receive_frames(&mut self, sink: &mut PacketSink) -> Result<()> {
+        let mut frame = frame::Video::empty();
+        while self.decoder.receive_frame(&mut frame).is_ok() {
+            frame.set_pts(frame.timestamp());
+            self.filter
+                .get("in")
+                .unwrap()
+                .source()
+                .add(&frame)
+                .chain_err(|| MediaProcessingError("Error filtering video".into()))?;
+            self.receive_filtered(sink)?;
+        }
+
+        Ok(())
+    }
+
+    fn receive_filtered(&mut self, sink: &mut PacketSink) -> Result<()> {
+        let mut filtered = frame::Video::empty();
+        while self
+            .filter
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut filtered)
+            .is_ok()
+        {
+            filtered.set_kind(picture::Type::None);
+
+            if let (Some(interval), Some(pts)) = (self.keyframe_interval, filtered.pts()) {
+                let seconds = pts as f64 * f64::from(self.time_base);
+                if seconds >= self.next_keyframe {
+                    filtered.set_kind(picture::Type::I);
+                    self.next_keyframe = ((seconds / interval).floor() + 1.0) * interval;
+                }
+            }
+
+            self.encoder
+                .send_frame(&filtered)
+                .chain_err(|| MediaProcessingError("Error encoding video".into()))?;
+            self.receive_packets(sink)?;
+        }
+
+        Ok(())
+    }
+
+    fn receive_packets(&mut self, sink: &mut PacketSink) -> Result<()> {
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            sink(&mut packet)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes an audio stream, resamples and remixes it and re-encodes it as AAC
+/// or Opus.
+pub struct AudioTranscoder {
+    stream_index: usize,
+    decoder: decoder::Audio,
+    filter: ffmpeg4::filter::Graph,
+    encoder: encoder::audio::Encoder,
+    time_base: Rational,
+}
+
+impl AudioTranscoder {
+    /// Creates an audio transcoder. `filter_spec` is an ffmpeg audio filter
+    /// chain applied before encoding, `anull` if nothing needs doing.
+    pub fn new(
+        stream: &Stream,
+        settings: &AudioSettings,
+        filter_spec: &str,
+    ) -> Result<AudioTranscoder> {
+        let decoder = codec::context::Context::from_parameters(stream.parameters())
+            .and_then(|context| context.decoder().audio())
+            .chain_err(|| MediaProcessingError("Error opening audio decoder".into()))?;
+
+        let codec = match settings.codec {
+            AudioCodec::Aac => encoder::find(codec::Id::AAC),
+            AudioCodec::Opus => {
+                encoder::find_by_name("libopus").or_else(|| encoder::find(codec::Id::OPUS))
+            }
+        }
+        .ok_or_else(|| MediaProcessingError(format!("No {:?} encoder", settings.codec).into()))?
+        .audio()
+        .chain_err(|| MediaProcessingError("Audio encoder is not an audio encoder".into()))?;
+
+        let channels = settings
+            .channels
+            .map(|channels| channels as i32)
+            .unwrap_or_else(|| decoder.channels() as i32)
+            .clamp(1, 8);
+        let channel_layout = ChannelLayout::default(channels);
+        let sample_format = codec
+            .formats()
+            .and_then(|mut formats| formats.next())
+            .unwrap_or(Sample::F32(ffmpeg4::format::sample::Type::Planar));
+
+        let mut context = codec::context::Context::new()
+            .encoder()
+            .audio()
+            .chain_err(|| MediaProcessingError("Error creating audio encoder".into()))?;
+        context.set_rate(AUDIO_SAMPLE_RATE);
+        context.set_channel_layout(channel_layout);
+        context.set_channels(channels);
+        context.set_format(sample_format);
+        context.set_bit_rate(settings.bit_rate.unwrap_or_else(|| {
+            let channel_bit_rate = match settings.codec {
+                AudioCodec::Aac => AAC_CHANNEL_BIT_RATE,
+                AudioCodec::Opus => OPUS_CHANNEL_BIT_RATE,
+            };
+            channel_bit_rate * channels as usize
+        }));
+        context.set_time_base((1, AUDIO_SAMPLE_RATE));
+        if settings.global_header {
+            context.set_flags(codec::Flags::GLOBAL_HEADER);
+        }
+
+        let encoder = context
+            .open_as(codec)
+            .chain_err(|| MediaProcessingError("Error opening audio encoder".into()))?;
+
+        let mut filter = audio_filter(&decoder, stream.time_base(), &encoder, filter_spec)?;
+        let time_base = filter.get("out").unwrap().sink().time_base();
+
+        Ok(AudioTranscoder {
+            stream_index: stream.index(),
+            decoder,
+            filter,
+            encoder,
+            time_base,
+        })
+    }
+
+    pub fn stream_index(&self) -> usize {
+        self.stream_index
+    }
+
+    pub fn time_base(&self) -> Rational {
+        self.time_base
+    }
+
+    pub fn encoder(&self) -> &encoder::audio::Encoder {
+        &self.encoder
+    }
+
+    /// Decodes a packet from the input and sends any resulting encoded
+    /// packets to `sink`.
+    pub fn send_packet(&mut self, packet: &Packet, sink: &mut PacketSink) -> Result<()> {
+        if self.decoder.send_packet(packet).is_err() {
+            return Ok(());
+        }
+
+        self.receive_frames(sink)
+    }
+
+    /// Flushes everything still buffered in the decoder, filter and encoder.
+    pub fn finish(&mut self, sink: &mut PacketSink) -> Result<()> {
+        self.decoder
+            .send_eof()
+            .chain_err(|| MediaProcessingError("Error flushing audio decoder".into()))?;
+        self.receive_frames(sink)?;
+
+        self.filter
+            .get("in")
+            .unwrap()
+            .source()
+            .flush()
+            .chain_err(|| MediaProcessingError("Error flushing audio filter".into()))?;
+        self.receive_filtered(sink)?;
+
+        self.encoder
+            .send_eof()
+            .chain_err(|| MediaProcessingError("Error flushing audio encoder".into()))?;
+        self.receive_packets(sink)
+    }
+
+    fn receive_frames(&mut self, sink: &mut PacketSink) -> Result<()> {
+        let mut frame = frame::Audio::empty();
+        while self.decoder.receive_frame(&mut frame).is_ok() {
+            frame.set_pts(frame.timestamp());
+            self.filter
+                .get("in")
+                .unwrap()
+                .source()
+                .add(&frame)
+                .chain_err(|| MediaProcessingError("Error filtering audio".into()))?;
+            self.receive_filtered(sink)?;
+        }
+
+        Ok(())
+    }
+
+    fn receive_filtered(&mut self, sink: &mut PacketSink) -> Result<()> {
+        let mut filtered = frame::Audio::empty();
+        while self
+            .filter
+            .get("out")
+            .unwrap()
+            .sink()
+            .frame(&mut filtered)
+            .is_ok()
+        {
+            self.encoder
+                .send_frame(&filtered)
+                .chain_err(|| MediaProcessingError("Error encoding audio".into()))?;
+            self.receive_packets(sink)?;
+        }
+
+        Ok(())
+    }
+
+    fn receive_packets(&mut self, sink: &mut PacketSink) -> Result<()> {
+        let mut packet = Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            sink(&mut packet)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn video_filter(
+    decoder: &decoder::Video,
+    time_base: Rational,
+    width: u32,
+    height: u32,
+) -> Result<ffmpeg4::filter::Graph> {
+    let aspect_ratio = decoder.aspect_ratio();
+    let args = format!(
+        "video_size={}x{}:pix_fmt={}:time_base={}:pixel_aspect={}",
+        decoder.width(),
+        decoder.height(),
+        AVPixelFormat::from(decoder.format()) as i32,
+        time_base,
+        if aspect_ratio.numerator() > 0 {
+            aspect_ratio
+        } else {
+            Rational(1, 1)
+        }
+    );
+    let spec = format!("scale={}:{},setsar=1,format=yuv420p", width, height);
+
+    build_filter("buffer", "buffersink", &args, &spec, |_| {})
+}
+
+fn audio_filter(
+    decoder: &decoder::Audio,
+    time_base: Rational,
+    encoder: &encoder::audio::Encoder,
+    spec: &str,
+) -> Result<ffmpeg4::filter::Graph> {
+    let args = audio_source_args(decoder, time_base);
+
+    let variable_frame_size = encoder.codec().is_some_and(|codec| {
+        codec
+            .capabilities()
+            .contains(codec::capabilities::Capabilities::VARIABLE_FRAME_SIZE)
+    });
+    let frame_size = encoder.frame_size();
+
+    build_filter("abuffer", "abuffersink", &args, spec, |out| {
+        out.set_sample_format(encoder.format());
+        out.set_channel_layout(encoder.channel_layout());
+        out.set_sample_rate(encoder.rate());
+        if !variable_frame_size && frame_size > 0 {
+            out.sink().set_frame_size(frame_size);
+        }
+    })
+}
+
+/// The `abuffer` arguments describing a decoder's output.
+pub fn audio_source_args(decoder: &decoder::Audio, time_base: Rational) -> String {
+    let channel_layout = if decoder.channel_layout().is_empty() {
+        ChannelLayout::default(decoder.channels() as i32)
+    } else {
+        decoder.channel_layout()
+    };
+
+    format!(
+        "time_base={}:sample_rate={}:sample_fmt={}:channel_layout=0x{:x}",
+        time_base,
+        decoder.rate(),
+        decoder.format().name(),
+        channel_layout.bits()
+    )
+}
+
+/// Builds a filter graph from a source filter named `in`, through `spec`, to
+/// a sink filter named `out`.
+pub fn build_filter(
+    source: &str,
+    sink: &str,
+    args: &str,
+    spec: &str,
+    configure_out: impl FnOnce(&mut ffmpeg4::filter::Context),
+) -> Result<ffmpeg4::filter::Graph> {
+    let mut filter = ffmpeg4::filter::Graph::new();
+    let source_filter = ffmpeg4::filter::find(source)
+        .ok_or_else(|| MediaProcessingError(format!("No {} filter", source).into()))?;
+    let sink_filter = ffmpeg4::filter::find(sink)
+        .ok_or_else(|| MediaProcessingError(format!("No {} filter", sink).into()))?;
+
+    filter
+        .add(&source_filter, "in", args)
+        .and_then(|_| filter.add(&sink_filter, "out", ""))
+        .chain_err(|| MediaProcessingError("Error creating filter graph".into()))?;
+
+    configure_out(&mut filter.get("out").unwrap());
+
+    filter
+        .output("in", 0)
+        .and_then(|parser| parser.input("out", 0))
+        .and_then(|parser| parser.parse(spec))
+        .and_then(|_| filter.validate())
+        .chain_err(|| {
+            MediaProcessingError(format!("Error configuring filter {:?}", spec).into())
+        })?;
+
+    Ok(filter)
+}
@@ -0,0 +1,178 @@
+use crate::error::{ErrorKind::MetadataLoadError, Result, ResultExt};
+use exif::{DateTime, Exif, In, Reader, Tag, Value};
+use std::{fs::File, io::BufReader, path::Path};
+
+/// Orientations from 5 to 8 are rotated by 90 degrees, which swaps the
+/// stored width and height.
+const FIRST_ROTATED_ORIENTATION: u32 = 5;
+
+/// The interesting parts of a photo's EXIF data.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExifData {
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens: Option<String>,
+    /// When the photo was taken as the camera recorded it, in ISO 8601. This
+    /// only has a time zone if the camera recorded one.
+    pub taken_at: Option<String>,
+    /// The size the photo is displayed at, after orientation is applied.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// How the stored image has to be rotated and flipped for display, from 1
+    /// to 8 as EXIF defines it.
+    pub orientation: Option<u32>,
+    pub gps: Option<GpsPosition>,
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+pub struct GpsPosition {
+    /// Degrees north.
+    pub latitude: f64,
+    /// Degrees east.
+    pub longitude: f64,
+    /// Meters above sea level.
+    pub altitude: Option<f64>,
+}
+
+/// Reads the EXIF data of a JPEG, TIFF, HEIF, PNG or WebP file. Files without
+/// any give `None`.
+pub fn load(path: &Path) -> Result<Option<ExifData>> {
+    let file = File::open(path)
+        .chain_err(|| MetadataLoadError(format!("Error opening {:?}", path).into()))?;
+    let exif = match Reader::new().read_from_container(&mut BufReader::new(file)) {
+        Ok(exif) => exif,
+        Err(exif::Error::NotFound(_)) => return Ok(None),
+        Err(e) => {
+            return Err(e).chain_err(|| {
+                MetadataLoadError(format!("Error reading EXIF data of {:?}", path).into())
+            })
+        }
+    };
+
+    let orientation = uint(&exif, Tag::Orientation);
+    let width = uint(&exif, Tag::PixelXDimension).or_else(|| uint(&exif, Tag::ImageWidth));
+    let height = uint(&exif, Tag::PixelYDimension).or_else(|| uint(&exif, Tag::ImageLength));
+    let (width, height) = if orientation.is_some_and(|o| o >= FIRST_ROTATED_ORIENTATION) {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    Ok(Some(ExifData {
+        camera_make: ascii(&exif, Tag::Make),
+        camera_model: ascii(&exif, Tag::Model),
+        lens: ascii(&exif, Tag::LensModel),
+        taken_at: taken_at(&exif),
+        width,
+        height,
+        orientation,
+        gps: gps(&exif),
+    }))
+}
+
+/// Reads just a file's orientation, 1 (as stored) if it has none.
+#[cfg(feature = "ffmpeg")]
+pub fn orientation(path: &Path) -> u32 {
+    load(path)
+        .ok()
+        .flatten()
+        .and_then(|exif| exif.orientation)
+        .unwrap_or(1)
+}
+
+fn taken_at(exif: &Exif) -> Option<String> {
+    let field = exif
+        .get_field(Tag::DateTimeOriginal, In::PRIMARY)
+        .or_else(|| exif.get_field(Tag::DateTime, In::PRIMARY))?;
+    let mut date_time = match &field.value {
+        Value::Ascii(values) => DateTime::from_ascii(values.first()?).ok()?,
+        _ => return None,
+    };
+    if let Some(Value::Ascii(values)) = exif
+        .get_field(Tag::OffsetTimeOriginal, In::PRIMARY)
+        .map(|field| &field.value)
+    {
+        if let Some(offset) = values.first() {
+            let _ = date_time.parse_offset(offset);
+        }
+    }
+
+    let mut taken_at = format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        date_time.year,
+        date_time.month,
+        date_time.day,
+        date_time.hour,
+        date_time.minute,
+        date_time.second
+    );
+    if let Some(offset) = date_time.offset {
+        let sign = if offset < 0 { '-' } else { '+' };
+        taken_at.push_str(&format!(
+            "{}{:02}:{:02}",
+            sign,
+            offset.abs() / 60,
+            offset.abs() % 60
+        ));
+    }
+
+    Some(taken_at)
+}
+
+fn gps(exif: &Exif) -> Option<GpsPosition> {
+    let coordinate = |tag: Tag, ref_tag: Tag, negative_ref: &str| {
+        let degrees = match rationals(exif, tag)?.as_slice() {
+            [degrees, minutes, seconds] => degrees + minutes / 60.0 + seconds / 3600.0,
+            [degrees, ..] => *degrees,
+            [] => return None,
+        };
+
+        if ascii(exif, ref_tag).is_some_and(|value| value.eq_ignore_ascii_case(negative_ref)) {
+            Some(-degrees)
+        } else {
+            Some(degrees)
+        }
+    };
+
+    let altitude = rationals(exif, Tag::GPSAltitude)
+        .and_then(|values| values.first().copied())
+        .map(|altitude| {
+            // a reference of 1 means below sea level
+            if uint(exif, Tag::GPSAltitudeRef) == Some(1) {
+                -altitude
+            } else {
+                altitude
+            }
+        });
+
+    Some(GpsPosition {
+        latitude: coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")?,
+        longitude: coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")?,
+        altitude,
+    })
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|value| {
+                String::from_utf8_lossy(value)
+                    .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+                    .to_string()
+            })
+            .filter(|value| !value.is_empty()),
+        _ => None,
+    }
+}
+
+fn uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+fn rationals(exif: &Exif, tag: Tag) -> Option<Vec<f64>> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Rational(values) => Some(values.iter().map(|value| value.to_f64()).collect()),
+        _ => None,
+    }
+}
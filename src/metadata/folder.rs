@@ -0,0 +1,70 @@
+use crate::error::{ErrorKind::MetadataLoadError, Result, ResultExt};
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+pub const FOLDER_METADATA_FILE_NAME: &str = "folder.toml";
+
+/// Optional per-directory metadata, read from a `folder.toml` inside the
+/// directory.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FolderMetadata {
+    /// Title displayed instead of the directory's name.
+    pub title: Option<String>,
+    /// Markdown description of the directory.
+    pub description: Option<String>,
+    /// How the directory's children are ordered.
+    #[serde(rename = "sort-order", default)]
+    pub sort_order: SortOrder,
+    /// Cover image path, relative to the directory.
+    pub cover: Option<String>,
+    /// Names of children that are left out of the directory's listing. Hidden
+    /// children can still be accessed directly.
+    #[serde(default)]
+    pub hidden: Vec<String>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default, Deserialize)]
+pub enum SortOrder {
+    #[default]
+    #[serde(rename = "name")]
+    Name,
+    #[serde(rename = "name-descending")]
+    NameDescending,
+    #[serde(rename = "newest")]
+    Newest,
+    #[serde(rename = "oldest")]
+    Oldest,
+}
+
+impl FolderMetadata {
+    pub fn is_hidden(&self, child_name: &str) -> bool {
+        self.hidden.iter().any(|hidden| hidden == child_name)
+    }
+}
+
+/// Checks whether a path refers to a folder metadata file.
+pub fn is_folder_metadata_file(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name == FOLDER_METADATA_FILE_NAME)
+}
+
+/// Finds the folder metadata file inside a directory.
+pub fn find_for_directory(dir_path: &Path) -> Option<PathBuf> {
+    Some(dir_path.join(FOLDER_METADATA_FILE_NAME)).filter(|path| path.is_file())
+}
+
+/// Loads and parses a folder metadata file.
+pub fn load(folder_path: &Path) -> Result<FolderMetadata> {
+    let mut folder_file = File::open(folder_path)
+        .chain_err(|| MetadataLoadError(format!("Error opening {:?}", folder_path).into()))?;
+    let mut folder_string = String::new();
+    folder_file
+        .read_to_string(&mut folder_string)
+        .chain_err(|| MetadataLoadError(format!("Error reading {:?}", folder_path).into()))?;
+
+    toml::from_str(&folder_string)
+        .chain_err(|| MetadataLoadError(format!("Error decoding {:?}", folder_path).into()))
+}
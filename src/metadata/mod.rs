@@ -0,0 +1,4 @@
+pub mod exif;
+pub mod folder;
+pub mod nfo;
+pub mod playlist;
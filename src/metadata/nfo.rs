@@ -0,0 +1,185 @@
+use crate::error::{ErrorKind::MetadataLoadError, Result, ResultExt};
+use regex::Regex;
+use roxmltree::{Document, Node};
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+const NFO_EXTENSION: &str = "nfo";
+const MOVIE_NFO: &str = "movie.nfo";
+const TVSHOW_NFO: &str = "tvshow.nfo";
+
+lazy_static! {
+    static ref XML_DECLARATION_PATTERN: Regex = Regex::new(r#"<\?xml[^>]*\?>"#).unwrap();
+}
+
+/// Metadata read from a Kodi-style `.nfo` file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Nfo {
+    pub kind: NfoKind,
+    pub title: Option<String>,
+    pub original_title: Option<String>,
+    pub year: Option<i32>,
+    pub plot: Option<String>,
+    pub genres: Vec<String>,
+    pub season: Option<u32>,
+    pub episode: Option<u32>,
+    /// Runtime in minutes.
+    pub runtime: Option<u32>,
+    pub artwork: Vec<NfoArtwork>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+pub enum NfoKind {
+    Movie,
+    TvShow,
+    Episode,
+}
+
+/// An artwork reference. The `url` is whatever the `.nfo` file contained,
+/// which is either a remote URL or a path relative to the `.nfo` file's
+/// directory.
+#[derive(Debug, Clone, Serialize)]
+pub struct NfoArtwork {
+    pub aspect: Option<String>,
+    pub url: String,
+}
+
+impl NfoArtwork {
+    pub fn is_remote(&self) -> bool {
+        self.url.starts_with("http://") || self.url.starts_with("https://")
+    }
+}
+
+/// Finds the `.nfo` file describing a media file, which is the file's name
+/// with its extension replaced by `.nfo`. A `movie.nfo` or `tvshow.nfo`
+/// describes the whole directory instead, even next to a `movie.mkv`, see
+/// [`find_for_directory`].
+pub fn find_for_file(file_path: &Path) -> Option<PathBuf> {
+    Some(file_path.with_extension(NFO_EXTENSION)).filter(|nfo_path| {
+        nfo_path != file_path
+            && !nfo_path
+                .file_name()
+                .is_some_and(|name| name == TVSHOW_NFO || name == MOVIE_NFO)
+            && nfo_path.is_file()
+    })
+}
+
+/// Finds the `.nfo` file describing a directory. This is either a
+/// `tvshow.nfo` or a `movie.nfo` inside the directory.
+pub fn find_for_directory(dir_path: &Path) -> Option<PathBuf> {
+    [TVSHOW_NFO, MOVIE_NFO]
+        .iter()
+        .map(|name| dir_path.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Loads and parses a `.nfo` file.
+pub fn load(nfo_path: &Path) -> Result<Nfo> {
+    let mut nfo_file = File::open(nfo_path)
+        .chain_err(|| MetadataLoadError(format!("Error opening {:?}", nfo_path).into()))?;
+    let mut nfo_string = String::new();
+    nfo_file
+        .read_to_string(&mut nfo_string)
+        .chain_err(|| MetadataLoadError(format!("Error reading {:?}", nfo_path).into()))?;
+
+    parse(&nfo_string)
+        .chain_err(|| MetadataLoadError(format!("Error parsing {:?}", nfo_path).into()))
+}
+
+/// Parses the contents of a `.nfo` file.
+///
+/// Kodi allows multi-episode files to have several `<episodedetails>`
+/// elements and allows a scraper URL to follow the XML, neither of which is
+/// well-formed XML, so everything is wrapped in a synthetic root element and
+/// the first recognized element is used.
+pub fn parse(nfo: &str) -> Result<Nfo> {
+    let wrapped = format!(
+        "<nfo>{}</nfo>",
+        XML_DECLARATION_PATTERN.replace_all(nfo, "")
+    );
+    let document = Document::parse(&wrapped).chain_err(|| "Malformed XML")?;
+
+    let (kind, root) = document
+        .root_element()
+        .children()
+        .filter(Node::is_element)
+        .find_map(|node| {
+            let kind = match node.tag_name().name() {
+                "movie" => NfoKind::Movie,
+                "tvshow" => NfoKind::TvShow,
+                "episodedetails" => NfoKind::Episode,
+                _ => return None,
+            };
+            Some((kind, node))
+        })
+        .ok_or("No movie, tvshow or episodedetails element")?;
+
+    let year = child_text(root, "year")
+        .and_then(|year| year.parse().ok())
+        .or_else(|| {
+            child_text(root, "premiered")
+                .or_else(|| child_text(root, "aired"))
+                .and_then(|date| date.get(..4))
+                .and_then(|year| year.parse().ok())
+        });
+
+    let mut artwork = vec![];
+    for node in root.children().filter(Node::is_element) {
+        match node.tag_name().name() {
+            "thumb" => artwork.extend(artwork_from(node, node.attribute("aspect"))),
+            "fanart" => artwork.extend(
+                node.children()
+                    .filter(|child| child.has_tag_name("thumb"))
+                    .filter_map(|child| artwork_from(child, Some("fanart"))),
+            ),
+            _ => {}
+        }
+    }
+
+    Ok(Nfo {
+        kind,
+        title: child_text(root, "title").map(str::to_string),
+        original_title: child_text(root, "originaltitle").map(str::to_string),
+        year,
+        plot: child_text(root, "plot").map(str::to_string),
+        genres: root
+            .children()
+            .filter(|child| child.has_tag_name("genre"))
+            .filter_map(node_text)
+            .map(str::to_string)
+            .collect(),
+        season: child_text(root, "season").and_then(|season| season.parse().ok()),
+        episode: child_text(root, "episode").and_then(|episode| episode.parse().ok()),
+        runtime: child_text(root, "runtime").and_then(leading_number),
+        artwork,
+    })
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .and_then(node_text)
+}
+
+fn node_text<'a>(node: Node<'a, '_>) -> Option<&'a str> {
+    node.text().map(str::trim).filter(|text| !text.is_empty())
+}
+
+/// Some tools write runtimes like `120 min`, so only the leading digits are
+/// parsed.
+fn leading_number(text: &str) -> Option<u32> {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    text[..end].parse().ok()
+}
+
+fn artwork_from(node: Node, aspect: Option<&str>) -> Option<NfoArtwork> {
+    node_text(node).map(|url| NfoArtwork {
+        aspect: aspect.map(str::to_string),
+        url: url.to_string(),
+    })
+}
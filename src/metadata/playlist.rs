@@ -0,0 +1,161 @@
+use crate::{
+    error::{ErrorKind::MetadataLoadError, Result, ResultExt},
+    media::subtitles::decode_text,
+    util::path::file_extension,
+};
+use std::{collections::BTreeMap, fs::File, io::Read, path::Path};
+
+/// Playlists bigger than this are not read.
+const MAX_PLAYLIST_SIZE: u64 = 4 * 1024 * 1024;
+
+/// A playlist file's entries, in order.
+#[derive(Debug, Clone, Default)]
+pub struct Playlist {
+    pub entries: Vec<PlaylistEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlaylistEntry {
+    /// The entry's location as written in the playlist, with backslashes
+    /// turned into forward slashes. This may be relative to the playlist,
+    /// absolute or a url.
+    pub location: String,
+    pub title: Option<String>,
+    /// Duration in seconds.
+    pub duration: Option<f64>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum PlaylistFormat {
+    M3u,
+    Pls,
+}
+
+/// Checks whether a path refers to a `.m3u`, `.m3u8` or `.pls` playlist.
+pub fn is_playlist(path: &str) -> bool {
+    format(path).is_some()
+}
+
+/// Loads and parses a playlist file. Plain `.m3u` files often are not UTF-8,
+/// so the encoding is guessed like for subtitles.
+pub fn load(playlist_path: &Path) -> Result<Playlist> {
+    let format = format(&playlist_path.to_string_lossy())
+        .ok_or_else(|| MetadataLoadError(format!("Not a playlist: {:?}", playlist_path).into()))?;
+    let playlist_file = File::open(playlist_path)
+        .chain_err(|| MetadataLoadError(format!("Error opening {:?}", playlist_path).into()))?;
+    let mut bytes = vec![];
+    playlist_file
+        .take(MAX_PLAYLIST_SIZE)
+        .read_to_end(&mut bytes)
+        .chain_err(|| MetadataLoadError(format!("Error reading {:?}", playlist_path).into()))?;
+
+    let contents = decode_text(&bytes);
+
+    Ok(match format {
+        PlaylistFormat::M3u => parse_m3u(&contents),
+        PlaylistFormat::Pls => parse_pls(&contents),
+    })
+}
+
+fn format(path: &str) -> Option<PlaylistFormat> {
+    match file_extension(path)?.to_lowercase().as_str() {
+        "m3u" | "m3u8" => Some(PlaylistFormat::M3u),
+        "pls" => Some(PlaylistFormat::Pls),
+        _ => None,
+    }
+}
+
+/// Parses a plain or extended M3U playlist. Directives other than `#EXTINF`
+/// are skipped.
+fn parse_m3u(contents: &str) -> Playlist {
+    let mut entries = vec![];
+    let mut info = None;
+
+    for line in contents.lines().map(str::trim) {
+        if let Some(extinf) = line.strip_prefix("#EXTINF:") {
+            info = Some(parse_extinf(extinf));
+        } else if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else {
+            let (duration, title) = info.take().unwrap_or((None, None));
+            entries.push(PlaylistEntry {
+                location: line.replace('\\', "/"),
+                title,
+                duration,
+            });
+        }
+    }
+
+    Playlist { entries }
+}
+
+/// Parses the part of an `#EXTINF` line after the colon: the duration,
+/// optionally followed by attributes, then a comma and the title. Commas
+/// inside quoted attribute values do not end the duration part.
+fn parse_extinf(extinf: &str) -> (Option<f64>, Option<String>) {
+    let mut quoted = false;
+    let comma = extinf.char_indices().find_map(|(i, c)| match c {
+        '"' => {
+            quoted = !quoted;
+            None
+        }
+        ',' if !quoted => Some(i),
+        _ => None,
+    });
+    let (duration, title) = match comma {
+        Some(comma) => (&extinf[..comma], Some(&extinf[comma + 1..])),
+        None => (extinf, None),
+    };
+
+    (
+        duration
+            .split_whitespace()
+            .next()
+            .and_then(|duration| duration.parse().ok())
+            .filter(|duration: &f64| *duration >= 0.0),
+        title
+            .map(|title| title.trim().to_string())
+            .filter(|title| !title.is_empty()),
+    )
+}
+
+/// Parses a PLS playlist. Entries are ordered by their number rather than by
+/// where they appear in the file.
+fn parse_pls(contents: &str) -> Playlist {
+    let mut entries: BTreeMap<u32, PlaylistEntry> = BTreeMap::new();
+
+    for line in contents.lines().map(str::trim) {
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+            None => continue,
+        };
+        let (field, number) = match key.find(|c: char| c.is_ascii_digit()) {
+            Some(i) => match key[i..].parse::<u32>() {
+                Ok(number) => (&key[..i], number),
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+        let entry = entries.entry(number).or_insert_with(|| PlaylistEntry {
+            location: String::new(),
+            title: None,
+            duration: None,
+        });
+
+        match field {
+            "file" => entry.location = value.replace('\\', "/"),
+            "title" if !value.is_empty() => entry.title = Some(value.to_string()),
+            "length" => {
+                entry.duration = value.parse().ok().filter(|duration: &f64| *duration >= 0.0)
+            }
+            _ => {}
+        }
+    }
+
+    Playlist {
+        entries: entries
+            .into_values()
+            .filter(|entry| !entry.location.is_empty())
+            .collect(),
+    }
+}
@@ -2,6 +2,15 @@ use crate::{
     error::{Result, ResultExt},
     logging,
 };
+use ffmpeg4_sys::{
+    av_codec_is_decoder, av_codec_is_encoder, av_codec_iterate, av_version_info, avcodec_version,
+    avfilter_version, avformat_version, avutil_version, swscale_version,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::CStr,
+    ptr,
+};
 
 /// Initializes ffmpeg and the custom logging callback.
 pub fn init_ffmpeg() -> Result<()> {
@@ -10,3 +19,60 @@ pub fn init_ffmpeg() -> Result<()> {
 
     Ok(())
 }
+
+/// The versions of the ffmpeg libraries in use, by library name.
+pub fn library_versions() -> BTreeMap<&'static str, String> {
+    let version = |version: u32| {
+        format!(
+            "{}.{}.{}",
+            version >> 16,
+            (version >> 8) & 0xff,
+            version & 0xff
+        )
+    };
+
+    let mut versions = BTreeMap::new();
+    versions.insert(
+        "ffmpeg",
+        unsafe { CStr::from_ptr(av_version_info()) }
+            .to_string_lossy()
+            .to_string(),
+    );
+    unsafe {
+        versions.insert("libavcodec", version(avcodec_version()));
+        versions.insert("libavfilter", version(avfilter_version()));
+        versions.insert("libavformat", version(avformat_version()));
+        versions.insert("libavutil", version(avutil_version()));
+        versions.insert("libswscale", version(swscale_version()));
+    }
+
+    versions
+}
+
+/// Lists the names of the available decoders and encoders.
+pub fn codecs() -> (Vec<String>, Vec<String>) {
+    let mut decoders = BTreeSet::new();
+    let mut encoders = BTreeSet::new();
+
+    let mut opaque = ptr::null_mut();
+    loop {
+        let codec = unsafe { av_codec_iterate(&mut opaque) };
+        if codec.is_null() {
+            break;
+        }
+
+        let name = unsafe { CStr::from_ptr((*codec).name) }
+            .to_string_lossy()
+            .to_string();
+        if unsafe { av_codec_is_encoder(codec) } != 0 {
+            encoders.insert(name);
+        } else if unsafe { av_codec_is_decoder(codec) } != 0 {
+            decoders.insert(name);
+        }
+    }
+
+    (
+        decoders.into_iter().collect(),
+        encoders.into_iter().collect(),
+    )
+}
@@ -1,7 +1,11 @@
 #[cfg(feature = "ffmpeg")]
 pub mod ffmpeg;
+pub mod hash;
 pub mod path;
+pub mod signing;
+pub mod vtt;
 pub mod web;
+pub mod zip;
 
 // Result wrapper functions
 
@@ -1,9 +1,20 @@
-use crate::error::{ErrorKind, Result};
+use crate::{
+    config::Config,
+    error::{ErrorKind, Result},
+};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
 use regex::Regex;
 use std::path::PathBuf;
 
 lazy_static! {
     static ref FILE_EXTENSION_PATTERN: Regex = Regex::new(r#".*\.(?P<ext>[^.]+)$"#).unwrap();
+    /// The characters percent-encoded when putting a path into a url.
+    pub static ref PATH_SET: AsciiSet = NON_ALPHANUMERIC
+        .remove(b'/')
+        .remove(b'-')
+        .remove(b'_')
+        .remove(b'.')
+        .remove(b'+');
 }
 
 pub fn file_extension(path: &str) -> Option<&str> {
@@ -13,6 +24,30 @@ pub fn file_extension(path: &str) -> Option<&str> {
         .map(|m| m.as_str())
 }
 
+/// Whether a file is an image that can be decoded and resized. Vector images
+/// can not be.
+pub fn is_image(path: &str) -> bool {
+    let mime = actix_files::file_extension_to_mime(file_extension(path).unwrap_or(""));
+    mime.type_() == "image" && mime.subtype() != "svg"
+}
+
+/// Resolves a request path relative to `base_dir` into the path of a file on
+/// disk. Illegal paths and paths that are not files are treated as not found.
+pub fn resolve_file(config: &Config, path: &str) -> Result<PathBuf> {
+    let relative_path = parse_path(path, false)?;
+
+    if !config.is_legal_path(&relative_path.to_string_lossy()) {
+        bail!(ErrorKind::NotFoundError)
+    }
+
+    let file_path = config.base_dir.join(&relative_path);
+    if file_path.is_file() {
+        Ok(file_path)
+    } else {
+        bail!(ErrorKind::NotFoundError)
+    }
+}
+
 /*
  * Copied from actix-files-0.5.0/src/error.rs to make sure responses stay the
  * same for limited files.
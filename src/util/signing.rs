@@ -0,0 +1,165 @@
+use crate::{
+    config::Config,
+    error::{ErrorKind::InvalidSignatureError, Result},
+    util::path::{parse_path, PATH_SET},
+};
+use actix_web::{web, HttpRequest};
+use hmac::{Hmac, Mac, NewMac};
+use path_slash::PathExt;
+use percent_encoding::utf8_percent_encode;
+use rand::RngCore;
+use sha2::Sha256;
+use std::{
+    net::IpAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const CDN_FILES_URL: &str = "/cdn/files";
+const SECRET_SIZE: usize = 32;
+
+/// The query parameters of a signed CDN url.
+#[derive(Debug, Deserialize)]
+struct SignatureQuery {
+    /// When the url stops working, in seconds since the Unix epoch.
+    expires: Option<u64>,
+    /// The only client IP address the url works for.
+    ip: Option<IpAddr>,
+    /// Hex HMAC-SHA256 of the path, expiry and IP address.
+    signature: Option<String>,
+}
+
+/// Generates a random secret to sign urls with.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; SECRET_SIZE];
+    rand::thread_rng().fill_bytes(&mut secret);
+
+    hex(&secret)
+}
+
+/// Links to a file on the CDN, signed if signing is enabled. The path is
+/// relative to `base_dir`, with forward slashes.
+pub fn files_url(config: &Config, path: &str, client: Option<IpAddr>) -> String {
+    sign_url(
+        config,
+        format!("{}/{}", CDN_FILES_URL, utf8_percent_encode(path, &PATH_SET)),
+        path,
+        client,
+    )
+}
+
+/// Signs a CDN url serving a file, or something made from it, if signing is
+/// enabled. The signature is for the file's path, relative to `base_dir` with
+/// forward slashes, so it is good for anything the CDN serves for that file.
+/// Signed urls are bound to the client if it is known and `bind-ip` is
+/// enabled.
+pub fn sign_url(config: &Config, url: String, path: &str, client: Option<IpAddr>) -> String {
+    if !config.signing.enabled {
+        return url;
+    }
+
+    let expires = unix_time() + config.signing.url_lifetime;
+    let ip = client.filter(|_| config.signing.bind_ip);
+    let signature = hex(&mac(config, path, expires, ip).finalize().into_bytes());
+    let separator = if url.contains('?') { '&' } else { '?' };
+
+    match ip {
+        Some(ip) => format!(
+            "{}{}expires={}&ip={}&signature={}",
+            url, separator, expires, ip, signature
+        ),
+        None => format!(
+            "{}{}expires={}&signature={}",
+            url, separator, expires, signature
+        ),
+    }
+}
+
+/// Checks the signature of a CDN request for a file, or something made from
+/// it, given the file's path as requested.
+pub fn verify_request(config: &Config, path: &str, req: &HttpRequest) -> Result<()> {
+    verify_query(
+        config,
+        path,
+        req.query_string(),
+        req.peer_addr().map(|addr| addr.ip()),
+    )
+}
+
+/// Like [`verify_request`], for the parts of a request.
+pub fn verify_query(
+    config: &Config,
+    path: &str,
+    query_string: &str,
+    client: Option<IpAddr>,
+) -> Result<()> {
+    let relative_path = parse_path(path, false)?;
+    let query = web::Query::<SignatureQuery>::from_query(query_string)
+        .map_err(|_| InvalidSignatureError("Malformed query".into()))?;
+
+    verify(config, &relative_path.to_slash_lossy(), &query, client)
+}
+
+/// Checks the signature of a request for a file on the CDN. Unsigned requests
+/// only pass if signatures are not required, signed ones only if they are
+/// valid, unexpired and come from the client they are bound to.
+fn verify(
+    config: &Config,
+    path: &str,
+    query: &SignatureQuery,
+    client: Option<IpAddr>,
+) -> Result<()> {
+    if !config.signing.enabled {
+        return Ok(());
+    }
+
+    let signature = match &query.signature {
+        Some(signature) => signature,
+        None if config.signing.required => bail!(InvalidSignatureError("Missing signature".into())),
+        None => return Ok(()),
+    };
+    let expires = match query.expires {
+        Some(expires) if expires > unix_time() => expires,
+        Some(_) => bail!(InvalidSignatureError("Expired".into())),
+        None => bail!(InvalidSignatureError("Missing expiry".into())),
+    };
+    if query.ip.is_some() && query.ip != client {
+        bail!(InvalidSignatureError("Wrong client".into()))
+    }
+
+    let signature =
+        unhex(signature).ok_or_else(|| InvalidSignatureError("Malformed signature".into()))?;
+    mac(config, path, expires, query.ip)
+        .verify(&signature)
+        .map_err(|_| InvalidSignatureError("Wrong signature".into()).into())
+}
+
+fn mac(config: &Config, path: &str, expires: u64, ip: Option<IpAddr>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(config.signing.secret.as_bytes())
+        .expect("HMAC takes keys of any size");
+    let ip = ip.map(|ip| ip.to_string()).unwrap_or_default();
+    mac.update(format!("{}\n{}\n{}", path, expires, ip).as_bytes());
+
+    mac
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
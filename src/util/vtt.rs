@@ -0,0 +1,48 @@
+/// Formats seconds as a WebVTT timestamp (`hh:mm:ss.ttt`).
+pub fn vtt_timestamp(seconds: f64) -> String {
+    let millis = (seconds.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// A single timed piece of text in a WebVTT file.
+#[derive(Debug, Clone)]
+pub struct Cue {
+    /// Start time in seconds.
+    pub start: f64,
+    /// End time in seconds.
+    pub end: f64,
+    pub text: String,
+}
+
+/// Renders cues as a WebVTT file. Empty cues are left out, and blank lines in
+/// a cue's text are dropped, since they would end the cue early.
+pub fn write_vtt(cues: &[Cue]) -> String {
+    let mut vtt = "WEBVTT\n".to_string();
+
+    for cue in cues {
+        let text: Vec<&str> = cue
+            .text
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty())
+            .collect();
+        if text.is_empty() {
+            continue;
+        }
+
+        vtt.push_str(&format!(
+            "\n{} --> {}\n{}\n",
+            vtt_timestamp(cue.start),
+            vtt_timestamp(cue.end.max(cue.start)),
+            text.join("\n")
+        ));
+    }
+
+    vtt
+}
@@ -1,5 +1,21 @@
-use crate::util::{w_err, w_ok};
-use actix_web::{dev::HttpResponseBuilder, http::StatusCode, HttpResponse};
+use crate::{
+    config::Config,
+    error::{ErrorKind::ForbiddenError, Result},
+    util::{w_err, w_ok},
+};
+use actix_web::{
+    dev::HttpResponseBuilder,
+    error::BlockingError,
+    http::{
+        header::{
+            self, Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue,
+        },
+        StatusCode,
+    },
+    web, HttpRequest, HttpResponse,
+};
+
+const BEARER_PREFIX: &str = "Bearer ";
 
 /// Constructs a JSON Err response with the specified status code.
 pub fn json_err<E: serde::Serialize>(status: StatusCode, json: E) -> HttpResponse {
@@ -18,3 +34,73 @@ pub fn json_ok<T: serde::Serialize>(json: T) -> HttpResponse {
 pub fn json_ok_status<T: serde::Serialize>(status: StatusCode, json: T) -> HttpResponse {
     HttpResponseBuilder::new(status).json(w_ok(json))
 }
+
+/// Constructs a `Content-Disposition` header that has browsers save a response
+/// as a file. Old clients get the name with anything but printable ASCII
+/// replaced, others get it in full as an RFC 5987 `filename*`.
+pub fn attachment(file_name: &str) -> ContentDisposition {
+    let ascii_name = file_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![
+            DispositionParam::Filename(ascii_name),
+            DispositionParam::FilenameExt(ExtendedValue {
+                charset: Charset::Ext("UTF-8".to_string()),
+                language_tag: None,
+                value: file_name.as_bytes().to_vec(),
+            }),
+        ],
+    }
+}
+
+/// Runs blocking work, like file IO or anything ffmpeg, on the actix thread
+/// pool.
+pub async fn blocking<F, T>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    web::block(f).await.map_err(|e| match e {
+        BlockingError::Error(e) => e,
+        BlockingError::Canceled => "Blocking task canceled".into(),
+    })
+}
+
+/// Lets only admins through to server-wide operations, like managing jobs:
+/// requests carrying the configured admin token, or from localhost if it is
+/// trusted.
+pub fn authorize_admin(config: &Config, req: &HttpRequest) -> Result<()> {
+    if config.jobs.trust_localhost && req.peer_addr().is_some_and(|addr| addr.ip().is_loopback()) {
+        return Ok(());
+    }
+
+    let token = &config.jobs.admin_token;
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix(BEARER_PREFIX));
+    match given {
+        Some(given) if !token.is_empty() && constant_time_eq(given, token) => Ok(()),
+        _ => bail!(ForbiddenError),
+    }
+}
+
+/// Compares without giving away through timing how much of a guess was right.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
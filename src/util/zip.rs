@@ -0,0 +1,374 @@
+use crate::error::{Result, ResultExt};
+use chrono::{DateTime, Datelike, Local, Timelike};
+use crc32fast::Hasher;
+use std::{fs::File, io::Read, path::PathBuf, time::SystemTime};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP64_END_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const END_SIGNATURE: u32 = 0x0605_4b50;
+
+const LOCAL_HEADER_SIZE: u64 = 30;
+const CENTRAL_HEADER_SIZE: u64 = 46;
+const ZIP64_END_SIZE: u64 = 56;
+const ZIP64_LOCATOR_SIZE: u64 = 20;
+const END_SIZE: u64 = 22;
+
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+/// Sizes in the ZIP64 extra field of a local header are left zero, as the
+/// data descriptor has them.
+const LOCAL_ZIP64_EXTRA_SIZE: u64 = 4 + 8 + 8;
+
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Unix, so extractors pick up the file mode.
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION_ZIP64;
+/// Sizes and CRC come in a data descriptor, names are UTF-8.
+const FLAGS: u16 = 0x0008 | 0x0800;
+const METHOD_STORED: u16 = 0;
+const FILE_MODE: u32 = 0o100644;
+
+const MAX_U16: u64 = 0xffff;
+const MAX_U32: u64 = 0xffff_ffff;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A file to put into an archive.
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    /// The name inside the archive, with forward slashes.
+    pub name: String,
+    pub path: PathBuf,
+    /// The file's size when the entry was made. Files that change size while
+    /// being archived are cut off or padded with zeros to keep to it.
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Where an entry ends up in the archive and which of its fields need ZIP64.
+#[derive(Debug, Copy, Clone)]
+struct EntryLayout {
+    offset: u64,
+    zip64_size: bool,
+    zip64_offset: bool,
+}
+
+impl EntryLayout {
+    fn version_needed(&self) -> u16 {
+        if self.zip64_size || self.zip64_offset {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFAULT
+        }
+    }
+
+    fn local_extra_size(&self) -> u64 {
+        if self.zip64_size {
+            LOCAL_ZIP64_EXTRA_SIZE
+        } else {
+            0
+        }
+    }
+
+    fn data_descriptor_size(&self) -> u64 {
+        if self.zip64_size {
+            4 + 4 + 8 + 8
+        } else {
+            4 + 4 + 4 + 4
+        }
+    }
+
+    fn central_extra_size(&self) -> u64 {
+        let fields = if self.zip64_size { 16 } else { 0 } + if self.zip64_offset { 8 } else { 0 };
+        if fields > 0 {
+            4 + fields
+        } else {
+            0
+        }
+    }
+}
+
+/// The layout of a whole archive.
+#[derive(Debug)]
+struct Layout {
+    entries: Vec<EntryLayout>,
+    central_offset: u64,
+    central_size: u64,
+    zip64_end: bool,
+}
+
+impl Layout {
+    fn new(entries: &[ZipEntry]) -> Layout {
+        let mut offset = 0;
+        let mut central_size = 0;
+        let layouts = entries
+            .iter()
+            .map(|entry| {
+                let layout = EntryLayout {
+                    offset,
+                    zip64_size: entry.size >= MAX_U32,
+                    zip64_offset: offset >= MAX_U32,
+                };
+                let name_size = entry.name.len() as u64;
+                offset += LOCAL_HEADER_SIZE
+                    + name_size
+                    + layout.local_extra_size()
+                    + entry.size
+                    + layout.data_descriptor_size();
+                central_size += CENTRAL_HEADER_SIZE + name_size + layout.central_extra_size();
+
+                layout
+            })
+            .collect::<Vec<_>>();
+
+        Layout {
+            zip64_end: layouts
+                .iter()
+                .any(|layout| layout.zip64_size || layout.zip64_offset)
+                || entries.len() as u64 >= MAX_U16
+                || offset >= MAX_U32
+                || central_size >= MAX_U32,
+            entries: layouts,
+            central_offset: offset,
+            central_size,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        let zip64_end_size = if self.zip64_end {
+            ZIP64_END_SIZE + ZIP64_LOCATOR_SIZE
+        } else {
+            0
+        };
+
+        self.central_offset + self.central_size + zip64_end_size + END_SIZE
+    }
+}
+
+/// Calculates the exact size of the archive `write_archive` writes.
+pub fn archive_size(entries: &[ZipEntry]) -> u64 {
+    Layout::new(entries).size()
+}
+
+/// Writes an uncompressed archive of the entries to the sink. Every entry's
+/// data is followed by a data descriptor holding its CRC-32, so nothing has
+/// to be read twice or staged. ZIP64 fields are only used where sizes or
+/// offsets need them. A sink returning `false` stops writing early, like when
+/// the client went away.
+pub fn write_archive(entries: &[ZipEntry], mut sink: impl FnMut(&[u8]) -> bool) -> Result<()> {
+    let layout = Layout::new(entries);
+    let mut crcs = Vec::with_capacity(entries.len());
+
+    for (entry, entry_layout) in entries.iter().zip(&layout.entries) {
+        if !sink(&local_header(entry, entry_layout)) {
+            return Ok(());
+        }
+        let crc = match write_data(entry, &mut sink)? {
+            Some(crc) => crc,
+            None => return Ok(()),
+        };
+        if !sink(&data_descriptor(entry, entry_layout, crc)) {
+            return Ok(());
+        }
+        crcs.push(crc);
+    }
+
+    let mut central = Vec::with_capacity(layout.central_size as usize);
+    for ((entry, entry_layout), crc) in entries.iter().zip(&layout.entries).zip(crcs) {
+        central.extend(central_header(entry, entry_layout, crc));
+    }
+    central.extend(end_records(entries.len() as u64, &layout));
+    sink(&central);
+
+    Ok(())
+}
+
+/// Sends a file's data, giving its CRC-32, or `None` if the sink stopped.
+fn write_data(entry: &ZipEntry, sink: &mut impl FnMut(&[u8]) -> bool) -> Result<Option<u32>> {
+    let file = File::open(&entry.path).chain_err(|| format!("Error opening {:?}", entry.path))?;
+    let mut reader = file.take(entry.size);
+    let mut hasher = Hasher::new();
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    let mut written = 0;
+
+    loop {
+        let read = reader
+            .read(&mut buffer)
+            .chain_err(|| format!("Error reading {:?}", entry.path))?;
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..read]);
+        if !sink(&buffer[..read]) {
+            return Ok(None);
+        }
+        written += read as u64;
+    }
+
+    if written < entry.size {
+        warn!(
+            "{:?} shrank while being archived, padding it with zeros",
+            entry.path
+        );
+        buffer.iter_mut().for_each(|byte| *byte = 0);
+        while written < entry.size {
+            let padding = (entry.size - written).min(READ_BUFFER_SIZE as u64) as usize;
+            hasher.update(&buffer[..padding]);
+            if !sink(&buffer[..padding]) {
+                return Ok(None);
+            }
+            written += padding as u64;
+        }
+    }
+
+    Ok(Some(hasher.finalize()))
+}
+
+fn local_header(entry: &ZipEntry, layout: &EntryLayout) -> Vec<u8> {
+    let (time, date) = dos_date_time(entry.modified);
+    let size_field = if layout.zip64_size { MAX_U32 as u32 } else { 0 };
+    let mut header = Vec::with_capacity(
+        (LOCAL_HEADER_SIZE + entry.name.len() as u64 + layout.local_extra_size()) as usize,
+    );
+
+    put_u32(&mut header, LOCAL_HEADER_SIGNATURE);
+    put_u16(&mut header, layout.version_needed());
+    put_u16(&mut header, FLAGS);
+    put_u16(&mut header, METHOD_STORED);
+    put_u16(&mut header, time);
+    put_u16(&mut header, date);
+    put_u32(&mut header, 0);
+    put_u32(&mut header, size_field);
+    put_u32(&mut header, size_field);
+    put_u16(&mut header, entry.name.len() as u16);
+    put_u16(&mut header, layout.local_extra_size() as u16);
+    header.extend(entry.name.as_bytes());
+    if layout.zip64_size {
+        put_u16(&mut header, ZIP64_EXTRA_ID);
+        put_u16(&mut header, 16);
+        put_u64(&mut header, 0);
+        put_u64(&mut header, 0);
+    }
+
+    header
+}
+
+fn data_descriptor(entry: &ZipEntry, layout: &EntryLayout, crc: u32) -> Vec<u8> {
+    let mut descriptor = Vec::with_capacity(layout.data_descriptor_size() as usize);
+
+    put_u32(&mut descriptor, DATA_DESCRIPTOR_SIGNATURE);
+    put_u32(&mut descriptor, crc);
+    if layout.zip64_size {
+        put_u64(&mut descriptor, entry.size);
+        put_u64(&mut descriptor, entry.size);
+    } else {
+        put_u32(&mut descriptor, entry.size as u32);
+        put_u32(&mut descriptor, entry.size as u32);
+    }
+
+    descriptor
+}
+
+fn central_header(entry: &ZipEntry, layout: &EntryLayout, crc: u32) -> Vec<u8> {
+    let (time, date) = dos_date_time(entry.modified);
+    let size_field = entry.size.min(MAX_U32) as u32;
+    let mut header = Vec::with_capacity(
+        (CENTRAL_HEADER_SIZE + entry.name.len() as u64 + layout.central_extra_size()) as usize,
+    );
+
+    put_u32(&mut header, CENTRAL_HEADER_SIGNATURE);
+    put_u16(&mut header, VERSION_MADE_BY);
+    put_u16(&mut header, layout.version_needed());
+    put_u16(&mut header, FLAGS);
+    put_u16(&mut header, METHOD_STORED);
+    put_u16(&mut header, time);
+    put_u16(&mut header, date);
+    put_u32(&mut header, crc);
+    put_u32(&mut header, size_field);
+    put_u32(&mut header, size_field);
+    put_u16(&mut header, entry.name.len() as u16);
+    put_u16(&mut header, layout.central_extra_size() as u16);
+    // comment length, disk number and internal attributes
+    put_u16(&mut header, 0);
+    put_u16(&mut header, 0);
+    put_u16(&mut header, 0);
+    put_u32(&mut header, FILE_MODE << 16);
+    put_u32(&mut header, layout.offset.min(MAX_U32) as u32);
+    header.extend(entry.name.as_bytes());
+    if layout.central_extra_size() > 0 {
+        put_u16(&mut header, ZIP64_EXTRA_ID);
+        put_u16(&mut header, (layout.central_extra_size() - 4) as u16);
+        if layout.zip64_size {
+            put_u64(&mut header, entry.size);
+            put_u64(&mut header, entry.size);
+        }
+        if layout.zip64_offset {
+            put_u64(&mut header, layout.offset);
+        }
+    }
+
+    header
+}
+
+fn end_records(count: u64, layout: &Layout) -> Vec<u8> {
+    let mut records = vec![];
+
+    if layout.zip64_end {
+        let zip64_end_offset = layout.central_offset + layout.central_size;
+
+        put_u32(&mut records, ZIP64_END_SIGNATURE);
+        put_u64(&mut records, ZIP64_END_SIZE - 12);
+        put_u16(&mut records, VERSION_MADE_BY);
+        put_u16(&mut records, VERSION_ZIP64);
+        put_u32(&mut records, 0);
+        put_u32(&mut records, 0);
+        put_u64(&mut records, count);
+        put_u64(&mut records, count);
+        put_u64(&mut records, layout.central_size);
+        put_u64(&mut records, layout.central_offset);
+
+        put_u32(&mut records, ZIP64_LOCATOR_SIGNATURE);
+        put_u32(&mut records, 0);
+        put_u64(&mut records, zip64_end_offset);
+        put_u32(&mut records, 1);
+    }
+
+    put_u32(&mut records, END_SIGNATURE);
+    put_u16(&mut records, 0);
+    put_u16(&mut records, 0);
+    put_u16(&mut records, count.min(MAX_U16) as u16);
+    put_u16(&mut records, count.min(MAX_U16) as u16);
+    put_u32(&mut records, layout.central_size.min(MAX_U32) as u32);
+    put_u32(&mut records, layout.central_offset.min(MAX_U32) as u32);
+    put_u16(&mut records, 0);
+
+    records
+}
+
+/// Converts a time into the local MS-DOS time and date ZIP headers use, which
+/// can not go before 1980.
+fn dos_date_time(time: SystemTime) -> (u16, u16) {
+    let time = DateTime::<Local>::from(time);
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+
+    (
+        ((time.hour() << 11) | (time.minute() << 5) | (time.second() / 2)) as u16,
+        ((((time.year() - 1980) as u32).min(127) << 9) | (time.month() << 5) | time.day()) as u16,
+    )
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend(&value.to_le_bytes());
+}
+
+fn put_u64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend(&value.to_le_bytes());
+}
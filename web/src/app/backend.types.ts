@@ -10,10 +10,38 @@ export interface Status {
   version: string;
   welcome_title: string;
   welcome_content: string;
+  build: BuildInfo;
+  capabilities: Capabilities;
 }
 
-/// Represents general file node information.
+/// When and from which commit the server was built.
+export interface BuildInfo {
+  git_commit: string | null;
+  build_time: string | null;
+}
+
+/// What this build and configuration of the server can do.
+export interface Capabilities {
+  features: string[];
+  ffmpeg: FfmpegCapabilities | null;
+  streaming: Array<'direct' | 'hls' | 'remux'>;
+  auth: string[];
+  loudness_analysis: boolean;
+  loudness_normalization: boolean;
+}
+
+export interface FfmpegCapabilities {
+  versions: { [library: string]: string };
+  decoders: string[];
+  encoders: string[];
+}
+
+/// Represents general file node information. The title, description and cover come from `folder.toml` or `.nfo`
+/// files.
 export interface EntryInfo {
+  title: string | null;
+  description: string | null;
+  cover: string | null;
   detail: EntryDetail;
   name: string;
   path: string;
@@ -24,12 +52,14 @@ export interface EntryInfo {
 export interface EntryDetail {
   Directory: EntryDetailDirectory | null;
   Error: EntryDetailError | null;
+  Playlist: EntryDetailPlaylist | null;
   File: EntryDetailFile | null;
 }
 
 /// Represents a file node that is a directory. This contains directory specific information.
 export interface EntryDetailDirectory {
   children: Array<DirectoryChild>;
+  nfo: Nfo | null;
 }
 
 /// Represents a file node that the backend was unable to load, either because it does not exist or because the backend
@@ -38,16 +68,103 @@ export interface EntryDetailError {
   error: 'NotFound' | 'Forbidden'
 }
 
-/// Represents a file node that is a file. This gives the cdn url where the file can be obtained.
+/// Represents a file node that is a `.m3u`, `.m3u8` or `.pls` playlist. The children are the media it refers to, in
+/// order, titled from the playlist where it has titles. The url is the playlist file itself.
+export interface EntryDetailPlaylist {
+  url: string;
+  children: Array<DirectoryChild>;
+}
+
+/// Represents a file node that is a file. This gives the cdn url where the file can be obtained, and for videos, the
+/// url of an HLS master playlist that streams it transcoded.
 export interface EntryDetailFile {
   mime_type: string;
   url: string;
+  /// The url to play the file from, which is its optimized version if it has one.
+  playback_url: string;
+  /// The url to download the file from, rather than view it in the browser.
+  download_url: string;
+  hls_url: string | null;
+  subtitles: SubtitleTrack[];
+  /// A WebVTT chapters track, if the file has chapters.
+  chapters_url: string | null;
+  loudness: Loudness | null;
+  /// Resized versions of an image file.
+  image_variants: ImageVariants | null;
+  exif: ExifData | null;
+  nfo: Nfo | null;
+}
+
+/// Urls of an image resized for thumbnails and for viewing on its own.
+export interface ImageVariants {
+  thumbnail: string;
+  screen: string;
+}
+
+/// A photo's EXIF data. The width and height are the displayed size, with the orientation applied.
+export interface ExifData {
+  camera_make: string | null;
+  camera_model: string | null;
+  lens: string | null;
+  taken_at: string | null;
+  width: number | null;
+  height: number | null;
+  orientation: number | null;
+  gps: GpsPosition | null;
+}
+
+export interface GpsPosition {
+  latitude: number;
+  longitude: number;
+  altitude: number | null;
+}
+
+/// EBU R128 measurements of a file's audio, with the gain in dB that brings it to the server's target loudness.
+export interface Loudness {
+  integrated: number;
+  range: number;
+  true_peak: number;
+  /// Silent files have no suggested gain.
+  suggested_gain: number | null;
+}
+
+/// A subtitle track of a video, either a sidecar file or a stream inside the video. The url serves it as WebVTT.
+export interface SubtitleTrack {
+  id: string;
+  language: string | null;
+  title: string | null;
+  format: string;
+  default: boolean;
+  forced: boolean;
+  url: string;
 }
 
-/// Represents a child element inside a directory.
+/// Represents a child element inside a directory. The title comes from the child's `.nfo` file, if it has one.
 export interface DirectoryChild {
   name: string;
-  type: 'Directory' | 'File';
+  title: string | null;
+  thumbnail: string | null;
+  type: 'Directory' | 'File' | 'Playlist';
   url: string;
   path: string;
 }
+
+/// Represents metadata read from a Kodi-style `.nfo` file.
+export interface Nfo {
+  kind: 'Movie' | 'TvShow' | 'Episode';
+  title: string | null;
+  original_title: string | null;
+  year: number | null;
+  plot: string | null;
+  genres: Array<string>;
+  season: number | null;
+  episode: number | null;
+  runtime: number | null;
+  artwork: Array<NfoArtwork>;
+}
+
+/// Represents an artwork reference from a `.nfo` file. Local artwork is given as a cdn url.
+export interface NfoArtwork {
+  aspect: string | null;
+  url: string;
+}
@@ -1,6 +1,7 @@
 import { Component, Input } from '@angular/core';
-import { DirectoryChild, EntryDetailDirectory } from "../backend.types";
+import { DirectoryChild, EntryDetailDirectory, EntryDetailPlaylist } from "../backend.types";
 import { Router } from "@angular/router";
+import { BackendService } from "../backend.service";
 
 @Component({
   selector: 'app-browse-directory',
@@ -12,17 +13,21 @@ export class BrowseDirectoryComponent {
 
   constructor(private router: Router) {}
 
-  private _directory!: EntryDetailDirectory;
+  private _directory!: EntryDetailDirectory | EntryDetailPlaylist;
 
   @Input()
-  get directory(): EntryDetailDirectory {
+  get directory(): EntryDetailDirectory | EntryDetailPlaylist {
     return this._directory;
   }
 
-  set directory(directory1: EntryDetailDirectory) {
+  set directory(directory1: EntryDetailDirectory | EntryDetailPlaylist) {
     this._directory = directory1;
+    // children are already sorted by the server
     this.children = directory1.children;
-    this.children.sort((a, b) => a.name.localeCompare(b.name));
+  }
+
+  url(url: string): string {
+    return BackendService.url(url);
   }
 
   navigateTo(path: string) {
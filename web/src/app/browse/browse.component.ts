@@ -15,10 +15,12 @@ export class BrowseComponent implements OnInit {
 
   name: string = 'Loading...'
   path: string = 'Loading...'
-  state: 'none' | 'directory' | 'error' | 'file' | 'media-file' = 'none';
+  state: 'none' | 'directory' | 'playlist' | 'error' | 'file' | 'media-file' = 'none';
   hasParent: boolean = false;
   parentUrl: string = '';
   detail: EntryDetail | null = null;
+  description: string | null = null;
+  cover: string | null = null;
 
   // error attributes
   error: string | null = null;
@@ -99,6 +101,13 @@ export class BrowseComponent implements OnInit {
       this.parentUrl = this.getParentPath();
     }
 
+    if (value.title) {
+      this.name = value.title;
+      this.title.setTitle(value.title);
+    }
+    this.description = value.description;
+    this.cover = value.cover;
+
     this.path = value.path_pretty;
 
     this.detail = value.detail;
@@ -107,6 +116,10 @@ export class BrowseComponent implements OnInit {
       this.state = 'directory';
     }
 
+    if (value.detail.Playlist != null) {
+      this.state = 'playlist';
+    }
+
     const file = value.detail.File;
     if (file != null) {
       if (file.mime_type.startsWith('video/')) {